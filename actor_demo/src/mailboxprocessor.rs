@@ -3,6 +3,7 @@ use std::pin::Pin;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::task::JoinHandle;
+use tracing::debug;
 
 enum Message {
     Ok,
@@ -24,6 +25,7 @@ impl MailboxProcessor {
         let (mailbox, mut receiver) = mpsc::channel::<Message>(100);
         let processor = tokio::task::spawn(async move {
             while let Some(message) = receiver.recv().await {
+                debug!("Mailbox received a message");
                 // let x = handler(message); //.downcast_ref();
                 // let x = (*handler) (message); // .await;
                 // let x = *handler;
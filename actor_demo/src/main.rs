@@ -1,6 +1,17 @@
 mod actor;
 mod mailboxprocessor;
 
+use clap::Parser;
+use logging::LogArgs;
+use tracing::info;
+
+#[derive(Parser)]
+struct Cli {
+    #[command(flatten)]
+    log: LogArgs,
+}
+
 fn main() {
-    println!("Hello, world!");
+    Cli::parse().log.init();
+    info!("Hello, world!");
 }
@@ -1,5 +1,9 @@
+use std::io::{self, IsTerminal};
 use std::path::PathBuf;
-use clap::{Parser};
+use clap::{Parser, ValueEnum};
+use tracing::{debug, trace};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
 
 #[derive(Debug)]
 #[derive(Parser)]
@@ -12,13 +16,52 @@ struct Cli {
     #[arg(short, long, value_name = "FILE")]
     output_file: PathBuf,
 
-    /// Turn debugging information on
+    /// Turn debugging information on, repeat for more (-d info, -dd debug, -ddd trace)
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
+
+    /// How log messages are written
+    #[arg(long, value_enum, default_value_t = LogFormat::Human)]
+    log_format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Human,
+    Json,
+}
+
+/// the level for the number of `-d` flags, None leaves it to `RUST_LOG`
+fn verbosity(debug: u8) -> Option<LevelFilter> {
+    match debug {
+        0 => None,
+        1 => Some(LevelFilter::INFO),
+        2 => Some(LevelFilter::DEBUG),
+        _ => Some(LevelFilter::TRACE),
+    }
+}
+
+fn init_logging(cli: &Cli) {
+    let filter = match verbosity(cli.debug) {
+        Some(level) => EnvFilter::default().add_directive(level.into()),
+        None => EnvFilter::builder().with_default_directive(LevelFilter::WARN.into()).from_env_lossy(),
+    };
+    // log messages go to stderr, stdout is left to the output
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal());
+    match cli.log_format {
+        LogFormat::Human => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
+    init_logging(&cli);
+    debug!(debug = cli.debug, "Logging set up");
+    trace!("{:?}", cli);
 
     println!("Hello, {} -> {}", cli.input_file.to_str().unwrap(), cli.output_file.to_str().unwrap());
 }
@@ -0,0 +1,87 @@
+//! Compares sending one request per round trip with pipelined batches.
+//!
+//! Concurrent clients send a mix of `set` and `get` requests over the text protocol in
+//! batches of a given depth, writing a whole batch before reading its replies. A depth of 1
+//! is a client without pipelining. Run with `cargo bench --bench pipelining`, optionally
+//! followed by `-- --shards N --clients N --requests N` (requests per client).
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+use common::{Client, Server, TEXT_PORT};
+
+const DEPTHS: &[usize] = &[1, 4, 16, 64, 128];
+
+struct Options {
+    shards: usize,
+    clients: usize,
+    requests: usize,
+}
+
+fn options() -> Options {
+    let args: Vec<String> = std::env::args().collect();
+    let value = |name: &str, default: usize| {
+        args.iter().position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(default)
+    };
+    Options {
+        shards: value("--shards", 4),
+        clients: value("--clients", 8),
+        requests: value("--requests", 20000),
+    }
+}
+
+fn main() {
+    let options = options();
+    let shards = options.shards.to_string();
+    let _server = Server::start_with(&["--shards", &shards]);
+    println!("{} clients with {} requests each on {} shards, every third request is a set",
+        options.clients, options.requests, options.shards);
+    println!("{:>8} {:>12} {:>10} {:>14}", "depth", "requests/s", "speedup", "batch latency");
+    let mut baseline = None;
+    for &depth in DEPTHS {
+        let (elapsed, latency) = run(&options, depth);
+        let throughput = (options.clients * options.requests) as f64 / elapsed.as_secs_f64();
+        let baseline = *baseline.get_or_insert(throughput);
+        println!("{:>8} {:>12.0} {:>9.1}x {:>14?}", depth, throughput, throughput / baseline, latency);
+    }
+}
+
+/// run all clients to completion, returns the wall time and the mean time per batch
+fn run(options: &Options, depth: usize) -> (Duration, Duration) {
+    let started = Instant::now();
+    let workers: Vec<_> = (0..options.clients).map(|client| {
+        let batches = options.requests / depth;
+        thread::spawn(move || {
+            let mut connection = Client::connect(TEXT_PORT);
+            let mut waited = Duration::ZERO;
+            for batch in 0..batches {
+                let mut lines = String::new();
+                for i in batch * depth..(batch + 1) * depth {
+                    let key = format!("bench:{}:{}", client, i % 100);
+                    if i % 3 == 0 {
+                        lines.push_str(&format!("set {} {}\n", key, i));
+                    } else {
+                        lines.push_str(&format!("get {}\n", key));
+                    }
+                }
+                let sent = Instant::now();
+                connection.send(lines.as_bytes());
+                for _ in 0..depth {
+                    let reply = connection.read_line();
+                    assert!(!reply.starts_with("-ERR"), "unexpected reply: {}", reply);
+                }
+                waited += sent.elapsed();
+            }
+            waited / batches.max(1) as u32
+        })
+    }).collect();
+
+    let latencies: Vec<Duration> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+    let mean = latencies.iter().sum::<Duration>() / latencies.len().max(1) as u32;
+    (started.elapsed(), mean)
+}
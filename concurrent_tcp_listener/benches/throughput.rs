@@ -0,0 +1,83 @@
+//! Compares a single storage task with a sharded one.
+//!
+//! Starts the server once per configuration and lets concurrent clients send a mix
+//! of `set` and `get` requests over the text protocol, then reports throughput and
+//! request latencies. Run with `cargo bench --bench throughput`, optionally followed
+//! by `-- --shards N --clients N --requests N` (requests per client).
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+use common::{Client, Server, TEXT_PORT};
+
+struct Options {
+    shards: usize,
+    clients: usize,
+    requests: usize,
+}
+
+fn options() -> Options {
+    let args: Vec<String> = std::env::args().collect();
+    let value = |name: &str, default: usize| {
+        args.iter().position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(default)
+    };
+    Options {
+        shards: value("--shards", 4),
+        clients: value("--clients", 16),
+        requests: value("--requests", 2000),
+    }
+}
+
+fn main() {
+    let options = options();
+    println!("{} clients with {} requests each, every third request is a set", options.clients, options.requests);
+    println!("{:>8} {:>12} {:>10} {:>10} {:>10} {:>10}", "shards", "requests/s", "p50", "p90", "p99", "max");
+    for shards in [1, options.shards] {
+        let shards_arg = shards.to_string();
+        let _server = Server::start_with(&["--shards", &shards_arg]);
+        let (elapsed, mut latencies) = run(&options);
+
+        latencies.sort();
+        let throughput = latencies.len() as f64 / elapsed.as_secs_f64();
+        println!("{:>8} {:>12.0} {:>10?} {:>10?} {:>10?} {:>10?}", shards, throughput,
+            percentile(&latencies, 50.0), percentile(&latencies, 90.0), percentile(&latencies, 99.0),
+            latencies.last().copied().unwrap_or_default());
+    }
+}
+
+/// run all clients to completion, returns the wall time and every request's latency
+fn run(options: &Options) -> (Duration, Vec<Duration>) {
+    let started = Instant::now();
+    let workers: Vec<_> = (0..options.clients).map(|client| {
+        let requests = options.requests;
+        thread::spawn(move || {
+            let mut connection = Client::connect(TEXT_PORT);
+            let mut latencies = Vec::with_capacity(requests);
+            for i in 0..requests {
+                let key = format!("bench:{}:{}", client, i % 100);
+                let line = if i % 3 == 0 { format!("set {} {}", key, i) } else { format!("get {}", key) };
+                let sent = Instant::now();
+                let reply = connection.request(&line);
+                latencies.push(sent.elapsed());
+                assert!(!reply.starts_with("-ERR"), "unexpected reply: {}", reply);
+            }
+            latencies
+        })
+    }).collect();
+
+    let latencies = workers.into_iter().flat_map(|w| w.join().unwrap()).collect();
+    (started.elapsed(), latencies)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let index = ((sorted.len() - 1) as f64 * p / 100.0).round() as usize;
+    sorted[index]
+}
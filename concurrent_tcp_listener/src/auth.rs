@@ -0,0 +1,222 @@
+//! Users and their permissions, loaded from a file given with `--users`.
+//!
+//! ```text
+//! # comments and empty lines are ignored
+//! user <name> <password hash>                 as printed by `--hash-password`
+//! allow <name> <read|write|readwrite> <key>   a key, a prefix ending in `*`, or `*` for all keys
+//! ```
+//!
+//! Without a users file every connection may do everything. With one, a connection
+//! has to `auth <name> <password>` first and may then only touch keys its rules allow.
+//! Channels are not covered by the rules, every user may publish and subscribe.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use argon2::password_hash::rand_core::OsRng;
+use tracing::info;
+use crate::error::ServiceError;
+use crate::shard::{Pending, Service};
+use crate::{ErrorKind, Request, Response};
+
+/// checked instead of a stored hash when the user does not exist, with the default parameters of `hash_password`
+const UNKNOWN_USER_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$WaJe04WkR0R04Kzul7lUGg$XttPcrDmd5srT2Wo5cla7vjuO90S/JKODaGstU2+h4E";
+
+/// what a rule allows on its keys
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+struct Rule {
+    read: bool,
+    write: bool,
+    pattern: String,
+}
+
+impl Rule {
+    /// true if the rule covers `key`, which may itself be a prefix ending in `*` as used by `watch`
+    fn covers(&self, key: &str) -> bool {
+        match (self.pattern.strip_suffix('*'), key.strip_suffix('*')) {
+            (Some(prefix), Some(key_prefix)) => key_prefix.starts_with(prefix),
+            (Some(prefix), None) => key.starts_with(prefix),
+            (None, _) => self.pattern == key,
+        }
+    }
+}
+
+pub struct User {
+    name: String,
+    password_hash: String,
+    rules: Vec<Rule>,
+}
+
+impl User {
+    pub fn allows(&self, access: Access, key: &str) -> bool {
+        self.rules.iter()
+            .filter(|rule| match access {
+                Access::Read => rule.read,
+                Access::Write => rule.write,
+            })
+            .any(|rule| rule.covers(key))
+    }
+}
+
+pub struct Users {
+    users: HashMap<String, Arc<User>>,
+}
+
+impl Users {
+    pub fn load(path: &Path) -> io::Result<Users> {
+        let content = fs::read_to_string(path)?;
+        let mut users: HashMap<String, User> = HashMap::new();
+        for (number, line) in content.lines().enumerate() {
+            let bad_line = |message: &str| io::Error::new(io::ErrorKind::InvalidData,
+                format!("{} line {}: {}", path.display(), number + 1, message));
+            let parts = line.split_whitespace().collect::<Vec<&str>>();
+            match parts[..] {
+                [] => {},
+                [first, ..] if first.starts_with('#') => {},
+                ["user", name, hash] => {
+                    PasswordHash::new(hash).map_err(|e| bad_line(&format!("invalid password hash: {}", e)))?;
+                    let user = User { name: name.to_string(), password_hash: hash.to_string(), rules: Vec::new() };
+                    if users.insert(name.to_string(), user).is_some() {
+                        return Err(bad_line(&format!("user {} is defined twice", name)));
+                    }
+                },
+                ["allow", name, access, pattern] => {
+                    let (read, write) = match access {
+                        "read" => (true, false),
+                        "write" => (false, true),
+                        "readwrite" => (true, true),
+                        _ => return Err(bad_line("access must be read, write or readwrite")),
+                    };
+                    let user = users.get_mut(name).ok_or_else(|| bad_line(&format!("unknown user {}", name)))?;
+                    user.rules.push(Rule { read, write, pattern: pattern.to_string() });
+                },
+                _ => return Err(bad_line("expected 'user <name> <hash>' or 'allow <name> <access> <key>'")),
+            }
+        }
+        info!("Loaded {} users from {}", users.len(), path.display());
+        Ok(Users { users: users.into_iter().map(|(name, user)| (name, Arc::new(user))).collect() })
+    }
+
+    /// the user if the password matches, hashing is slow on purpose and runs off the async threads
+    async fn authenticate(&self, name: &str, password: &str) -> Option<Arc<User>> {
+        let user = self.users.get(name).cloned();
+        let password = password.to_string();
+        tokio::task::spawn_blocking(move || {
+            // unknown users are hashed just the same, so the time taken does not tell which names exist
+            let stored = user.as_ref().map_or(UNKNOWN_USER_HASH, |user| &user.password_hash);
+            let hash = PasswordHash::new(stored).ok()?;
+            Argon2::default().verify_password(password.as_bytes(), &hash).ok()?;
+            user
+        }).await.ok().flatten()
+    }
+}
+
+/// hash a password for the users file
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default().hash_password(password.as_bytes(), &salt)
+        .expect("hashing with the default parameters works")
+        .to_string()
+}
+
+/// Who is logged in on a connection and what they may do.
+pub struct Login {
+    // None if the server runs without users
+    users: Option<Arc<Users>>,
+    user: Option<Arc<User>>,
+}
+
+impl Login {
+    pub fn new(users: Option<Arc<Users>>) -> Login {
+        Login { users, user: None }
+    }
+
+    /// handle `auth <name> <password>`, a failed attempt logs the connection out
+    pub async fn auth(&mut self, name: &str, password: &str) -> Response {
+        let Some(users) = &self.users else {
+            return Response::Error(ErrorKind::BadRequest, "the server runs without users".to_string());
+        };
+        self.user = users.authenticate(name, password).await;
+        match &self.user {
+            Some(user) => {
+                info!("Authenticated as {}", user.name);
+                Response::Ok()
+            },
+            None => Response::Error(ErrorKind::NoAuth, "invalid user name or password".to_string()),
+        }
+    }
+
+    /// the error to answer with if the request is not allowed
+    pub fn check(&self, request: &Request) -> Result<(), Response> {
+        self.require(request.accesses())
+    }
+
+    /// a follower needs to read every key
+    pub fn may_replicate(&self) -> Result<(), Response> {
+        self.require(vec![(Access::Read, "*")])
+    }
+
+    /// server statistics are open to every authenticated user
+    pub fn may_inspect(&self) -> Result<(), Response> {
+        self.require(Vec::new())
+    }
+
+    fn require(&self, accesses: Vec<(Access, &str)>) -> Result<(), Response> {
+        if self.users.is_none() {
+            return Ok(());
+        }
+        let Some(user) = &self.user else {
+            return Err(Response::Error(ErrorKind::NoAuth, "authentication required".to_string()));
+        };
+        for (access, key) in accesses {
+            if !user.allows(access, key) {
+                let verb = match access {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                };
+                return Err(Response::Error(ErrorKind::NoPerm, format!("user {} may not {} '{}'", user.name, verb, key)));
+            }
+        }
+        Ok(())
+    }
+
+    /// check the request and send it to the service, replies to `keys` only list readable keys
+    pub async fn send(&self, request: Request, tx: &Service) -> Result<Response, ServiceError> {
+        self.submit(request, tx).await?.response().await
+    }
+
+    /// like `send`, without waiting for the response, see `Service::send`
+    pub async fn submit(&self, request: Request, tx: &Service) -> Result<Pending, ServiceError> {
+        if let Err(response) = self.check(&request) {
+            return Ok(Pending::ready(response));
+        }
+        let Some(user) = self.user.clone() else {
+            return tx.send(request).await;
+        };
+        let lists_keys = |request: &Request| matches!(request, Request::Keys(_));
+        let filtered: Vec<bool> = match &request {
+            Request::Exec(requests) => requests.iter().map(lists_keys).collect(),
+            request => vec![lists_keys(request)],
+        };
+        // the user at the time of the request, a later auth does not change what it may see
+        let filter = move |response: Response, filtered: bool| match response {
+            Response::List(keys) if filtered =>
+                Response::List(keys.into_iter().filter(|key| user.allows(Access::Read, key)).collect()),
+            response => response,
+        };
+        Ok(tx.send(request).await?.map(move |response| match response {
+            Response::Multi(responses) if filtered.len() == responses.len() => Response::Multi(
+                responses.into_iter().zip(filtered).map(|(response, filtered)| filter(response, filtered)).collect()),
+            response => filter(response, filtered.first() == Some(&true)),
+        }))
+    }
+}
@@ -0,0 +1,270 @@
+//! Command line client of the key-value server.
+//!
+//! ```text
+//! kv-cli                          interactive, with history and tab completion
+//! kv-cli get foo                  run one command and exit
+//! kv-cli --file commands.txt      run the commands in a file, `-` or a pipe for stdin
+//! kv-cli --output json mget a b   print replies as JSON, or `raw` without decoration
+//! ```
+//!
+//! Commands are typed as on the text protocol, whitespace separates the arguments and double
+//! quotes with escapes like `\n` and `\xff` take whitespace and any other bytes, e.g.
+//! `set "my key" "line\nbreak"`. The arguments of a one-shot command are used as they are,
+//! the shell already split them. `multi`, `exec`, `discard` and `auth` work as on the text protocol:
+//! the first of them opens a connection that all later commands share. If it is lost, they fail
+//! until the next `multi` or `auth`. `subscribe`, `psubscribe` and `watch` print pushes until Ctrl-C.
+//! The exit code is 1 if a command failed.
+
+mod output;
+mod repl;
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use clap::Parser;
+use concurrent_tcp_listener::client::{self, Client, Options};
+use concurrent_tcp_listener::error::ClientError;
+use concurrent_tcp_listener::{escape, parse_command, Request, Response};
+use tokio::runtime::Runtime;
+use tracing::debug;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
+use output::Output;
+
+/// commands of the server the client can send, completed in the interactive mode
+const COMMANDS: &[&str] = &[
+    "get", "set", "del", "exists", "keys", "incr", "decr", "append", "strlen", "mset", "mget",
+    "expire", "ttl", "persist", "cas", "publish", "subscribe", "psubscribe", "watch",
+    "multi", "exec", "discard", "info", "stats", "auth",
+];
+
+/// commands of kv-cli itself
+const LOCAL_COMMANDS: &[&str] = &["help", "quit", "exit"];
+
+#[derive(Debug)]
+#[derive(Parser)]
+#[command(name = "kv-cli", about = "Command line client of the key-value server")]
+struct Cli {
+    /// Address of the server as host:port
+    #[arg(short, long, default_value = "127.0.0.1:8000", env = "KV_ADDRESS")]
+    address: String,
+
+    /// Log in as this user, needs --password
+    #[arg(short, long, requires = "password")]
+    user: Option<String>,
+
+    /// Password of the user
+    #[arg(short, long, env = "KV_PASSWORD", hide_env_values = true, requires = "user")]
+    password: Option<String>,
+
+    /// Connect with TLS, trusting the authority in this PEM file
+    #[arg(long, value_name = "FILE")]
+    tls_ca: Option<String>,
+
+    /// Run the commands in this file, one per line, `-` for stdin
+    #[arg(short, long, value_name = "FILE", conflicts_with = "command")]
+    file: Option<PathBuf>,
+
+    /// How replies are printed
+    #[arg(short, long, value_enum, default_value_t = Output::Human)]
+    output: Output,
+
+    /// Where the interactive mode keeps its history, ~/.kv_cli_history by default
+    #[arg(long, value_name = "FILE")]
+    history: Option<PathBuf>,
+
+    /// Turn debugging information on, repeat for more (-d info, -dd debug, -ddd trace)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    debug: u8,
+
+    /// Command to run instead of the interactive mode, e.g. `get foo`
+    #[arg(trailing_var_arg = true)]
+    command: Vec<String>,
+}
+
+fn init_logging(debug: u8) {
+    let filter = match debug {
+        0 => EnvFilter::builder().with_default_directive(LevelFilter::WARN.into()).from_env_lossy(),
+        1 => EnvFilter::default().add_directive(LevelFilter::INFO.into()),
+        2 => EnvFilter::default().add_directive(LevelFilter::DEBUG.into()),
+        _ => EnvFilter::default().add_directive(LevelFilter::TRACE.into()),
+    };
+    // log messages go to stderr, stdout is left to the replies
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .init();
+}
+
+/// what became of a command line
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Done,
+    Failed,
+    Quit,
+}
+
+/// the connection to the server and how replies are printed
+struct Session {
+    runtime: Runtime,
+    client: Client,
+    // the connection of a transaction or login, once opened all commands go through it
+    connection: RefCell<Option<client::Session>>,
+    output: Output,
+}
+
+impl Session {
+    /// run one line of input, empty lines and comments starting with `#` do nothing
+    fn run(&self, line: &str) -> Outcome {
+        if line.trim_start().starts_with('#') {
+            return Outcome::Done;
+        }
+        match escape::split(line.as_bytes()) {
+            Ok(words) => self.command(&words),
+            Err(message) => self.output.error(&ClientError::Invalid(message)),
+        }
+    }
+
+    /// run a command given as its words
+    fn command<W: AsRef<[u8]>>(&self, words: &[W]) -> Outcome {
+        let name = words.first().map(|name| String::from_utf8_lossy(name.as_ref()).to_ascii_lowercase()).unwrap_or_default();
+        match name.as_str() {
+            "" => Outcome::Done,
+            "quit" | "exit" => Outcome::Quit,
+            "help" => {
+                println!("commands: {}", COMMANDS.join(" "));
+                println!("also: {}", LOCAL_COMMANDS.join(" "));
+                Outcome::Done
+            },
+            "subscribe" | "psubscribe" | "watch" => self.listen(&name, &words[1..]),
+            // commands without a request, the server checks their arguments
+            "multi" | "exec" | "discard" | "auth" => self.print(self.in_session(&name, words)),
+            "info" | "stats" => self.print(self.runtime.block_on(self.client.command(words))),
+            _ => match parse_command(words) {
+                Ok(request) => self.print(self.request(request)),
+                Err(message) => self.output.error(&ClientError::Invalid(message)),
+            },
+        }
+    }
+
+    fn print(&self, result: Result<Response, ClientError>) -> Outcome {
+        match result {
+            Ok(response) => self.output.response(&response),
+            Err(e) => self.output.error(&e),
+        }
+    }
+
+    /// Send the command on the connection of the session, opened first if there is none.
+    /// After the connection was lost only a new `multi` or `auth` opens another one.
+    fn in_session<W: AsRef<[u8]>>(&self, name: &str, words: &[W]) -> Result<Response, ClientError> {
+        let mut connection = self.connection.borrow_mut();
+        let starts_over = name == "multi" || name == "auth";
+        let session = match connection.as_mut() {
+            Some(session) if !(session.is_lost() && starts_over) => session,
+            _ => connection.insert(self.runtime.block_on(self.client.session())?),
+        };
+        self.runtime.block_on(session.command(words))
+    }
+
+    /// send the request on the connection of the session if there is one, else on any connection
+    fn request(&self, request: Request) -> Result<Response, ClientError> {
+        match self.connection.borrow_mut().as_mut() {
+            Some(session) => self.runtime.block_on(session.request(request)),
+            None => self.runtime.block_on(self.client.request(request)),
+        }
+    }
+
+    /// print the pushes of a subscription until Ctrl-C or until the server is gone
+    fn listen<W: AsRef<[u8]>>(&self, command: &str, names: &[W]) -> Outcome {
+        let Ok(names) = names.iter().map(|name| std::str::from_utf8(name.as_ref())).collect::<Result<Vec<&str>, _>>() else {
+            return self.output.error(&ClientError::Invalid(format!("{} needs names in UTF-8", command)));
+        };
+        self.runtime.block_on(async {
+            let subscription = match command {
+                "subscribe" => self.client.subscribe(&names).await,
+                "psubscribe" => self.client.psubscribe(&names).await,
+                _ => self.client.watch(&names).await,
+            };
+            let mut subscription = match subscription {
+                Ok(subscription) => subscription,
+                Err(e) => return self.output.error(&e),
+            };
+            loop {
+                tokio::select! {
+                    push = subscription.next() => match push {
+                        Some(push) => self.output.push(&push),
+                        None => return self.output.error(&ClientError::Closed),
+                    },
+                    _ = tokio::signal::ctrl_c() => return Outcome::Done,
+                }
+            }
+        })
+    }
+
+    /// run the lines of a file or stdin, the outcome is Failed if any of them failed
+    fn script(&self, input: impl BufRead) -> Outcome {
+        let mut outcome = Outcome::Done;
+        for line in input.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return self.output.error(&ClientError::Io(e)),
+            };
+            match self.run(&line) {
+                Outcome::Quit => break,
+                Outcome::Failed => outcome = Outcome::Failed,
+                Outcome::Done => {},
+            }
+        }
+        outcome
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    init_logging(cli.debug);
+    debug!(address = %cli.address, output = ?cli.output, "Starting");
+
+    let runtime = match Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("kv-cli: {}", e);
+            return ExitCode::FAILURE;
+        },
+    };
+    let options = Options {
+        credentials: cli.user.clone().zip(cli.password.clone()),
+        tls_ca: cli.tls_ca.clone(),
+        // commands run one after the other, a transaction or login has a connection of its own
+        pool_size: 2,
+        ..Options::default()
+    };
+    let client = match runtime.block_on(Client::connect_with(&cli.address, options)) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("kv-cli: unable to connect to {}: {}", cli.address, e);
+            return ExitCode::FAILURE;
+        },
+    };
+    let session = Session { runtime, client, connection: RefCell::new(None), output: cli.output };
+
+    let outcome = match &cli.file {
+        _ if !cli.command.is_empty() => session.command(&cli.command),
+        Some(path) if path.as_os_str() == "-" => session.script(io::stdin().lock()),
+        Some(path) => match File::open(path) {
+            Ok(file) => session.script(BufReader::new(file)),
+            Err(e) => {
+                eprintln!("kv-cli: unable to read {}: {}", path.display(), e);
+                Outcome::Failed
+            },
+        },
+        None if !io::stdin().is_terminal() => session.script(io::stdin().lock()),
+        None => repl::run(&session, &cli.address, cli.history.or_else(repl::default_history)),
+    };
+    match outcome {
+        Outcome::Failed => ExitCode::FAILURE,
+        _ => ExitCode::SUCCESS,
+    }
+}
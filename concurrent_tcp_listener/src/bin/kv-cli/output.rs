@@ -0,0 +1,147 @@
+//! How replies and pushes are printed. Replies go to stdout, errors to stderr except in JSON,
+//! where every command prints exactly one line so the output of a script lines up with it.
+
+use std::io::{self, Write};
+use clap::ValueEnum;
+use concurrent_tcp_listener::escape;
+use concurrent_tcp_listener::Response;
+use concurrent_tcp_listener::error::ClientError;
+use concurrent_tcp_listener::pubsub::Push;
+use serde_json::{json, Value};
+use crate::Outcome;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Output {
+    /// quoted values and typed numbers, like `"bar"` and `(integer) 3`, escaped as in commands
+    Human,
+    /// values as the bytes they are, one per line, nothing for a missing key
+    Raw,
+    /// one JSON value per reply, errors as `{"error": code, "message": text}`,
+    /// values that are not UTF-8 have replacement characters
+    Json,
+}
+
+impl Output {
+    pub fn response(&self, response: &Response) -> Outcome {
+        if let Response::Error(kind, message) = response {
+            return self.failure(kind.code(), message);
+        }
+        match self {
+            Output::Human => println!("{}", human(response)),
+            Output::Raw => {
+                let mut stdout = io::stdout().lock();
+                let _ = stdout.write_all(&raw(response)).and_then(|()| stdout.write_all(b"\n"));
+            },
+            Output::Json => println!("{}", to_json(response)),
+        }
+        Outcome::Done
+    }
+
+    pub fn error(&self, error: &ClientError) -> Outcome {
+        match error {
+            ClientError::Server(kind, message) => self.failure(kind.code(), message),
+            ClientError::Invalid(message) => self.failure("INVALID", message),
+            other => self.failure("CONNECTION", &other.to_string()),
+        }
+    }
+
+    fn failure(&self, code: &str, message: &str) -> Outcome {
+        match self {
+            Output::Human => eprintln!("(error) {} {}", code, message),
+            Output::Raw => eprintln!("{} {}", code, message),
+            Output::Json => println!("{}", json!({"error": code, "message": message})),
+        }
+        Outcome::Failed
+    }
+
+    pub fn push(&self, push: &Push) {
+        match self {
+            Output::Human => println!("{}", push_text(push, |payload| format!("{:?}", payload))),
+            Output::Raw => println!("{}", push_text(push, str::to_string)),
+            Output::Json => println!("{}", push_json(push)),
+        }
+    }
+}
+
+/// lists are numbered, one item per line
+fn human(response: &Response) -> String {
+    let items = |items: Vec<String>| {
+        if items.is_empty() {
+            return "(empty list)".to_string();
+        }
+        items.iter().enumerate()
+            .map(|(i, item)| format!("{}) {}", i + 1, item))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+    match response {
+        Response::Ok() => "OK".to_string(),
+        Response::Queued() => "QUEUED".to_string(),
+        Response::NotFound(_) => "(nil)".to_string(),
+        Response::Result(value) => escape::quoted(value),
+        Response::Integer(n) => format!("(integer) {}", n),
+        Response::List(values) => items(values.iter().map(|value| escape::quoted(value.as_bytes())).collect()),
+        Response::Values(values) => items(values.iter().map(|value| match value {
+            Some(value) => escape::quoted(value),
+            None => "(nil)".to_string(),
+        }).collect()),
+        Response::Multi(responses) => items(responses.iter().map(human).collect()),
+        Response::Error(kind, message) => format!("(error) {} {}", kind.code(), message),
+        other => format!("{:?}", other),
+    }
+}
+
+fn raw(response: &Response) -> Vec<u8> {
+    match response {
+        Response::Ok() => b"OK".to_vec(),
+        Response::Queued() => b"QUEUED".to_vec(),
+        Response::NotFound(_) => Vec::new(),
+        Response::Result(value) => value.clone(),
+        Response::Integer(n) => n.to_string().into_bytes(),
+        Response::List(values) => values.join("\n").into_bytes(),
+        Response::Values(values) =>
+            values.iter().map(|value| value.as_deref().unwrap_or_default()).collect::<Vec<&[u8]>>().join(&b'\n'),
+        Response::Multi(responses) => responses.iter().map(raw).collect::<Vec<Vec<u8>>>().join(&b'\n'),
+        Response::Error(kind, message) => format!("{} {}", kind.code(), message).into_bytes(),
+        other => format!("{:?}", other).into_bytes(),
+    }
+}
+
+fn to_json(response: &Response) -> Value {
+    match response {
+        Response::Ok() => json!("OK"),
+        Response::Queued() => json!("QUEUED"),
+        Response::NotFound(_) => Value::Null,
+        Response::Result(value) => json!(String::from_utf8_lossy(value)),
+        Response::Integer(n) => json!(n),
+        Response::List(values) => json!(values),
+        Response::Values(values) =>
+            Value::Array(values.iter().map(|value| json!(value.as_deref().map(String::from_utf8_lossy))).collect()),
+        Response::Multi(responses) => Value::Array(responses.iter().map(to_json).collect()),
+        Response::Error(kind, message) => json!({"error": kind.code(), "message": message}),
+        other => json!(format!("{:?}", other)),
+    }
+}
+
+/// the push in words, payloads shown by `payload`
+fn push_text(push: &Push, payload: impl Fn(&str) -> String) -> String {
+    match push {
+        Push::Message { channel, payload: text } => format!("message {} {}", channel, payload(text)),
+        Push::PatternMessage { pattern, channel, payload: text } =>
+            format!("pmessage {} {} {}", pattern, channel, payload(text)),
+        Push::Event(event) => format!("event {} {}", event.kind.name(), event.key),
+        Push::Lagged(count) => format!("lagged {} pushes dropped", count),
+        Push::Shutdown => "shutdown".to_string(),
+    }
+}
+
+fn push_json(push: &Push) -> Value {
+    match push {
+        Push::Message { channel, payload } => json!({"type": "message", "channel": channel, "payload": payload}),
+        Push::PatternMessage { pattern, channel, payload } =>
+            json!({"type": "pmessage", "pattern": pattern, "channel": channel, "payload": payload}),
+        Push::Event(event) => json!({"type": "event", "kind": event.kind.name(), "key": event.key}),
+        Push::Lagged(count) => json!({"type": "lagged", "count": count}),
+        Push::Shutdown => json!({"type": "shutdown"}),
+    }
+}
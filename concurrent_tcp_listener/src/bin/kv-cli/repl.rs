@@ -0,0 +1,92 @@
+//! The interactive mode: a prompt with line editing, history kept across sessions and tab
+//! completion of command names.
+
+use std::path::PathBuf;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use tracing::debug;
+use crate::{Outcome, Session, COMMANDS, LOCAL_COMMANDS};
+
+/// ~/.kv_cli_history, None without a home directory
+pub fn default_history() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".kv_cli_history"))
+}
+
+/// read and run commands until quit or end of input, Ctrl-C only clears the line
+pub fn run(session: &Session, address: &str, history: Option<PathBuf>) -> Outcome {
+    let mut editor = match Editor::<Commands, FileHistory>::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("kv-cli: no interactive mode on this terminal: {}", e);
+            return Outcome::Failed;
+        },
+    };
+    editor.set_helper(Some(Commands));
+    if let Some(path) = &history {
+        // there is none before the first session
+        if let Err(e) = editor.load_history(path) {
+            debug!("No history loaded from {}: {}", path.display(), e);
+        }
+    }
+    let prompt = format!("{}> ", address);
+    loop {
+        match editor.readline(&prompt) {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    let _ = editor.add_history_entry(line.as_str());
+                }
+                if session.run(&line) == Outcome::Quit {
+                    break;
+                }
+            },
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("kv-cli: {}", e);
+                break;
+            },
+        }
+    }
+    if let Some(path) = &history && let Err(e) = editor.save_history(path) {
+        eprintln!("kv-cli: unable to save the history to {}: {}", path.display(), e);
+    }
+    Outcome::Done
+}
+
+/// completes the command name at the start of the line
+struct Commands;
+
+impl Completer for Commands {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        let start = before.len() - before.trim_start().len();
+        let word = &before[start..];
+        // arguments are keys, which only the server knows
+        if word.contains(char::is_whitespace) {
+            return Ok((pos, Vec::new()));
+        }
+        let word = word.to_ascii_lowercase();
+        let candidates = COMMANDS.iter().chain(LOCAL_COMMANDS)
+            .filter(|command| command.starts_with(&word))
+            .map(|command| format!("{} ", command))
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for Commands {
+    type Hint = String;
+}
+
+impl Highlighter for Commands {}
+
+impl Validator for Commands {}
+
+impl Helper for Commands {}
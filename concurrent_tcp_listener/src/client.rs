@@ -0,0 +1,605 @@
+//! An async client for the text protocol, speaking the `Request` and `Response` types of the server.
+//!
+//! A `Client` keeps a pool of connections shared by its clones, each request borrows one for
+//! its round trip. A broken connection is replaced by a new one on the next request. A pooled
+//! connection the server has closed in the meantime, e.g. by restarting, is noticed before a
+//! request is sent on it. Requests that only read are sent again on a new connection if the
+//! old one fails while they are in flight, writes are not. Subscriptions get a connection of their
+//! own, which subscribes again after reconnecting; messages published in between are lost.
+//! Transactions and logins need a `Session`, which keeps one connection to itself and fails
+//! once that connection is lost instead of quietly carrying on without the server side state.
+//! Values are bytes, keys and values are quoted on the wire as far as needed, see `escape`.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), concurrent_tcp_listener::error::ClientError> {
+//! use concurrent_tcp_listener::client::Client;
+//! use concurrent_tcp_listener::Request;
+//!
+//! let client = Client::connect("127.0.0.1:8000").await?;
+//! client.set("greeting", "hello world").await?;
+//! assert_eq!(client.get("greeting").await?.as_deref(), Some(&b"hello world"[..]));
+//! let responses = client.pipeline(vec![Request::Incr("n".to_string()), Request::Get("n".to_string())]).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufStream};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::time::{self, Duration};
+use tokio_rustls::TlsConnector;
+use tracing::debug;
+use crate::error::ClientError;
+use crate::escape;
+use crate::pubsub::{Push, OUTBOX_CAPACITY};
+use crate::store::{EventKind, KeyEvent};
+use crate::tls::{self, Connection};
+use crate::{parse_command, ErrorKind, Request, Response};
+
+type Stream = BufStream<Connection>;
+
+/// how a client connects
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// connections open at the same time at most, further requests wait for one
+    pub pool_size: usize,
+    /// user and password sent with `auth` on every new connection
+    pub credentials: Option<(String, String)>,
+    /// PEM file of the authority that signed the server certificate, None for plaintext
+    pub tls_ca: Option<String>,
+    /// how often a subscription tries to reconnect before it ends
+    pub reconnect_attempts: u32,
+    /// pause before each attempt to reconnect, doubled after every failed one
+    pub reconnect_delay: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            pool_size: 4,
+            credentials: None,
+            tls_ca: None,
+            reconnect_attempts: 5,
+            reconnect_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// A connection pool to a server, cheap to clone.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    address: String,
+    options: Options,
+    tls: Option<TlsConnector>,
+    idle: Mutex<Vec<Stream>>,
+    // one permit per connection in use
+    slots: Arc<Semaphore>,
+}
+
+impl Client {
+    /// connect to `address` given as host:port with the default options
+    pub async fn connect(address: &str) -> Result<Client, ClientError> {
+        Client::connect_with(address, Options::default()).await
+    }
+
+    /// connect with `options`, fails if the first connection cannot be opened
+    pub async fn connect_with(address: &str, options: Options) -> Result<Client, ClientError> {
+        let tls = match &options.tls_ca {
+            Some(ca) => Some(tls::connector(ca, None)?),
+            None => None,
+        };
+        let slots = Arc::new(Semaphore::new(options.pool_size.max(1)));
+        let inner = Inner { address: address.to_string(), options, tls, idle: Mutex::new(Vec::new()), slots };
+        let client = Client { inner: Arc::new(inner) };
+        let stream = client.open().await?;
+        client.put_back(stream);
+        Ok(client)
+    }
+
+    /// the value of `key`, None if it does not exist
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, ClientError> {
+        match self.request(Request::Get(key.to_string())).await? {
+            Response::Result(value) => Ok(Some(value)),
+            Response::NotFound(_) => Ok(None),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn set(&self, key: &str, value: impl AsRef<[u8]>) -> Result<(), ClientError> {
+        match self.request(Request::Set(key.to_string(), value.as_ref().to_vec())).await? {
+            Response::Ok() => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// remove the keys, returns how many of them existed
+    pub async fn del(&self, keys: &[&str]) -> Result<i64, ClientError> {
+        let keys = keys.iter().map(|key| key.to_string()).collect();
+        match self.request(Request::Del(keys)).await? {
+            Response::Integer(count) => Ok(count),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// send `message` to the subscribers of `channel`, returns how many received it
+    pub async fn publish(&self, channel: &str, message: &str) -> Result<i64, ClientError> {
+        match self.request(Request::Publish(channel.to_string(), message.to_string())).await? {
+            Response::Integer(count) => Ok(count),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Send a request and wait for its response.
+    /// Errors reported by the server are returned as `Response::Error`.
+    pub async fn request(&self, request: Request) -> Result<Response, ClientError> {
+        let mut responses = self.pipeline(vec![request]).await?;
+        Ok(responses.remove(0))
+    }
+
+    /// Send all requests at once and read their responses, in the same order.
+    /// Transactions and subscriptions cannot be part of a pipeline.
+    pub async fn pipeline(&self, requests: Vec<Request>) -> Result<Vec<Response>, ClientError> {
+        let mut batch = Vec::new();
+        for request in &requests {
+            batch.extend_from_slice(line(request)?.as_bytes());
+            batch.extend_from_slice(b"\n");
+        }
+        let _slot = self.inner.slots.acquire().await.expect("the pool is never closed");
+        let pooled = self.take_open().await;
+        let reused = pooled.is_some();
+        let mut stream = match pooled {
+            Some(stream) => stream,
+            None => self.open().await?,
+        };
+        let result = exchange(&mut stream, &batch, &requests).await;
+        // the server may have applied writes before the connection failed, only reads are repeated
+        let retry = reused && matches!(result, Err(ClientError::Closed | ClientError::Io(_)))
+            && !requests.iter().any(Request::is_write);
+        let result = if retry {
+            debug!("Pooled connection to {} was closed, reconnecting", self.inner.address);
+            stream = self.open().await?;
+            exchange(&mut stream, &batch, &requests).await
+        } else {
+            result
+        };
+        // after an error the state of the connection is unknown, so it is dropped
+        if result.is_ok() {
+            self.put_back(stream);
+        }
+        result
+    }
+
+    /// Send a command given as its words and wait for the reply, for commands without a
+    /// `Request` like `info` or `stats`. It is never sent again, even if the connection fails.
+    /// Commands that change the state of the connection, like `multi` or `auth`, need a `Session`.
+    pub async fn command<W: AsRef<[u8]>>(&self, words: &[W]) -> Result<Response, ClientError> {
+        let line = command_line(words)?;
+        let name = String::from_utf8_lossy(words[0].as_ref()).to_ascii_lowercase();
+        if SESSION_COMMANDS.contains(&name.as_str()) {
+            return Err(ClientError::Invalid(format!("{} changes the state of the connection, it needs a session", name)));
+        }
+        let _slot = self.inner.slots.acquire().await.expect("the pool is never closed");
+        let mut stream = match self.take_open().await {
+            Some(stream) => stream,
+            None => self.open().await?,
+        };
+        stream.write_all(line.as_bytes()).await?;
+        stream.flush().await?;
+        let response = untyped(read_reply(&mut stream).await?)?;
+        self.put_back(stream);
+        Ok(response)
+    }
+
+    /// A connection of its own for a transaction or a login, taken from the pool and
+    /// closed when the session is dropped. It counts towards the pool size meanwhile.
+    pub async fn session(&self) -> Result<Session, ClientError> {
+        let slot = self.inner.slots.clone().acquire_owned().await.expect("the pool is never closed");
+        let stream = match self.take_open().await {
+            Some(stream) => stream,
+            None => self.open().await?,
+        };
+        Ok(Session { stream: Some(stream), _slot: slot })
+    }
+
+    /// messages published to any of the channels
+    pub async fn subscribe(&self, channels: &[&str]) -> Result<Subscription, ClientError> {
+        self.listen("subscribe", channels).await
+    }
+
+    /// messages published to channels matching any of the glob patterns
+    pub async fn psubscribe(&self, patterns: &[&str]) -> Result<Subscription, ClientError> {
+        self.listen("psubscribe", patterns).await
+    }
+
+    /// changes of the keys, or of all keys with a prefix if it ends with `*`
+    pub async fn watch(&self, keys: &[&str]) -> Result<Subscription, ClientError> {
+        self.listen("watch", keys).await
+    }
+
+    async fn listen(&self, command: &str, names: &[&str]) -> Result<Subscription, ClientError> {
+        if names.is_empty() {
+            return Err(ClientError::Invalid(format!("{} needs at least one name", command)));
+        }
+        let names = names.iter().map(|name| escape::quote(name.as_bytes())).collect::<Vec<String>>();
+        let line = format!("{} {}\n", command, names.join(" "));
+        let stream = self.subscribed(&line).await?;
+        let (tx, rx) = mpsc::channel(OUTBOX_CAPACITY);
+        tokio::spawn(forward(self.clone(), line, stream, tx));
+        Ok(Subscription { pushes: rx })
+    }
+
+    /// a new connection that sent `line` to subscribe
+    async fn subscribed(&self, line: &str) -> Result<Stream, ClientError> {
+        let mut stream = self.open().await?;
+        stream.write_all(line.as_bytes()).await?;
+        stream.flush().await?;
+        match read_reply(&mut stream).await? {
+            Reply::Integer(_) => Ok(stream),
+            Reply::Error(kind, message) => Err(ClientError::Server(kind, message)),
+            other => Err(ClientError::Protocol(format!("unexpected reply {:?}", other))),
+        }
+    }
+
+    /// a new connection, logged in if there are credentials
+    async fn open(&self) -> Result<Stream, ClientError> {
+        let socket = tls::connect(&self.inner.address, self.inner.tls.as_ref()).await?;
+        let mut stream = BufStream::new(socket);
+        if let Some((user, password)) = &self.inner.options.credentials {
+            let line = format!("auth {} {}\n", escape::quote(user.as_bytes()), escape::quote(password.as_bytes()));
+            stream.write_all(line.as_bytes()).await?;
+            stream.flush().await?;
+            match read_reply(&mut stream).await? {
+                Reply::Status(status) if status == "OK" => {},
+                Reply::Error(kind, message) => return Err(ClientError::Server(kind, message)),
+                other => return Err(ClientError::Protocol(format!("unexpected reply {:?}", other))),
+            }
+        }
+        Ok(stream)
+    }
+
+    fn take_idle(&self) -> Option<Stream> {
+        self.inner.idle.lock().unwrap_or_else(|e| e.into_inner()).pop()
+    }
+
+    /// an idle connection still open, the closed ones are dropped
+    async fn take_open(&self) -> Option<Stream> {
+        while let Some(mut stream) = self.take_idle() {
+            if !is_closed(&mut stream).await {
+                return Some(stream);
+            }
+            debug!("Pooled connection to {} was closed, dropping it", self.inner.address);
+        }
+        None
+    }
+
+    fn put_back(&self, stream: Stream) {
+        self.inner.idle.lock().unwrap_or_else(|e| e.into_inner()).push(stream);
+    }
+}
+
+/// commands that change the state of the connection they are sent on
+const SESSION_COMMANDS: &[&str] = &[
+    "multi", "exec", "discard", "auth", "subscribe", "psubscribe", "unsubscribe", "punsubscribe", "watch", "unwatch",
+];
+
+/// One connection for commands that depend on each other, like those of a transaction.
+/// Nothing is sent again on another connection: once the connection failed, every further
+/// command fails with `ClientError::SessionLost`, the server dropped the transaction or login with it.
+pub struct Session {
+    // None once the connection failed
+    stream: Option<Stream>,
+    _slot: OwnedSemaphorePermit,
+}
+
+impl Session {
+    /// send a request and wait for its response, errors of the server are a `Response::Error`
+    pub async fn request(&mut self, request: Request) -> Result<Response, ClientError> {
+        let line = line(&request)? + "\n";
+        let reply = self.exchange(&line).await?;
+        response(reply, &request)
+    }
+
+    /// send a command given as its words, like `multi`, `exec` or `auth`, and wait for the reply
+    pub async fn command<W: AsRef<[u8]>>(&mut self, words: &[W]) -> Result<Response, ClientError> {
+        let line = command_line(words)?;
+        untyped(self.exchange(&line).await?)
+    }
+
+    /// true once the connection failed, the session cannot be used any more
+    pub fn is_lost(&self) -> bool {
+        self.stream.is_none()
+    }
+
+    async fn exchange(&mut self, line: &str) -> Result<Reply, ClientError> {
+        let Some(stream) = &mut self.stream else {
+            return Err(ClientError::SessionLost);
+        };
+        let result = async {
+            stream.write_all(line.as_bytes()).await?;
+            stream.flush().await?;
+            read_reply(stream).await
+        }.await;
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+}
+
+/// Pushes to a subscription, ends when the server cannot be reached any more.
+pub struct Subscription {
+    pushes: mpsc::Receiver<Push>,
+}
+
+impl Subscription {
+    /// the next push, None once the subscription ended
+    pub async fn next(&mut self) -> Option<Push> {
+        self.pushes.recv().await
+    }
+}
+
+impl tokio_stream::Stream for Subscription {
+    type Item = Push;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Push>> {
+        self.pushes.poll_recv(cx)
+    }
+}
+
+/// hand pushes to the subscription until it is dropped, reconnecting when the server goes away
+async fn forward(client: Client, line: String, mut stream: Stream, tx: mpsc::Sender<Push>) {
+    loop {
+        let push = tokio::select! {
+            push = read_push(&mut stream) => push,
+            _ = tx.closed() => return,
+        };
+        match push {
+            Ok(Push::Shutdown) | Err(_) => match resubscribe(&client, &line).await {
+                Some(new_stream) => stream = new_stream,
+                None => return,
+            },
+            Ok(push) => {
+                if tx.send(push).await.is_err() {
+                    return;
+                }
+            },
+        }
+    }
+}
+
+async fn resubscribe(client: &Client, line: &str) -> Option<Stream> {
+    let options = &client.inner.options;
+    let mut delay = options.reconnect_delay;
+    for attempt in 1..=options.reconnect_attempts {
+        time::sleep(delay).await;
+        match client.subscribed(line).await {
+            Ok(stream) => return Some(stream),
+            Err(e) => debug!("Resubscribing to {} failed ({}/{}): {}",
+                             client.inner.address, attempt, options.reconnect_attempts, e),
+        }
+        delay *= 2;
+    }
+    None
+}
+
+/// True if an idle connection has anything to read: the end of the stream, an error or
+/// a push like the shutdown notice. None of them leave the connection usable.
+async fn is_closed(stream: &mut Stream) -> bool {
+    poll_fn(|cx| Poll::Ready(Pin::new(&mut *stream).poll_fill_buf(cx).is_ready())).await
+}
+
+/// the words quoted as a line of the text protocol, with its line break
+fn command_line<W: AsRef<[u8]>>(words: &[W]) -> Result<String, ClientError> {
+    if words.is_empty() {
+        return Err(ClientError::Invalid("a command needs a name".to_string()));
+    }
+    let mut line = words.iter().map(|word| escape::quote(word.as_ref())).collect::<Vec<String>>().join(" ");
+    line.push('\n');
+    Ok(line)
+}
+
+/// the request as a line of the text protocol, if it reads back as the same request
+fn line(request: &Request) -> Result<String, ClientError> {
+    let Some(parts) = request.command() else {
+        return Err(ClientError::Invalid(format!("{} cannot be sent by the client", request.name())));
+    };
+    let line = parts.iter().map(|part| escape::quote(part)).collect::<Vec<String>>().join(" ");
+    // the server would reject it, e.g. an expiry of 0 seconds
+    let words = escape::split(line.as_bytes()).map_err(ClientError::Invalid)?;
+    if parse_command(&words).ok().and_then(|parsed| parsed.command()) != Some(parts) {
+        return Err(ClientError::Invalid(format!("{} is not a valid request", line)));
+    }
+    Ok(line)
+}
+
+async fn exchange(stream: &mut Stream, batch: &[u8], requests: &[Request]) -> Result<Vec<Response>, ClientError> {
+    stream.write_all(batch).await?;
+    stream.flush().await?;
+    let mut responses = Vec::with_capacity(requests.len());
+    for request in requests {
+        let reply = match read_reply(stream).await {
+            // only a connection closed before the first reply may be retried
+            Err(ClientError::Closed) if !responses.is_empty() =>
+                return Err(ClientError::Io(std::io::ErrorKind::UnexpectedEof.into())),
+            reply => reply?,
+        };
+        responses.push(response(reply, request)?);
+    }
+    Ok(responses)
+}
+
+/// a reply of the text protocol before it is matched with its request
+#[derive(Debug)]
+enum Reply {
+    Status(String),
+    NotFound(String),
+    Value(Option<Vec<u8>>),
+    Integer(i64),
+    Array(Vec<Reply>),
+    Error(ErrorKind, String),
+}
+
+fn response(reply: Reply, request: &Request) -> Result<Response, ClientError> {
+    Ok(match reply {
+        Reply::Array(items) if !matches!(request, Request::MGet(_)) => Response::List(values(items)?.into_iter().flatten()
+            .map(|key| String::from_utf8(key).map_err(|e| ClientError::Protocol(e.to_string())))
+            .collect::<Result<_, _>>()?),
+        reply => untyped(reply)?,
+    })
+}
+
+/// the response to a command without a request, arrays of values become `Values`, others `Multi`
+fn untyped(reply: Reply) -> Result<Response, ClientError> {
+    Ok(match reply {
+        Reply::Status(status) if status == "OK" => Response::Ok(),
+        Reply::Status(status) if status == "QUEUED" => Response::Queued(),
+        Reply::NotFound(key) => Response::NotFound(key),
+        Reply::Value(Some(value)) => Response::Result(value),
+        Reply::Integer(n) => Response::Integer(n),
+        Reply::Array(items) if items.iter().all(|item| matches!(item, Reply::Value(_))) => Response::Values(values(items)?),
+        Reply::Array(items) => Response::Multi(items.into_iter().map(untyped).collect::<Result<_, _>>()?),
+        Reply::Error(kind, message) => Response::Error(kind, message),
+        other => return Err(ClientError::Protocol(format!("unexpected reply {:?}", other))),
+    })
+}
+
+fn values(items: Vec<Reply>) -> Result<Vec<Option<Vec<u8>>>, ClientError> {
+    items.into_iter().map(|item| match item {
+        Reply::Value(value) => Ok(value),
+        other => Err(ClientError::Protocol(format!("unexpected reply {:?}", other))),
+    }).collect()
+}
+
+/// the next reply, pushes other than the shutdown notice are skipped
+async fn read_reply(stream: &mut Stream) -> Result<Reply, ClientError> {
+    loop {
+        let line = read_line(stream).await?;
+        // a value may be any bytes
+        if line.starts_with(b"$") {
+            return Ok(Reply::Value(read_value(stream, line).await?));
+        }
+        let text = trimmed(&line)?;
+        if text.starts_with(">shutdown") {
+            return Err(ClientError::Closed);
+        }
+        if text.starts_with('>') {
+            continue;
+        }
+        return Ok(match text.as_bytes().first() {
+            Some(b'+') => Reply::Status(text[1..].to_string()),
+            Some(b':') => Reply::Integer(number(&text[1..])?),
+            Some(b'*') => {
+                let mut items = Vec::new();
+                for _ in 0..number(&text[1..])? {
+                    // the reply to exec has the replies of the queued commands
+                    items.push(Box::pin(read_reply(stream)).await?);
+                }
+                Reply::Array(items)
+            },
+            Some(b'-') => {
+                let error = text.strip_prefix("-ERR ").ok_or_else(|| protocol(&text))?;
+                let (code, message) = error.split_once(' ').unwrap_or((error, ""));
+                let kind = ErrorKind::parse(code).ok_or_else(|| protocol(&text))?;
+                Reply::Error(kind, message.to_string())
+            },
+            _ => match text.strip_prefix("NOTFOUND ") {
+                Some(key) => match escape::split(key.as_bytes()).as_deref() {
+                    Ok([key]) => Reply::NotFound(name(key)?),
+                    _ => return Err(protocol(&text)),
+                },
+                None => return Err(protocol(&text)),
+            },
+        });
+    }
+}
+
+/// the next push on a subscribed connection
+async fn read_push(stream: &mut Stream) -> Result<Push, ClientError> {
+    let line = read_line(stream).await?;
+    let text = String::from_utf8_lossy(&line).trim_end().to_string();
+    // the payload is the sized value after the first words, it may span several lines
+    let count = if line.starts_with(b">pmessage ") || line.starts_with(b">event ") { 3 } else { 2 };
+    let (words, rest) = escape::split_words(&line, count).map_err(|_| protocol(&text))?;
+    let words = words.iter().map(|word| name(word)).collect::<Result<Vec<String>, ClientError>>()?;
+    Ok(match words.iter().map(String::as_str).collect::<Vec<&str>>()[..] {
+        [">message", channel] => {
+            let payload = read_value(stream, rest.to_vec()).await?.ok_or_else(|| protocol(&text))?;
+            Push::Message { channel: channel.to_string(), payload: name(&payload)? }
+        },
+        [">pmessage", pattern, channel] => {
+            let payload = read_value(stream, rest.to_vec()).await?.ok_or_else(|| protocol(&text))?;
+            Push::PatternMessage { pattern: pattern.to_string(), channel: channel.to_string(), payload: name(&payload)? }
+        },
+        [">event", kind, key] => {
+            let kind = EventKind::parse(kind).ok_or_else(|| protocol(&text))?;
+            Push::Event(KeyEvent { kind, key: key.to_string() })
+        },
+        [">lagged", count, ..] => Push::Lagged(count.parse().map_err(|_| protocol(&text))?),
+        [">shutdown", ..] => Push::Shutdown,
+        _ => return Err(protocol(&text)),
+    })
+}
+
+/// a line including its line break, fails at the end of the stream
+async fn read_line(stream: &mut Stream) -> Result<Vec<u8>, ClientError> {
+    let mut line = Vec::new();
+    if stream.read_until(b'\n', &mut line).await? == 0 {
+        return Err(ClientError::Closed);
+    }
+    Ok(line)
+}
+
+/// `$<len> <value>` starting at `line`, the value is read on as long as it has line breaks
+async fn read_value(stream: &mut Stream, line: Vec<u8>) -> Result<Option<Vec<u8>>, ClientError> {
+    let text = String::from_utf8_lossy(&line).to_string();
+    if text.trim_end() == "$-1" {
+        return Ok(None);
+    }
+    let Some((digits, _)) = text.strip_prefix('$').and_then(|rest| rest.split_once(' ')) else {
+        return Err(protocol(&text));
+    };
+    let len = usize::try_from(number(digits)?).map_err(|_| protocol(&text))?;
+    // the value starts after the space following the length
+    let mut value = line[digits.len() + 2..].to_vec();
+    while value.len() < len + 2 {
+        value.extend(read_line(stream).await?);
+    }
+    if value.len() != len + 2 || !value.ends_with(b"\r\n") {
+        return Err(protocol(&text));
+    }
+    value.truncate(len);
+    Ok(Some(value))
+}
+
+/// a key, channel or message of a reply, which has to be UTF-8 unlike values
+fn name(bytes: &[u8]) -> Result<String, ClientError> {
+    String::from_utf8(bytes.to_vec()).map_err(|e| ClientError::Protocol(e.to_string()))
+}
+
+fn trimmed(line: &[u8]) -> Result<String, ClientError> {
+    std::str::from_utf8(line)
+        .map(|text| text.trim_end_matches(['\r', '\n']).to_string())
+        .map_err(|e| ClientError::Protocol(e.to_string()))
+}
+
+fn number(text: &str) -> Result<i64, ClientError> {
+    text.parse().map_err(|_| protocol(text))
+}
+
+fn protocol(reply: &str) -> ClientError {
+    ClientError::Protocol(format!("unexpected reply {:?}", reply))
+}
+
+fn unexpected(response: Response) -> ClientError {
+    match response {
+        Response::Error(kind, message) => ClientError::Server(kind, message),
+        other => ClientError::Protocol(format!("unexpected response {:?}", other)),
+    }
+}
@@ -0,0 +1,470 @@
+//! Settings of the service. Every source overrides the ones before it:
+//!
+//! 1. the defaults
+//! 2. a TOML file given with `--config <path>` or `KV_CONFIG`
+//! 3. environment variables `KV_<NAME>`, e.g. `KV_MAX_CONNECTIONS=100`
+//! 4. the command line, `--<name> <value>`, e.g. `--max-connections 100`
+//!
+//! ```toml
+//! listen = ["127.0.0.1:8000", "[::1]:8000", "unix:/run/kv/text.sock"]  # text protocol
+//! resp-listen = ["127.0.0.1:6380"]      # redis protocol, [] for none
+//! metrics-listen = "127.0.0.1:9100"     # Prometheus metrics over HTTP, off by default, see `metrics`
+//! max-connections = 1024                # clients on all addresses together
+//! idle-timeout = 300                    # seconds without requests, 0 keeps connections open
+//! max-line-length = 1048576             # bytes per request, longer ones close the connection
+//! rate-limit = 1000                     # requests per second and connection, 0 for no limit
+//! persist-interval = 20                 # seconds between snapshots
+//! channel-capacity = 100                # requests queued per storage shard
+//! snapshot-path = "kv_snapshot.dat"     # also wal-path and data-path
+//! log-level = "info"                    # error, warn, info, debug, trace or a RUST_LOG filter
+//! log-format = "human"                  # human or json
+//! shards = 1
+//! engine = "memory"                     # memory, ordered or disk
+//! users = "users.txt"                   # see `auth`
+//! tls-cert = "cert.pem"                 # with tls-key and optionally tls-client-ca, see `tls`
+//! replicaof = "10.0.0.1:8000"           # with leader-user, leader-password and leader-tls-ca
+//! legacy-format = false
+//! ```
+//!
+//! The command line is parsed by `Args`, `--help` lists every setting with its variable.
+//! Environment variables use the same names in upper case with underscores, lists are comma
+//! separated. On the command line lists take one value per occurrence, e.g.
+//! `--listen 127.0.0.1:8000 --listen unix:/tmp/kv.sock`, and flags like `--legacy-format`
+//! need no value. `--port N` and `--resp-port N` are short for listening on 127.0.0.1:N.
+//! Without `log-level` the filter is taken from `RUST_LOG`, like in other programs using `tracing`.
+
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, Parser};
+use crate::log::{self, Format};
+use crate::storage::Engine;
+
+/// shortest max-line-length accepted
+const MIN_LINE_LENGTH: usize = 64;
+
+/// The command line of the server. Settings are kept as text and checked by `Config::load`,
+/// so that all problems are reported at once.
+#[derive(Debug, Parser)]
+#[command(name = "concurrent_tcp_listener", version, about = "A key-value server for a text protocol and RESP")]
+pub struct Args {
+    /// TOML file with settings, overridden by the environment and the command line
+    #[arg(long, value_name = "FILE", env = "KV_CONFIG")]
+    pub config: Option<String>,
+
+    /// Print the hash of the password read from stdin for the users file and exit
+    #[arg(long)]
+    pub hash_password: bool,
+
+    /// Address for the text protocol as host:port or unix:/path, repeat for more [default: 127.0.0.1:8000]
+    #[arg(long, value_name = "ADDRESS", env = "KV_LISTEN", value_delimiter = ',')]
+    pub listen: Vec<String>,
+
+    /// Address for the redis protocol as host:port or unix:/path, repeat for more [default: 127.0.0.1:6380]
+    #[arg(long, value_name = "ADDRESS", env = "KV_RESP_LISTEN", value_delimiter = ',')]
+    pub resp_listen: Vec<String>,
+
+    /// Serve the text protocol on 127.0.0.1 and this port only
+    #[arg(long, value_name = "PORT", env = "KV_PORT")]
+    pub port: Option<String>,
+
+    /// Serve the redis protocol on 127.0.0.1 and this port only
+    #[arg(long, value_name = "PORT", env = "KV_RESP_PORT")]
+    pub resp_port: Option<String>,
+
+    /// Address for Prometheus metrics over HTTP as host:port, off by default
+    #[arg(long, value_name = "ADDRESS", env = "KV_METRICS_LISTEN")]
+    pub metrics_listen: Option<String>,
+
+    /// Clients on all addresses together [default: 1024]
+    #[arg(long, value_name = "N", env = "KV_MAX_CONNECTIONS")]
+    pub max_connections: Option<String>,
+
+    /// Seconds without requests before a connection is closed, 0 keeps it open [default: 0]
+    #[arg(long, value_name = "SECONDS", env = "KV_IDLE_TIMEOUT")]
+    pub idle_timeout: Option<String>,
+
+    /// Bytes per request, longer ones close the connection [default: 1048576]
+    #[arg(long, value_name = "BYTES", env = "KV_MAX_LINE_LENGTH")]
+    pub max_line_length: Option<String>,
+
+    /// Requests per second and connection, 0 for no limit [default: 0]
+    #[arg(long, value_name = "N", env = "KV_RATE_LIMIT")]
+    pub rate_limit: Option<String>,
+
+    /// Seconds between snapshots [default: 20]
+    #[arg(long, value_name = "SECONDS", env = "KV_PERSIST_INTERVAL")]
+    pub persist_interval: Option<String>,
+
+    /// Requests queued per storage shard [default: 100]
+    #[arg(long, value_name = "N", env = "KV_CHANNEL_CAPACITY")]
+    pub channel_capacity: Option<String>,
+
+    /// Snapshot of an engine in memory [default: kv_snapshot.dat]
+    #[arg(long, value_name = "FILE", env = "KV_SNAPSHOT_PATH")]
+    pub snapshot_path: Option<String>,
+
+    /// Write-ahead log of an engine in memory [default: kv_wal.log]
+    #[arg(long, value_name = "FILE", env = "KV_WAL_PATH")]
+    pub wal_path: Option<String>,
+
+    /// Data file of the disk engine [default: kv_data.log]
+    #[arg(long, value_name = "FILE", env = "KV_DATA_PATH")]
+    pub data_path: Option<String>,
+
+    /// error, warn, info, debug, trace or a RUST_LOG filter [default: info]
+    #[arg(long, value_name = "FILTER", env = "KV_LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// human or json [default: human]
+    #[arg(long, value_name = "FORMAT", env = "KV_LOG_FORMAT")]
+    pub log_format: Option<String>,
+
+    /// Storage shards, each with its own files [default: 1]
+    #[arg(long, value_name = "N", env = "KV_SHARDS")]
+    pub shards: Option<String>,
+
+    /// memory, ordered or disk [default: memory]
+    #[arg(long, value_name = "ENGINE", env = "KV_ENGINE")]
+    pub engine: Option<String>,
+
+    /// File of users and their permissions
+    #[arg(long, value_name = "FILE", env = "KV_USERS")]
+    pub users: Option<String>,
+
+    /// PEM certificate chain to serve TLS with, needs --tls-key
+    #[arg(long, value_name = "FILE", env = "KV_TLS_CERT")]
+    pub tls_cert: Option<String>,
+
+    /// PEM private key of the certificate
+    #[arg(long, value_name = "FILE", env = "KV_TLS_KEY")]
+    pub tls_key: Option<String>,
+
+    /// Require client certificates signed by the authority in this PEM file
+    #[arg(long, value_name = "FILE", env = "KV_TLS_CLIENT_CA")]
+    pub tls_client_ca: Option<String>,
+
+    /// Follow the leader at this host:port
+    #[arg(long, value_name = "ADDRESS", env = "KV_REPLICAOF")]
+    pub replicaof: Option<String>,
+
+    /// User to log in to the leader as, needs --leader-password
+    #[arg(long, value_name = "USER", env = "KV_LEADER_USER")]
+    pub leader_user: Option<String>,
+
+    /// Password of the leader user
+    #[arg(long, value_name = "PASSWORD", env = "KV_LEADER_PASSWORD", hide_env_values = true)]
+    pub leader_password: Option<String>,
+
+    /// Connect to the leader with TLS, trusting the authority in this PEM file
+    #[arg(long, value_name = "FILE", env = "KV_LEADER_TLS_CA")]
+    pub leader_tls_ca: Option<String>,
+
+    /// Reply in the Debug format of earlier versions and join unquoted words into values
+    #[arg(long, value_name = "BOOL", env = "KV_LEGACY_FORMAT", num_args = 0..=1, default_missing_value = "true")]
+    pub legacy_format: Option<String>,
+}
+
+/// where clients connect
+#[derive(Debug, Clone, PartialEq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    /// `host:port` for TCP or `unix:/path/to/socket`
+    fn from_str(text: &str) -> Result<Address, String> {
+        if let Some(path) = text.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("unix: needs a path".to_string());
+            }
+            return Ok(Address::Unix(PathBuf::from(path)));
+        }
+        text.parse::<SocketAddr>().map(Address::Tcp).map_err(|_| format!(
+            "{:?} is no address, expected e.g. 127.0.0.1:8000, [::1]:8000 or unix:/path/to/socket", text))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => write!(f, "{}", address),
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub listen: Vec<Address>,
+    pub resp_listen: Vec<Address>,
+    pub metrics_listen: Option<SocketAddr>,
+    pub max_connections: usize,
+    // None keeps idle connections open
+    pub idle_timeout: Option<Duration>,
+    pub max_line_length: usize,
+    // None for no limit
+    pub rate_limit: Option<u32>,
+    pub persist_interval: Duration,
+    pub channel_capacity: usize,
+    pub snapshot_path: PathBuf,
+    pub wal_path: PathBuf,
+    pub data_path: PathBuf,
+    // a filter in the syntax of RUST_LOG
+    pub log_level: String,
+    pub log_format: Format,
+    pub shards: usize,
+    pub engine: Engine,
+    pub users: Option<String>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_client_ca: Option<String>,
+    pub replicaof: Option<String>,
+    pub leader_user: Option<String>,
+    pub leader_password: Option<String>,
+    pub leader_tls_ca: Option<String>,
+    pub legacy_format: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            listen: vec![local(8000)],
+            // the default redis port is left to a real redis
+            resp_listen: vec![local(6380)],
+            metrics_listen: None,
+            max_connections: 1024,
+            idle_timeout: None,
+            max_line_length: 1024 * 1024,
+            rate_limit: None,
+            persist_interval: Duration::from_secs(20),
+            channel_capacity: 100,
+            snapshot_path: PathBuf::from("kv_snapshot.dat"),
+            wal_path: PathBuf::from("kv_wal.log"),
+            data_path: PathBuf::from("kv_data.log"),
+            log_level: "info".to_string(),
+            log_format: Format::Human,
+            shards: 1,
+            engine: Engine::Memory,
+            users: None,
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
+            replicaof: None,
+            leader_user: None,
+            leader_password: None,
+            leader_tls_ca: None,
+            legacy_format: false,
+        }
+    }
+}
+
+impl Config {
+    /// Read all sources for a command line parsed with `Args` and check the result.
+    /// The error lists every problem found.
+    pub fn load(matches: &ArgMatches) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+
+        // the usual variable of programs using tracing, below every other source
+        if let Ok(value) = std::env::var("RUST_LOG")
+            && let Err(e) = config.set("log-level", &[&value]) {
+            errors.push(format!("RUST_LOG: {}", e));
+        }
+        if let Some(path) = matches.get_one::<String>("config") {
+            match std::fs::read_to_string(path) {
+                Ok(content) => config.apply_file(path, &content, &mut errors),
+                Err(e) => errors.push(format!("{}: {}", path, e)),
+            }
+        }
+
+        // the environment first, then the command line in the order it was given
+        let command = Args::command();
+        let mut settings = Vec::new();
+        for arg in command.get_arguments() {
+            let id = arg.get_id().as_str();
+            // flags like --hash-password are no settings
+            let (Some(name), Ok(Some(values))) = (arg.get_long(), matches.try_get_many::<String>(id)) else {
+                continue;
+            };
+            if name == "config" {
+                continue;
+            }
+            let mut values = values.map(String::as_str).collect::<Vec<&str>>();
+            if arg.get_value_delimiter().is_some() {
+                values = values.into_iter().map(str::trim).filter(|value| !value.is_empty()).collect();
+            }
+            let (position, source) = match (matches.value_source(id), arg.get_env()) {
+                (Some(ValueSource::EnvVariable), Some(variable)) => (0, variable.to_string_lossy().into_owned()),
+                _ => (matches.index_of(id).map_or(0, |index| index + 1), format!("--{}", name)),
+            };
+            settings.push((position, source, name, values));
+        }
+        settings.sort_by_key(|(position, ..)| *position);
+        for (_, source, name, values) in settings {
+            if let Err(e) = config.set(name, &values) {
+                errors.push(format!("{}: {}", source, e));
+            }
+        }
+
+        errors.extend(config.check());
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(format!("Invalid configuration:\n  {}", errors.join("\n  ")))
+        }
+    }
+
+    fn apply_file(&mut self, path: &str, content: &str, errors: &mut Vec<String>) {
+        let table = match content.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(e) => return errors.push(format!("{}: {}", path, e)),
+        };
+        for (name, value) in table {
+            let values = match value {
+                toml::Value::Array(items) => items.into_iter().map(scalar).collect(),
+                value => vec![scalar(value)],
+            };
+            let result = match values.into_iter().collect::<Result<Vec<String>, String>>() {
+                Ok(values) => self.set(&name, &values.iter().map(String::as_str).collect::<Vec<&str>>()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                errors.push(format!("{}: {}: {}", path, name, e));
+            }
+        }
+    }
+
+    /// set one setting from its textual values
+    fn set(&mut self, name: &str, values: &[&str]) -> Result<(), String> {
+        let value = || match values {
+            [value] => Ok(*value),
+            _ => Err("expected a single value".to_string()),
+        };
+        let text = || value().map(|value| Some(value.to_string()));
+        match name {
+            "listen" => self.listen = addresses(values)?,
+            "resp-listen" => self.resp_listen = addresses(values)?,
+            "port" => self.listen = vec![local(port(value()?)?)],
+            "resp-port" => self.resp_listen = vec![local(port(value()?)?)],
+            "metrics-listen" => self.metrics_listen = match Address::from_str(value()?)? {
+                Address::Tcp(address) => Some(address),
+                Address::Unix(_) => return Err("metrics are only served over TCP".to_string()),
+            },
+            "max-connections" => self.max_connections = positive(value()?)?,
+            "idle-timeout" => self.idle_timeout = match seconds(value()?)? {
+                0 => None,
+                seconds => Some(Duration::from_secs(seconds)),
+            },
+            "max-line-length" => self.max_line_length = match positive(value()?)? {
+                // room for at least a short command
+                n if n < MIN_LINE_LENGTH => return Err(format!("{} is less than the minimum of {}", n, MIN_LINE_LENGTH)),
+                n => n,
+            },
+            "rate-limit" => self.rate_limit = match value()?.parse::<u32>() {
+                Ok(0) => None,
+                Ok(n) => Some(n),
+                Err(_) => return Err(format!("{:?} is no number of requests per second", value()?)),
+            },
+            "persist-interval" => self.persist_interval = Duration::from_secs(positive(value()?)? as u64),
+            "channel-capacity" => self.channel_capacity = positive(value()?)?,
+            "snapshot-path" => self.snapshot_path = path(value()?)?,
+            "wal-path" => self.wal_path = path(value()?)?,
+            "data-path" => self.data_path = path(value()?)?,
+            "log-level" => {
+                log::filter(value()?)?;
+                self.log_level = value()?.to_string();
+            },
+            "log-format" => self.log_format = Format::parse(value()?).ok_or("expected human or json")?,
+            "shards" => self.shards = positive(value()?)?,
+            "engine" => self.engine = Engine::parse(value()?)
+                .ok_or("expected one of memory, ordered or disk")?,
+            "users" => self.users = text()?,
+            "tls-cert" => self.tls_cert = text()?,
+            "tls-key" => self.tls_key = text()?,
+            "tls-client-ca" => self.tls_client_ca = text()?,
+            "replicaof" => self.replicaof = text()?,
+            "leader-user" => self.leader_user = text()?,
+            "leader-password" => self.leader_password = text()?,
+            "leader-tls-ca" => self.leader_tls_ca = text()?,
+            "legacy-format" => self.legacy_format = value()?.parse::<bool>()
+                .map_err(|_| "expected true or false")?,
+            _ => return Err("unknown setting".to_string()),
+        }
+        Ok(())
+    }
+
+    /// problems with combinations of settings
+    fn check(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.listen.is_empty() && self.resp_listen.is_empty() {
+            errors.push("listen and resp-listen are both empty, clients could not connect".to_string());
+        }
+        let metrics = self.metrics_listen.map(Address::Tcp);
+        let all = self.listen.iter().chain(&self.resp_listen).chain(&metrics).collect::<Vec<&Address>>();
+        for (i, address) in all.iter().enumerate() {
+            if all[..i].contains(address) {
+                errors.push(format!("{} is used twice", address));
+            }
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            errors.push("tls-cert and tls-key go together".to_string());
+        }
+        if self.tls_client_ca.is_some() && self.tls_cert.is_none() {
+            errors.push("tls-client-ca needs tls-cert and tls-key".to_string());
+        }
+        if self.leader_user.is_some() != self.leader_password.is_some() {
+            errors.push("leader-user and leader-password go together".to_string());
+        }
+        if self.replicaof.is_none() && (self.leader_user.is_some() || self.leader_tls_ca.is_some()) {
+            errors.push("leader-user, leader-password and leader-tls-ca need replicaof".to_string());
+        }
+        errors
+    }
+}
+
+/// a value in the file as it would be written on the command line
+fn scalar(value: toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(text) => Ok(text),
+        toml::Value::Integer(n) => Ok(n.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        other => Err(format!("unsupported value {}", other)),
+    }
+}
+
+fn local(port: u16) -> Address {
+    Address::Tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+}
+
+fn addresses(values: &[&str]) -> Result<Vec<Address>, String> {
+    values.iter().map(|value| Address::from_str(value)).collect()
+}
+
+fn port(value: &str) -> Result<u16, String> {
+    value.parse::<u16>().map_err(|_| format!("{:?} is no port number", value))
+}
+
+fn positive(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("{:?} is no positive number", value)),
+    }
+}
+
+fn seconds(value: &str) -> Result<u64, String> {
+    value.parse::<u64>().map_err(|_| format!("{:?} is no number of seconds", value))
+}
+
+fn path(value: &str) -> Result<PathBuf, String> {
+    if value.is_empty() {
+        return Err("the path is empty".to_string());
+    }
+    Ok(PathBuf::from(value))
+}
@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use crate::snapshot;
+use crate::storage::{self, Storage};
+use crate::store::Entry;
+use crate::wal;
+
+const OP_PUT: u8 = 1;
+const OP_DELETE: u8 = 2;
+
+/// frame header written by `wal::put_frame`: payload length and checksum
+const FRAME_HEADER_LEN: u64 = wal::FRAME_HEADER_LEN as u64;
+
+/// compaction is skipped while overwritten and deleted records take less than this
+const COMPACT_MIN_GARBAGE: u64 = 1024 * 1024;
+
+/// where the current value of a key is found in the file
+struct Location {
+    offset: u64,
+    len: u32,
+    expires_at: Option<u64>,
+    // size of the whole record, it becomes garbage when the key is overwritten or deleted
+    record_len: u64,
+}
+
+/// A log-structured engine: every write appends a record to a single file and is synced
+/// before it returns, an index in memory points to the latest value of each key.
+/// Values are only read from disk when requested. `compact` rewrites the file with the
+/// live records once more than half of it is garbage.
+///
+/// Records are framed like the write-ahead log, the payload is either
+/// `put <key> <value> <deadline>` or `delete <key>`.
+pub struct DiskStorage {
+    path: PathBuf,
+    file: File,
+    index: BTreeMap<String, Location>,
+    len: u64,
+    garbage: u64,
+}
+
+impl DiskStorage {
+    /// open (or create) the data file and build the index, a torn tail is truncated
+    pub fn open(path: &Path) -> io::Result<DiskStorage> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        let mut storage = DiskStorage { path: path.to_path_buf(), file, index: BTreeMap::new(), len: 0, garbage: 0 };
+        let (frames, valid_len) = wal::read_frames(&content);
+        for (start, payload) in frames {
+            if storage.index_record(start as u64, payload).is_none() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("damaged record at offset {} in {}", start, path.display())));
+            }
+        }
+        if valid_len < content.len() {
+            warn!("Data file {} has a corrupted tail, truncating {} bytes", path.display(), content.len() - valid_len);
+            storage.file.set_len(valid_len as u64)?;
+            storage.file.sync_all()?;
+        }
+        storage.len = valid_len as u64;
+        storage.file.seek(SeekFrom::End(0))?;
+        info!("Indexed {} keys from {}", storage.index.len(), path.display());
+        Ok(storage)
+    }
+
+    /// update the index for a record whose payload starts at `start`
+    fn index_record(&mut self, start: u64, payload: &[u8]) -> Option<()> {
+        let record_len = FRAME_HEADER_LEN + payload.len() as u64;
+        let (&op, mut rest) = payload.split_first()?;
+        let key = wal::take_string(&mut rest)?;
+        let replaced = match op {
+            OP_PUT => {
+                let len = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?);
+                // the value follows the op, the key and the value length
+                let offset = start + 1 + 4 + key.len() as u64 + 4;
+                rest = rest.get(4 + len as usize..)?;
+                let expires_at = wal::take_deadline(&mut rest)?;
+                self.index.insert(key, Location { offset, len, expires_at, record_len })
+            },
+            OP_DELETE => {
+                // the tombstone itself is garbage as soon as the older records are compacted away
+                self.garbage += record_len;
+                self.index.remove(&key)
+            },
+            _ => return None,
+        };
+        if !rest.is_empty() {
+            return None;
+        }
+        if let Some(old) = replaced {
+            self.garbage += old.record_len;
+        }
+        Some(())
+    }
+
+    /// append one record and sync it, then index it
+    fn append(&mut self, payload: Vec<u8>) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + FRAME_HEADER_LEN as usize);
+        wal::put_frame(&mut frame, &payload);
+        if let Err(e) = self.file.write_all(&frame).and_then(|()| self.file.sync_data()) {
+            // cut off a partial record, later appends would be lost behind it on the next open
+            let _ = self.file.set_len(self.len);
+            let _ = self.file.seek(SeekFrom::End(0));
+            return Err(e);
+        }
+        let start = self.len + FRAME_HEADER_LEN;
+        self.len += frame.len() as u64;
+        self.index_record(start, &payload).expect("a record just encoded can be indexed");
+        Ok(())
+    }
+
+    fn read_value(&self, location: &Location) -> io::Result<Vec<u8>> {
+        let mut value = vec![0u8; location.len as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(location.offset))?;
+        file.read_exact(&mut value)?;
+        // appends expect the position at the end
+        file.seek(SeekFrom::End(0))?;
+        Ok(value)
+    }
+}
+
+fn put_payload(key: &str, entry: &Entry) -> Vec<u8> {
+    let mut payload = vec![OP_PUT];
+    wal::put_bytes(&mut payload, key.as_bytes());
+    wal::put_bytes(&mut payload, &entry.value);
+    wal::put_deadline(&mut payload, entry.expires_at);
+    payload
+}
+
+impl Storage for DiskStorage {
+    fn get(&self, key: &str) -> io::Result<Option<Entry>> {
+        match self.index.get(key) {
+            Some(location) => Ok(Some(Entry { value: self.read_value(location)?, expires_at: location.expires_at })),
+            None => Ok(None),
+        }
+    }
+
+    fn set(&mut self, key: String, entry: Entry) -> io::Result<()> {
+        self.append(put_payload(&key, &entry))
+    }
+
+    fn delete(&mut self, key: &str) -> io::Result<bool> {
+        if !self.index.contains_key(key) {
+            return Ok(false);
+        }
+        let mut payload = vec![OP_DELETE];
+        wal::put_bytes(&mut payload, key.as_bytes());
+        self.append(payload)?;
+        Ok(true)
+    }
+
+    fn scan(&self, from: Bound<&str>, to: Bound<&str>) -> io::Result<Vec<(String, Entry)>> {
+        if !storage::is_valid_range(from, to) {
+            return Ok(Vec::new());
+        }
+        let mut entries = Vec::new();
+        for (key, location) in self.index.range::<str, _>((from, to)) {
+            entries.push((key.clone(), Entry { value: self.read_value(location)?, expires_at: location.expires_at }));
+        }
+        Ok(entries)
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// only the index is in memory, values stay on disk
+    fn memory(&self) -> usize {
+        self.index.keys().map(|key| size_of::<(String, Location)>() + key.len()).sum()
+    }
+
+    fn deadlines(&self) -> io::Result<Vec<(String, u64)>> {
+        Ok(self.index.iter()
+            .filter_map(|(key, location)| location.expires_at.map(|deadline| (key.clone(), deadline)))
+            .collect())
+    }
+
+    fn is_durable(&self) -> bool {
+        true
+    }
+
+    /// rewrite the live records into a new file and swap it in atomically
+    fn compact(&mut self) -> io::Result<()> {
+        if self.garbage < COMPACT_MIN_GARBAGE || self.garbage * 2 < self.len {
+            return Ok(());
+        }
+        let mut temp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = self.path.with_file_name(temp_name);
+        {
+            // one record at a time, the values stay on disk until they are copied
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            let mut frame = Vec::new();
+            for (key, location) in &self.index {
+                let entry = Entry { value: self.read_value(location)?, expires_at: location.expires_at };
+                frame.clear();
+                wal::put_frame(&mut frame, &put_payload(key, &entry));
+                writer.write_all(&frame)?;
+            }
+            writer.into_inner()?.sync_all()?;
+        }
+        snapshot::rename_durably(&temp_path, &self.path)?;
+
+        let before = self.len;
+        *self = DiskStorage::open(&self.path)?;
+        info!("Compacted {} from {} to {} bytes", self.path.display(), before, self.len);
+        Ok(())
+    }
+}
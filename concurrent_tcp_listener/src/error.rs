@@ -0,0 +1,90 @@
+use std::{fmt, io};
+use std::time::Duration;
+use crate::ErrorKind;
+
+/// Why serving a connection or a timer had to stop.
+#[derive(Debug)]
+pub enum ServiceError {
+    /// reading from or writing to the socket failed, e.g. the peer reset the connection
+    Io(io::Error),
+    /// the client violated the wire protocol so badly that the stream cannot be resynchronized
+    Protocol(String),
+    /// the service task no longer accepts requests, it is shutting down or has failed
+    Unavailable,
+    /// the client sent nothing for longer than the idle timeout
+    Idle(Duration),
+    /// the request is longer than the limit in bytes, the rest of it is not read
+    TooLong(usize),
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::Io(e) => write!(f, "i/o error: {}", e),
+            ServiceError::Protocol(message) => write!(f, "protocol error: {}", message),
+            ServiceError::Unavailable => write!(f, "service is not available"),
+            ServiceError::Idle(timeout) => write!(f, "idle for more than {:?}", timeout),
+            ServiceError::TooLong(max) => write!(f, "request longer than {} bytes", max),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServiceError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ServiceError {
+    fn from(e: io::Error) -> Self {
+        ServiceError::Io(e)
+    }
+}
+
+/// Why a request of the `client` failed.
+#[derive(Debug)]
+pub enum ClientError {
+    /// connecting, reading or writing failed
+    Io(io::Error),
+    /// the server closed the connection before replying
+    Closed,
+    /// the reply could not be understood
+    Protocol(String),
+    /// the server answered with an error
+    Server(ErrorKind, String),
+    /// the request cannot be sent, e.g. an empty command or an expiry of 0 seconds
+    Invalid(String),
+    /// the connection of a `Session` failed earlier, and the transaction or login with it
+    SessionLost,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "i/o error: {}", e),
+            ClientError::Closed => write!(f, "connection closed by the server"),
+            ClientError::Protocol(message) => write!(f, "protocol error: {}", message),
+            ClientError::Server(kind, message) => write!(f, "{} {}", kind.code(), message),
+            ClientError::Invalid(message) => write!(f, "invalid request: {}", message),
+            ClientError::SessionLost => write!(f, "the connection of the session was lost"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
@@ -0,0 +1,114 @@
+//! Quoting of keys and values on a line of the text protocol.
+//!
+//! Words are separated by whitespace. A word starting with `"` runs until the next unescaped
+//! `"` and may contain whitespace and these escapes: `\"`, `\\`, `\n`, `\r`, `\t`, `\0` and
+//! `\xHH` for any byte. Words without quotes are taken as they are, backslashes included.
+//!
+//! ```text
+//! set greeting "hello\r\n  world"
+//! set "key with spaces" "\x00\xff"
+//! ```
+
+/// split a request line into its words, unquoting the quoted ones
+pub fn split(line: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    split_words(line, usize::MAX).map(|(words, _)| words)
+}
+
+/// the first `count` words of a line and the rest of it, starting after the whitespace that follows them
+pub fn split_words(line: &[u8], count: usize) -> Result<(Vec<Vec<u8>>, &[u8]), String> {
+    let mut words = Vec::new();
+    let mut rest = skip_whitespace(line);
+    while words.len() < count && !rest.is_empty() {
+        if rest[0] == b'"' {
+            let (word, len) = unquote(&rest[1..])?;
+            rest = &rest[1 + len..];
+            if rest.first().is_some_and(|b| !b.is_ascii_whitespace()) {
+                return Err("a closing quote must be followed by whitespace".to_string());
+            }
+            words.push(word);
+        } else {
+            let end = rest.iter().position(u8::is_ascii_whitespace).unwrap_or(rest.len());
+            words.push(rest[..end].to_vec());
+            rest = &rest[end..];
+        }
+        rest = skip_whitespace(rest);
+    }
+    Ok((words, rest))
+}
+
+fn skip_whitespace(line: &[u8]) -> &[u8] {
+    let start = line.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(line.len());
+    &line[start..]
+}
+
+/// the word up to the closing quote and the bytes taken including the quote
+fn unquote(quoted: &[u8]) -> Result<(Vec<u8>, usize), String> {
+    let mut word = Vec::new();
+    let mut i = 0;
+    while let Some(&byte) = quoted.get(i) {
+        i += 1;
+        match byte {
+            b'"' => return Ok((word, i)),
+            b'\\' => {
+                let escape = quoted.get(i).ok_or_else(unterminated)?;
+                i += 1;
+                word.push(match escape {
+                    b'"' | b'\\' => *escape,
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    b't' => b'\t',
+                    b'0' => 0,
+                    b'x' => {
+                        let digits = quoted.get(i..i + 2).and_then(|hex| std::str::from_utf8(hex).ok());
+                        i += 2;
+                        digits.and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                            .ok_or_else(|| "\\x needs two hex digits".to_string())?
+                    },
+                    other => return Err(format!("unknown escape \\{}", other.escape_ascii())),
+                });
+            },
+            _ => word.push(byte),
+        }
+    }
+    Err(unterminated())
+}
+
+fn unterminated() -> String {
+    "missing closing quote".to_string()
+}
+
+/// the word as `split` reads it back, quoted only if it has to be
+pub fn quote(word: &[u8]) -> String {
+    match std::str::from_utf8(word) {
+        Ok(text) if !text.is_empty() && !text.starts_with('"')
+            && !text.contains(|c: char| c.is_whitespace() || c.is_control()) => text.to_string(),
+        _ => quoted(word),
+    }
+}
+
+/// the word in quotes, with escapes for quotes, backslashes, control characters and invalid UTF-8
+pub fn quoted(word: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for chunk in word.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                c if c.is_control() => push_hex(&mut quoted, c.encode_utf8(&mut [0; 4]).as_bytes()),
+                c => quoted.push(c),
+            }
+        }
+        push_hex(&mut quoted, chunk.invalid());
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn push_hex(quoted: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        quoted.push_str(&format!("\\x{:02x}", byte));
+    }
+}
@@ -0,0 +1,66 @@
+/// Match `text` against a glob pattern as used by `keys`:
+/// `*` matches any sequence, `?` any single character,
+/// `[abc]`/`[a-z]`/`[^a]` a character class and `\x` the literal `x`.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // the pattern position after the last `*` and the text position it resumes from,
+    // only that `*` is ever widened so matching takes at most pattern * text steps
+    let mut star = None;
+    while t < text.len() {
+        if pattern.get(p) == Some(&'*') {
+            p += 1;
+            star = Some((p, t));
+        } else if let Some(next) = match_one(&pattern, p, text[t]) {
+            p = next;
+            t += 1;
+        } else if let Some((after_star, resume)) = star {
+            p = after_star;
+            t = resume + 1;
+            star = Some((after_star, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// the position after the single character pattern at `pattern[p]` if it matches `c`
+fn match_one(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    match *pattern.get(p)? {
+        '?' => Some(p + 1),
+        '[' => match class_end(&pattern[p..]) {
+            Some(end) => class_matches(&pattern[p + 1..p + end], c).then_some(p + end + 1),
+            None => (c == '[').then_some(p + 1),
+        },
+        '\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        literal => (literal == c).then_some(p + 1),
+    }
+}
+
+/// index of the `]` closing the class opened at `pattern[0]`
+fn class_end(pattern: &[char]) -> Option<usize> {
+    // a `]` right after the opening (or negation) is a literal member
+    let first_member = if pattern.get(1) == Some(&'^') { 2 } else { 1 };
+    (first_member + 1..pattern.len()).find(|&i| pattern[i] == ']')
+}
+
+fn class_matches(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
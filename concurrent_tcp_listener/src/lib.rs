@@ -0,0 +1,60 @@
+//! A key-value store served over a line based text protocol and RESP, see `text` and `resp`.
+//!
+//! `Server` runs it inside another program. Both protocols are translated into a `Request`
+//! with `parse_command` and answered with a `Response` by the storage task
+//! `handle_single_request`, which can also be driven directly over a channel.
+
+pub mod auth;
+pub mod client;
+pub mod config;
+mod disk;
+pub mod error;
+pub mod escape;
+mod glob;
+mod limits;
+mod listener;
+pub mod log;
+pub mod metrics;
+pub mod pubsub;
+pub mod replication;
+mod request;
+mod resp;
+mod server;
+pub mod shard;
+mod shutdown;
+mod snapshot;
+pub mod storage;
+pub mod store;
+mod task;
+mod text;
+mod tls;
+mod transaction;
+pub mod wal;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use error::ServiceError;
+use pubsub::ClientId;
+use shard::Service;
+
+pub use request::{parse_command, ErrorKind, Request, RequestTransport, Response};
+pub use server::{Builder, Server};
+pub use task::handle_single_request;
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// a fresh id for a new connection
+fn next_client_id() -> ClientId {
+    NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// a fresh id for the span of a client request
+fn next_request_id() -> u64 {
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// fails only if the service task is gone
+async fn send_request_and_wait_for_response(r:Request, tx:&Service) -> Result<Response, ServiceError> {
+    tx.request(r).await
+}
@@ -0,0 +1,149 @@
+//! Protection against clients taking more than their share of the server:
+//! a cap on open connections, an idle timeout, a maximum request length and a rate limit
+//! per connection. How often each of them applied is reported by `stats`.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{self, Duration, Instant};
+use crate::config::Config;
+
+/// the limits of every client connection, shared by all listeners
+#[derive(Clone)]
+pub struct Limits {
+    pub max_connections: usize,
+    // None keeps idle connections open
+    pub idle_timeout: Option<Duration>,
+    // in bytes including the line break, for RESP the whole command
+    pub max_line_length: usize,
+    // requests per second and connection, None for no limit
+    pub rate_limit: Option<u32>,
+    pub stats: Arc<Stats>,
+    // one permit per open client connection
+    slots: Arc<Semaphore>,
+}
+
+impl Limits {
+    pub fn new(config: &Config) -> Limits {
+        Limits {
+            max_connections: config.max_connections,
+            idle_timeout: config.idle_timeout,
+            max_line_length: config.max_line_length,
+            rate_limit: config.rate_limit,
+            stats: Arc::new(Stats::default()),
+            slots: Arc::new(Semaphore::new(config.max_connections)),
+        }
+    }
+
+    /// a place for a new connection, None if all are taken
+    pub fn admit(&self) -> Option<Slot> {
+        match self.slots.clone().try_acquire_owned() {
+            Ok(permit) => {
+                Stats::count(&self.stats.accepted);
+                self.stats.connected.fetch_add(1, Ordering::Relaxed);
+                Some(Slot { _permit: permit, stats: self.stats.clone() })
+            },
+            Err(_) => {
+                Stats::count(&self.stats.rejected);
+                None
+            },
+        }
+    }
+
+    /// when a connection active now becomes idle
+    pub fn idle_deadline(&self) -> Option<Instant> {
+        self.idle_timeout.map(|timeout| Instant::now() + timeout)
+    }
+
+    pub fn rate_limiter(&self) -> RateLimiter {
+        RateLimiter::new(self.rate_limit)
+    }
+
+    /// the counters and limits as `name:value` lines
+    pub fn report(&self) -> String {
+        let stats = &self.stats;
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        [
+            ("connected_clients", get(&stats.connected)),
+            ("total_connections", get(&stats.accepted)),
+            ("rejected_connections", get(&stats.rejected)),
+            ("idle_timeouts", get(&stats.idle_timeouts)),
+            ("oversized_requests", get(&stats.oversized)),
+            ("rate_limited_requests", get(&stats.rate_limited)),
+            ("max_connections", self.max_connections as u64),
+            ("idle_timeout_seconds", self.idle_timeout.map_or(0, |timeout| timeout.as_secs())),
+            ("max_line_length", self.max_line_length as u64),
+            ("rate_limit", self.rate_limit.map_or(0, u64::from)),
+        ]
+            .iter()
+            .map(|(name, value)| format!("{}:{}", name, value))
+            .collect::<Vec<String>>()
+            .join("\r\n")
+    }
+}
+
+/// how often the limits applied since the start
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub connected: AtomicU64,
+    pub accepted: AtomicU64,
+    pub rejected: AtomicU64,
+    pub idle_timeouts: AtomicU64,
+    pub oversized: AtomicU64,
+    pub rate_limited: AtomicU64,
+}
+
+impl Stats {
+    pub fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// held by a connection while it is open
+pub struct Slot {
+    _permit: OwnedSemaphorePermit,
+    stats: Arc<Stats>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.stats.connected.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A token bucket allowing bursts of up to one second worth of requests.
+pub struct RateLimiter {
+    rate: Option<f64>,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    fn new(rate: Option<u32>) -> RateLimiter {
+        let rate = rate.map(f64::from);
+        RateLimiter { rate, tokens: rate.unwrap_or_default(), refilled: Instant::now() }
+    }
+
+    /// take a token for the next request, false if there is none left
+    pub fn allow(&mut self) -> bool {
+        let Some(rate) = self.rate else {
+            return true;
+        };
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.refilled).as_secs_f64() * rate).min(rate);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// resolves at the deadline, never without one
+pub async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
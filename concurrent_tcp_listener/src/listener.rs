@@ -0,0 +1,75 @@
+use std::io;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tracing::info;
+use crate::config::Address;
+use crate::tls::Connection;
+
+/// A bound address accepting connections, over TCP or a Unix domain socket.
+/// The socket file of a Unix listener is removed when it is dropped.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, std::path::PathBuf),
+}
+
+impl Listener {
+    pub async fn bind(address: &Address) -> io::Result<Listener> {
+        match address {
+            Address::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address).await?)),
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+                if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                    // only a socket left behind by a previous run that did not shut down cleanly refuses connections
+                    match std::os::unix::net::UnixStream::connect(path) {
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                            info!("Removing stale socket {}", path.display());
+                            std::fs::remove_file(path)?;
+                        },
+                        _ => return Err(io::Error::new(io::ErrorKind::AddrInUse,
+                                                       format!("{} is in use by another server", path.display()))),
+                    }
+                }
+                Ok(Listener::Unix(UnixListener::bind(path)?, path.clone()))
+            },
+            #[cfg(not(unix))]
+            Address::Unix(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "Unix domain sockets need a unix system")),
+        }
+    }
+
+    /// the address actually bound, with the port the system picked for port 0
+    pub fn local_address(&self) -> io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => Ok(Address::Tcp(listener.local_addr()?)),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(Address::Unix(path.clone())),
+        }
+    }
+
+    /// the next client and a name for it in log messages
+    pub async fn accept(&self) -> io::Result<(Connection, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (socket, peer) = listener.accept().await?;
+                Ok((Box::new(socket), peer.to_string()))
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener, path) => {
+                // clients of a Unix socket are usually unnamed
+                let (socket, _) = listener.accept().await?;
+                Ok((Box::new(socket), format!("unix:{}", path.display())))
+            },
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
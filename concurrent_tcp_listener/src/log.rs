@@ -0,0 +1,37 @@
+//! Log output through `tracing`, set up at startup from `log-level` and `log-format`.
+//! Every connection runs in a `connection` span with the peer address and every request
+//! in a `request` span with its id, so messages show which client they are about.
+
+use std::io::{self, IsTerminal};
+use tracing_subscriber::EnvFilter;
+
+/// how events are written to stdout
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Human,  // one line per event, prefixed with its spans
+    Json,   // one object per event, with the fields of its spans
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "human" => Some(Format::Human),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// a filter in the syntax of `RUST_LOG`, e.g. `debug` or `info,concurrent_tcp_listener::resp=debug`
+pub fn filter(directives: &str) -> Result<EnvFilter, String> {
+    EnvFilter::try_new(directives).map_err(|e| format!("{:?} is no log filter: {}", directives, e))
+}
+
+pub fn init(filter: EnvFilter, format: Format) {
+    // no colour codes in files and pipes
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_ansi(io::stdout().is_terminal());
+    match format {
+        Format::Human => builder.init(),
+        Format::Json => builder.json().with_current_span(true).with_span_list(true).init(),
+    }
+}
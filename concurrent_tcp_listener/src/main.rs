@@ -1,151 +1,69 @@
-use std::collections::HashMap;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufStream};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{oneshot,mpsc};
-use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::{signal, time};
-
-#[derive(Debug)]
-enum Request {
-    Set(String, String),    // set key := value -> OK
-    Get(String),            // get key -> value
-
-    // management requests, used internally
-    Persist(),              // persist hashmap to disk -> OK
-    Close(),                // Close channel and terminate processing -> OK
-}
-
-#[derive(Debug)]
-enum Response {
-    Ok(),
-    NotFound(String),
-    Result(String),
+use std::io::{self, BufRead};
+use clap::{CommandFactory, FromArgMatches};
+use clap::error::ErrorKind;
+use concurrent_tcp_listener::config::{Args, Config};
+use concurrent_tcp_listener::{auth, log, Server};
+use tokio::signal;
+use tracing::{error, info};
+
+/// report on stderr in the format of clap's own errors and exit with status 2
+fn exit_with_error(kind: ErrorKind, message: &str) -> ! {
+    Args::command().error(kind, message).exit()
 }
 
-/// data transferred over the channel to our service
-type RequestTransport = (Request, oneshot::Sender<Response>);
-
-async fn send_request_and_wait_for_response(r:Request, tx:&Sender<RequestTransport>) -> Response {
-    let (response_tx, response_rx) = oneshot::channel::<Response>();
-    tx.send((r, response_tx)).await.unwrap();
-    response_rx.await.unwrap()
+/// `--hash-password`: print the hash of the password read from stdin for the users file
+fn print_password_hash() {
+    let mut password = String::new();
+    if let Err(e) = io::stdin().lock().read_line(&mut password) {
+        exit_with_error(ErrorKind::Io, &format!("Unable to read the password: {}", e));
+    }
+    println!("{}", auth::hash_password(password.trim_end_matches(['\r', '\n'])));
 }
 
 #[tokio::main]
 async fn main() {
-    println!("Listening on port 8000");
-    let listener = TcpListener::bind("127.0.0.1:8000").await.unwrap();
-
-    // build a channel to a handler processing each request in turn in order to prevent concurrency issues
-    let (tx, rx) = mpsc::channel::<RequestTransport>(100);
-    tokio::spawn(async move {
-        handle_single_request(rx).await;
-        println!("Handle Single Request Task isDone");
+    // --help, --version and unknown arguments end here
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if args.hash_password {
+        print_password_hash();
+        return;
+    }
+    let config = Config::load(&matches).unwrap_or_else(|message| exit_with_error(ErrorKind::ValueValidation, &message));
+    // checked while loading the configuration
+    let filter = log::filter(&config.log_level).expect("valid log filter");
+    log::init(filter, config.log_format);
+    let server = match Server::builder().config(config).start().await {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        },
+    };
+
+    // graceful shutdown: stop accepting, let connections finish their current request,
+    // then close the service which writes the final snapshot
+    wait_for_termination_signal().await;
+    if let Err(e) = server.shutdown().await {
+        error!("{}", e);
         std::process::exit(1);
-    });
-
-    // establish a Ctrl-C handler for a graceful shutdown
-    let tx_clone = tx.clone();
-    tokio::spawn(async move {
-        signal::ctrl_c().await.unwrap();
-
-        println!("CTRL-C received, Closing Service");
-        send_request_and_wait_for_response(Request::Close(), &tx_clone).await;
-    });
-
-
-    // a timer triggering a Persist request every 20s
-    let tx_clone = tx.clone();
-    tokio::spawn(async move {
-        loop {
-            time::sleep(time::Duration::from_secs(20)).await;
-            send_request_and_wait_for_response(Request::Persist(), &tx_clone).await;
-        };
-    });
-
-    // accept loop
-    loop {
-        let (socket, _) = listener.accept().await.unwrap();
-        // A new task is spawned for each inbound socket.
-        // The socket is moved to the new task and processed there.
-        let tx_clone = tx.clone();
-        tokio::spawn(async move {
-            handle_client_connection(socket, &tx_clone).await;
-        });
     }
+    info!("Shutdown complete");
 }
 
-async fn handle_client_connection(socket: TcpStream, tx: &Sender<RequestTransport>) {
-    println!("Connection from {}", socket.peer_addr().unwrap());
-    let mut stream = BufStream::new(socket);
-
-    loop {
-        let line = &mut String::new();
-        let nr_bytes = stream.read_line(line).await.unwrap();
-        if nr_bytes == 0 {
-            stream.write(b"bye\r\n").await.unwrap();
-            stream.flush().await.unwrap();
-            break;
-        } else {
-            stream.consume(nr_bytes);
-            stream.write(format!("consumed {} bytes\r\n", nr_bytes).as_bytes()).await.unwrap();
-            stream.flush().await.unwrap();
-
-            let parts = line.split_whitespace().collect::<Vec<&str>>();
-
-            let maybe_request: Result<Request, String> = match parts[..] {
-                ["set", key, value] =>
-                    Ok(Request::Set(parts[1].to_string(), parts[2..].join(" ").to_string())),
-                ["get", key] =>
-                    Ok(Request::Get(parts[1].to_string())),
-                _ => Err(format!("not a valid request: {:?}", parts))
-            };
-
-            match maybe_request {
-                Ok(request) => {
-                    let response = send_request_and_wait_for_response(request, tx).await;
-                    stream.write(format!("response: {:?}\r\n", response).as_bytes()).await.unwrap();
-                    stream.flush().await.unwrap();
-                },
-                Err(message) => {
-                    stream.write(format!("bad request - {}\r\n", message).as_bytes()).await.unwrap();
-                    stream.flush().await.unwrap();
-                }
-            }
+/// resolves on Ctrl-C, or on SIGTERM as sent by systemd
+async fn wait_for_termination_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = signal::ctrl_c() => info!("CTRL-C received"),
+            _ = terminate.recv() => info!("SIGTERM received"),
         }
     }
-}
-
-async fn handle_single_request(mut rx: Receiver<RequestTransport>) {
-    let mut storage = HashMap::new();
-    // TODO: initialize or load hashmap from disk
-    while let Some((command, response_channel)) = rx.recv().await {
-        println!("Service received: {:?}", command);
-        let response = match command {
-            Request::Set(key, value) => {
-                storage.insert(key, value);
-                Response::Ok()
-            },
-            Request::Get(key) => {
-                storage.get(&key)
-                    .map(|v| Response::Result(v.clone()))
-                    .unwrap_or(Response::NotFound(key))
-            },
-
-            // Maintenance requests
-            Request::Close() => {
-                rx.close();
-                Response::Ok()
-            },
-            Request::Persist() => {
-                // TODO: persist to disk if not changed
-                println!("Persist request");
-                Response::Ok()
-            }
-        };
-        response_channel.send(response).unwrap();
+    #[cfg(not(unix))]
+    {
+        signal::ctrl_c().await.unwrap();
+        info!("CTRL-C received");
     }
-
-    println!("Service is finished. TODO: shut down");
-    // TODO: finally persist hashmap to disk
 }
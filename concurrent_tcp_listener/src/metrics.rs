@@ -0,0 +1,234 @@
+//! What the server is doing: `info` on the client ports and, with `metrics-listen` set,
+//! the same numbers in the Prometheus text format at `http://<metrics-listen>/metrics`,
+//! together with a histogram of the time requests take in the service.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{self, Duration, Instant};
+use tracing::{info, warn};
+use crate::error::ServiceError;
+use crate::limits::Limits;
+use crate::server::ACCEPT_ERROR_BACKOFF;
+use crate::shard::Service;
+use crate::shutdown::Shutdown;
+
+/// upper bounds of the latency buckets in seconds, +Inf is added when rendering
+const BUCKETS: [f64; 12] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0];
+
+/// a scraper sending more than this before the end of its request head is not served
+const MAX_HEAD_LEN: u64 = 8 * 1024;
+
+/// how long a scraper may take to send its request
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests handled by the service since the start, by command.
+pub struct Metrics {
+    started: Instant,
+    commands: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics { started: Instant::now(), commands: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// count a request of the given command that took `elapsed` in the service
+    pub fn record(&self, command: &'static str, elapsed: Duration) {
+        // a panic while holding the lock leaves the counters usable
+        let mut commands = self.commands.lock().unwrap_or_else(|e| e.into_inner());
+        commands.entry(command).or_default().observe(elapsed);
+    }
+
+    fn commands(&self) -> BTreeMap<&'static str, Histogram> {
+        self.commands.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// how many requests took how long
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    count: u64,
+    sum: Duration,
+    // requests up to each bound of BUCKETS, not cumulative
+    buckets: [u64; BUCKETS.len()],
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        self.count += 1;
+        self.sum += elapsed;
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[bucket] += 1;
+        }
+    }
+}
+
+/// what the storage holds, summed over all shards
+#[derive(Debug, Clone, Default)]
+pub struct Usage {
+    pub keys: usize,
+    // estimated bytes of keys and values in memory
+    pub memory: usize,
+    pub last_persist: Option<Persisted>,
+}
+
+impl Usage {
+    /// combine the usage of two shards, the latest snapshot of either counts
+    pub fn merge(self, other: Usage) -> Usage {
+        let last_persist = match (self.last_persist, other.last_persist) {
+            (Some(a), Some(b)) => Some(if a.at >= b.at { a } else { b }),
+            (a, b) => a.or(b),
+        };
+        Usage { keys: self.keys + other.keys, memory: self.memory + other.memory, last_persist }
+    }
+}
+
+/// a snapshot that was written
+#[derive(Debug, Clone, Copy)]
+pub struct Persisted {
+    // milliseconds since the unix epoch
+    pub at: u64,
+    pub duration: Duration,
+}
+
+/// the reply to `info`, `name:value` lines in sections like redis
+pub async fn info(tx: &Service, limits: &Limits) -> Result<String, ServiceError> {
+    let usage = tx.usage().await?;
+    let commands = tx.metrics().commands();
+    let mut text = String::new();
+    let _ = write!(text, "# Server\r\nuptime_seconds:{}\r\n", tx.metrics().uptime().as_secs());
+    let _ = write!(text, "# Clients\r\n{}\r\n", limits.report());
+    let _ = write!(text, "# Keyspace\r\nkeys:{}\r\nmemory_estimate_bytes:{}\r\n", usage.keys, usage.memory);
+    let (at, duration) = usage.last_persist.map_or((0, 0), |p| (p.at / 1000, p.duration.as_millis()));
+    let _ = write!(text, "# Persistence\r\nlast_persist_time:{}\r\nlast_persist_duration_ms:{}\r\n", at, duration);
+    let total = commands.values().map(|histogram| histogram.count).sum::<u64>();
+    let _ = write!(text, "# Commands\r\ncommands_processed:{}", total);
+    for (command, histogram) in &commands {
+        let _ = write!(text, "\r\ncmd_{}:{}", command, histogram.count);
+    }
+    Ok(text)
+}
+
+/// the counters in the Prometheus text exposition format
+async fn prometheus(tx: &Service, limits: &Limits) -> Result<String, ServiceError> {
+    let usage = tx.usage().await?;
+    let stats = &limits.stats;
+    let get = |counter: &std::sync::atomic::AtomicU64| counter.load(Ordering::Relaxed) as f64;
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+        let _ = write!(text, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n");
+    };
+    metric("kv_uptime_seconds", "gauge", "Time since the server started.", tx.metrics().uptime().as_secs_f64());
+    metric("kv_keys", "gauge", "Keys stored, including expired ones not yet removed.", usage.keys as f64);
+    metric("kv_memory_bytes", "gauge", "Estimated memory taken by keys and values.", usage.memory as f64);
+    metric("kv_connected_clients", "gauge", "Open client connections.", get(&stats.connected));
+    metric("kv_connections_total", "counter", "Client connections accepted.", get(&stats.accepted));
+    metric("kv_rejected_connections_total", "counter", "Connections rejected over max-connections.",
+           get(&stats.rejected));
+    metric("kv_idle_timeouts_total", "counter", "Connections closed after idle-timeout.", get(&stats.idle_timeouts));
+    metric("kv_oversized_requests_total", "counter", "Requests longer than max-line-length.", get(&stats.oversized));
+    metric("kv_rate_limited_requests_total", "counter", "Requests refused over rate-limit.", get(&stats.rate_limited));
+    let (at, duration) = usage.last_persist.map_or((0.0, 0.0), |p| (p.at as f64 / 1000.0, p.duration.as_secs_f64()));
+    metric("kv_last_persist_timestamp_seconds", "gauge", "When the last snapshot was written, 0 if never.", at);
+    metric("kv_last_persist_duration_seconds", "gauge", "How long writing the last snapshot took.", duration);
+
+    let commands = tx.metrics().commands();
+    text.push_str("# HELP kv_commands_total Requests handled by the service.\n# TYPE kv_commands_total counter\n");
+    for (command, histogram) in &commands {
+        let _ = writeln!(text, "kv_commands_total{{command=\"{}\"}} {}", command, histogram.count);
+    }
+    text.push_str("# HELP kv_request_duration_seconds Time requests took in the service.\n");
+    text.push_str("# TYPE kv_request_duration_seconds histogram\n");
+    for (command, histogram) in &commands {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(text, "kv_request_duration_seconds_bucket{{command=\"{}\",le=\"{}\"}} {}",
+                             command, bound, cumulative);
+        }
+        let _ = writeln!(text, "kv_request_duration_seconds_bucket{{command=\"{}\",le=\"+Inf\"}} {}",
+                         command, histogram.count);
+        let _ = writeln!(text, "kv_request_duration_seconds_sum{{command=\"{}\"}} {}",
+                         command, histogram.sum.as_secs_f64());
+        let _ = writeln!(text, "kv_request_duration_seconds_count{{command=\"{}\"}} {}", command, histogram.count);
+    }
+    Ok(text)
+}
+
+/// answer scrapers on `listener` until the shutdown starts
+pub async fn serve(listener: TcpListener, tx: Service, limits: Limits, mut shutdown: Shutdown) {
+    loop {
+        let (socket, peer) = tokio::select! {
+            result = listener.accept() => match result {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Accepting a metrics connection failed: {}", e);
+                    time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                },
+            },
+            _ = shutdown.wait() => break,
+        };
+        let (tx, limits) = (tx.clone(), limits.clone());
+        tokio::spawn(async move {
+            if let Err(e) = respond(socket, &tx, &limits).await {
+                info!("Metrics request from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// a single HTTP/1.1 exchange, the connection is closed after the response
+async fn respond(socket: TcpStream, tx: &Service, limits: &Limits) -> io::Result<()> {
+    let mut reader = BufReader::new(socket);
+    let head = time::timeout(HEAD_TIMEOUT, read_head(&mut reader)).await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request head timed out"))??;
+    let parts = head.split_whitespace().collect::<Vec<&str>>();
+    let (status, body) = match parts[..] {
+        ["GET", "/metrics", _] => match prometheus(tx, limits).await {
+            Ok(body) => ("200 OK", body),
+            Err(e) => ("503 Service Unavailable", format!("{}\n", e)),
+        },
+        [_, "/metrics", _] => ("405 Method Not Allowed", "only GET is supported\n".to_string()),
+        _ => ("404 Not Found", "see /metrics\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body);
+    let socket = reader.get_mut();
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+/// the request line, the headers up to the empty line are skipped
+async fn read_head(reader: &mut BufReader<TcpStream>) -> io::Result<String> {
+    let mut limited = reader.take(MAX_HEAD_LEN);
+    let mut request_line = String::new();
+    limited.read_line(&mut request_line).await?;
+    loop {
+        let mut header = String::new();
+        if limited.read_line(&mut header).await? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+    }
+    if !request_line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "incomplete request line"));
+    }
+    Ok(request_line)
+}
@@ -0,0 +1,177 @@
+use std::collections::{HashMap, HashSet};
+use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::warn;
+use crate::glob;
+use crate::store::KeyEvent;
+
+/// identifies a client connection for the lifetime of the server
+pub type ClientId = u64;
+
+/// how many pushes may queue up for a connection before further ones are dropped
+pub const OUTBOX_CAPACITY: usize = 128;
+
+/// a message sent to a connection without it asking for it
+#[derive(Debug, Clone)]
+pub enum Push {
+    Message { channel: String, payload: String },
+    PatternMessage { pattern: String, channel: String, payload: String },
+    Event(KeyEvent),
+    /// the connection did not keep up and this many pushes were dropped since the last delivery
+    Lagged(u64),
+    /// the server is shutting down and closes the connection
+    Shutdown,
+}
+
+/// the queue of pushes to a single connection
+pub type Outbox = Sender<Push>;
+
+pub fn outbox() -> (Outbox, mpsc::Receiver<Push>) {
+    mpsc::channel(OUTBOX_CAPACITY)
+}
+
+/// what a subscription listens to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Channel,    // messages published to exactly this channel
+    Pattern,    // messages published to channels matching a glob pattern
+    Watch,      // changes of a key, or of all keys with a prefix if it ends with `*`
+}
+
+struct Subscriber {
+    outbox: Outbox,
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+    watches: HashSet<String>,
+    // pushes lost to a full outbox, reported before the next delivery
+    dropped: u64,
+}
+
+impl Subscriber {
+    fn names(&mut self, kind: Kind) -> &mut HashSet<String> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::Watch => &mut self.watches,
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len() + self.watches.len()
+    }
+
+    /// queue a push without waiting, returns true if it was queued
+    fn deliver(&mut self, client: ClientId, push: Push) -> bool {
+        if self.dropped > 0 {
+            match self.outbox.try_send(Push::Lagged(self.dropped)) {
+                Ok(()) => self.dropped = 0,
+                Err(_) => {
+                    self.dropped += 1;
+                    return false;
+                },
+            }
+        }
+        match self.outbox.try_send(push) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                if self.dropped == 0 {
+                    warn!("Outbox of client {} is full, dropping pushes", client);
+                }
+                self.dropped += 1;
+                false
+            },
+            // the connection is gone, its disconnect request is on the way
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+/// Channel, pattern and key subscriptions of all connections, owned by the service task.
+#[derive(Default)]
+pub struct PubSub {
+    subscribers: HashMap<ClientId, Subscriber>,
+}
+
+impl PubSub {
+    /// subscribe to channels, patterns or keys, returns the number of subscriptions of the client
+    pub fn subscribe(&mut self, client: ClientId, outbox: Outbox, names: Vec<String>, kind: Kind) -> usize {
+        let subscriber = self.subscribers.entry(client).or_insert_with(|| Subscriber {
+            outbox,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            watches: HashSet::new(),
+            dropped: 0,
+        });
+        subscriber.names(kind).extend(names);
+        subscriber.count()
+    }
+
+    /// unsubscribe from the given names, from all of this kind if none are given.
+    /// Returns the number of remaining subscriptions of the client.
+    pub fn unsubscribe(&mut self, client: ClientId, names: Vec<String>, kind: Kind) -> usize {
+        let Some(subscriber) = self.subscribers.get_mut(&client) else {
+            return 0;
+        };
+        let set = subscriber.names(kind);
+        if names.is_empty() {
+            set.clear();
+        } else {
+            for name in &names {
+                set.remove(name);
+            }
+        }
+
+        let remaining = subscriber.count();
+        if remaining == 0 {
+            self.subscribers.remove(&client);
+        }
+        remaining
+    }
+
+    /// forget everything about a client, called when its connection ends
+    pub fn disconnect(&mut self, client: ClientId) {
+        self.subscribers.remove(&client);
+    }
+
+    /// deliver a message to every matching subscription, returns the number of deliveries.
+    /// Never waits: a subscriber with a full outbox misses the message.
+    pub fn publish(&mut self, channel: &str, payload: &str) -> usize {
+        let mut delivered = 0;
+        for (&client, subscriber) in self.subscribers.iter_mut() {
+            let mut pushes = Vec::new();
+            if subscriber.channels.contains(channel) {
+                pushes.push(Push::Message { channel: channel.to_string(), payload: payload.to_string() });
+            }
+            for pattern in subscriber.patterns.iter().filter(|p| glob::matches(p, channel)) {
+                pushes.push(Push::PatternMessage {
+                    pattern: pattern.clone(),
+                    channel: channel.to_string(),
+                    payload: payload.to_string(),
+                });
+            }
+
+            for push in pushes {
+                if subscriber.deliver(client, push) {
+                    delivered += 1;
+                }
+            }
+        }
+        delivered
+    }
+
+    /// tell every watcher of the key about a change
+    pub fn notify(&mut self, event: &KeyEvent) {
+        for (&client, subscriber) in self.subscribers.iter_mut() {
+            if subscriber.watches.iter().any(|w| watch_matches(w, &event.key)) {
+                subscriber.deliver(client, Push::Event(event.clone()));
+            }
+        }
+    }
+}
+
+fn watch_matches(watch: &str, key: &str) -> bool {
+    match watch.strip_suffix('*') {
+        Some(prefix) => key.starts_with(prefix),
+        None => watch == key,
+    }
+}
@@ -0,0 +1,300 @@
+//! Leader-follower replication.
+//!
+//! Every mutation a shard writes is appended to the `Hub`, which numbers them with
+//! consecutive offsets and keeps the most recent ones in a backlog. A follower connects
+//! to the text port of the leader and sends one of
+//!
+//! ```text
+//! sync                       start from scratch
+//! sync <id> <offset>         continue where a previous stream from leader <id> ended
+//! ```
+//!
+//! The leader answers `+CONTINUE` if the offset is still in its backlog, otherwise
+//! `+FULLSYNC <id> <offset> <count>` followed by <count> records rebuilding its data.
+//! Afterwards it streams every record from the offset on, framed like the write-ahead log.
+//! Records carry absolute values and deadlines, so applying one twice does no harm.
+//! Expiry is not replicated, a follower expires keys on its own by their deadline.
+//! If the leader runs with users, the follower sends `auth <name> <password>` before
+//! `sync` and the user needs read access to all keys. Over TLS the follower verifies
+//! the leader's certificate, see `tls`.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufStream};
+use tokio::sync::watch;
+use tokio::time::{self, Duration};
+use tokio_rustls::TlsConnector;
+use tracing::{info, warn};
+use crate::error::ServiceError;
+use crate::escape;
+use crate::shard::Service;
+use crate::shutdown::Shutdown;
+use crate::tls::{self, Connection};
+use crate::wal::{self, Record};
+use crate::{Request, Response};
+
+/// records kept for followers continuing after a disconnect
+const BACKLOG_CAPACITY: usize = 100_000;
+
+/// most records sent to or applied on a follower at once
+const MAX_BATCH: usize = 1000;
+
+/// pause before a follower connects again after losing the leader
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// largest record a follower accepts, anything bigger means the stream is out of sync
+const MAX_RECORD_LEN: usize = 1024 * 1024 * 1024;
+
+/// The numbered stream of mutations of this server.
+pub struct Hub {
+    // changes on every start, offsets of an earlier run mean nothing
+    id: String,
+    backlog: Mutex<Backlog>,
+    // the offset the next record gets, followers wait for it to change
+    next_offset: watch::Sender<u64>,
+}
+
+struct Backlog {
+    // offset of the oldest record kept
+    first: u64,
+    records: VecDeque<Record>,
+}
+
+impl Default for Hub {
+    fn default() -> Hub {
+        Hub::new()
+    }
+}
+
+impl Hub {
+    pub fn new() -> Hub {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+        let id = format!("{:x}{:x}", started, std::process::id());
+        let (next_offset, _) = watch::channel(0);
+        Hub { id, backlog: Mutex::new(Backlog { first: 0, records: VecDeque::new() }), next_offset }
+    }
+
+    /// append written records, called by the shards in the order they applied them
+    pub fn push(&self, records: &[Record]) {
+        let mut backlog = self.backlog.lock().unwrap();
+        backlog.records.extend(records.iter().cloned());
+        let excess = backlog.records.len().saturating_sub(BACKLOG_CAPACITY);
+        backlog.records.drain(..excess);
+        backlog.first += excess as u64;
+        self.next_offset.send_replace(backlog.first + backlog.records.len() as u64);
+    }
+
+    fn offset(&self) -> u64 {
+        *self.next_offset.borrow()
+    }
+
+    /// up to `MAX_BATCH` records starting at `offset`, `None` if they left the backlog already
+    fn read_from(&self, offset: u64) -> Option<Vec<Record>> {
+        let backlog = self.backlog.lock().unwrap();
+        let end = backlog.first + backlog.records.len() as u64;
+        if offset < backlog.first || offset > end {
+            return None;
+        }
+        let start = (offset - backlog.first) as usize;
+        Some(backlog.records.range(start..).take(MAX_BATCH).cloned().collect())
+    }
+}
+
+/// true for the line a follower opens the replication stream with
+pub fn is_sync(line: &str) -> bool {
+    line.split_whitespace().next().is_some_and(|name| name.eq_ignore_ascii_case("sync"))
+}
+
+/// Leader side: answer the `sync` line and stream records until the follower goes away.
+pub async fn serve_follower(mut stream: BufStream<Connection>, line: &str, service: &Service,
+                            mut shutdown: Shutdown) -> Result<(), ServiceError> {
+    let hub = service.hub();
+    // subscribe before taking the offset, so no record after it can go unnoticed
+    let mut changes = hub.next_offset.subscribe();
+    let parts = line.split_whitespace().collect::<Vec<&str>>();
+    let resumable = match parts[1..] {
+        [id, offset] if id == hub.id => offset.parse::<u64>().ok().filter(|&offset| hub.read_from(offset).is_some()),
+        _ => None,
+    };
+    let mut offset = match (resumable, &parts[1..]) {
+        (Some(offset), _) => {
+            info!("Follower continues at offset {}", offset);
+            stream.write_all(b"+CONTINUE\r\n").await?;
+            offset
+        },
+        (None, [] | [_, _]) => full_sync(&mut stream, hub, service).await?,
+        _ => {
+            stream.write_all(b"-ERR BADREQUEST usage: sync [<id> <offset>]\r\n").await?;
+            stream.flush().await?;
+            return Ok(());
+        },
+    };
+    stream.flush().await?;
+
+    let mut unused = [0u8; 64];
+    loop {
+        changes.borrow_and_update();
+        let Some(records) = hub.read_from(offset) else {
+            // the follower will come back and ask for a full sync
+            warn!("Follower fell behind the backlog at offset {}", offset);
+            return Ok(());
+        };
+        if records.is_empty() {
+            tokio::select! {
+                result = changes.changed() => if result.is_err() { return Ok(()) },
+                // a follower sends nothing after sync, reading only notices it is gone
+                result = stream.read(&mut unused) => if result? == 0 { return Ok(()) },
+                _ = shutdown.wait() => return Ok(()),
+            }
+            continue;
+        }
+
+        offset += records.len() as u64;
+        stream.write_all(&frames(&records)).await?;
+        stream.flush().await?;
+    }
+}
+
+/// send all current data and return the offset the stream continues at
+async fn full_sync(stream: &mut BufStream<Connection>, hub: &Hub, service: &Service) -> Result<u64, ServiceError> {
+    let offset = hub.offset();
+    // the dump reflects at least every record before the offset
+    let entries = match service.request(Request::Dump()).await? {
+        Response::Entries(entries) => entries,
+        other => return Err(ServiceError::Protocol(format!("unexpected dump response {:?}", other))),
+    };
+    let mut records = Vec::with_capacity(entries.len());
+    for (key, entry) in entries {
+        if entry.expires_at.is_some() {
+            records.push(Record::Set(key.clone(), entry.value));
+            records.push(Record::Expire(key, entry.expires_at));
+        } else {
+            records.push(Record::Set(key, entry.value));
+        }
+    }
+    info!("Full sync of a follower with {} records, continuing at offset {}", records.len(), offset);
+    stream.write_all(format!("+FULLSYNC {} {} {}\r\n", hub.id, offset, records.len()).as_bytes()).await?;
+    stream.write_all(&frames(&records)).await?;
+    Ok(offset)
+}
+
+fn frames(records: &[Record]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for record in records {
+        wal::put_frame(&mut out, &wal::encode(record));
+    }
+    out
+}
+
+/// how a follower reaches its leader
+pub struct Leader {
+    pub address: String,
+    // user name and password if the leader runs with users
+    pub credentials: Option<(String, String)>,
+    pub tls: Option<TlsConnector>,
+}
+
+/// Follower side: replicate from the leader until the shutdown starts, reconnecting after errors.
+pub async fn follow(leader: Leader, service: Service, mut shutdown: Shutdown) {
+    // leader id and offset of the next record, known once a sync succeeded
+    let mut position: Option<(String, u64)> = None;
+    loop {
+        let result = tokio::select! {
+            result = replicate(&leader, &service, &mut position) => result,
+            _ = shutdown.wait() => break,
+        };
+        match result {
+            Ok(()) => info!("Leader {} closed the replication stream", leader.address),
+            Err(e) => warn!("Replication from {} interrupted: {}", leader.address, e),
+        }
+        tokio::select! {
+            _ = time::sleep(RECONNECT_DELAY) => {},
+            _ = shutdown.wait() => break,
+        }
+    }
+}
+
+async fn replicate(leader: &Leader, service: &Service, position: &mut Option<(String, u64)>) -> Result<(), ServiceError> {
+    let mut stream = BufReader::new(tls::connect(&leader.address, leader.tls.as_ref()).await?);
+    let mut line = String::new();
+    if let Some((user, password)) = &leader.credentials {
+        stream.get_mut().write_all(format!("auth {} {}\r\n", escape::quote(user.as_bytes()), escape::quote(password.as_bytes())).as_bytes()).await?;
+        stream.get_mut().flush().await?;
+        stream.read_line(&mut line).await?;
+        if line != "+OK\r\n" {
+            return Err(ServiceError::Protocol(format!("leader refused the credentials: {:?}", line.trim_end())));
+        }
+        line.clear();
+    }
+    let request = match position {
+        Some((id, offset)) => format!("sync {} {}\r\n", id, offset),
+        None => "sync\r\n".to_string(),
+    };
+    stream.get_mut().write_all(request.as_bytes()).await?;
+    stream.get_mut().flush().await?;
+
+    stream.read_line(&mut line).await?;
+    let parts = line.split_whitespace().collect::<Vec<&str>>();
+    match parts[..] {
+        ["+CONTINUE"] if position.is_some() => info!("Continuing replication from {}", leader.address),
+        ["+FULLSYNC", id, offset, count] => {
+            let (Ok(offset), Ok(count)) = (offset.parse::<u64>(), count.parse::<usize>()) else {
+                return Err(ServiceError::Protocol(format!("bad full sync header {:?}", line.trim_end())));
+            };
+            info!("Full sync from {} with {} records", leader.address, count);
+            // forget the position first, an interrupted full sync has to start over
+            *position = None;
+            apply(service, Request::Clear()).await?;
+            let mut remaining = count;
+            while remaining > 0 {
+                let batch = read_records(&mut stream, remaining.min(MAX_BATCH)).await?;
+                remaining -= batch.len();
+                apply(service, Request::Apply(batch)).await?;
+            }
+            *position = Some((id.to_string(), offset));
+        },
+        _ => return Err(ServiceError::Protocol(format!("unexpected sync reply {:?}", line.trim_end()))),
+    }
+
+    loop {
+        // wait for one record, then take whatever else already arrived
+        let mut batch = read_records(&mut stream, 1).await?;
+        while batch.len() < MAX_BATCH && !stream.buffer().is_empty() {
+            batch.extend(read_records(&mut stream, 1).await?);
+        }
+        let count = batch.len() as u64;
+        apply(service, Request::Apply(batch)).await?;
+        if let Some((_, offset)) = position {
+            *offset += count;
+        }
+    }
+}
+
+async fn apply(service: &Service, request: Request) -> Result<(), ServiceError> {
+    match service.request(request).await? {
+        Response::Error(kind, message) =>
+            Err(ServiceError::Io(std::io::Error::other(format!("{} {}", kind.code(), message)))),
+        _ => Ok(()),
+    }
+}
+
+/// read `count` framed records, a clean end of the stream before the first one is an error too
+async fn read_records(stream: &mut BufReader<Connection>, count: usize) -> Result<Vec<Record>, ServiceError> {
+    let mut records = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut header = [0u8; wal::FRAME_HEADER_LEN];
+        stream.read_exact(&mut header).await?;
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+        if len > MAX_RECORD_LEN {
+            return Err(ServiceError::Protocol(format!("record of {} bytes", len)));
+        }
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).await?;
+        let record = (wal::crc32(&payload) == crc).then(|| wal::decode(&payload)).flatten()
+            .ok_or_else(|| ServiceError::Protocol("damaged record".to_string()))?;
+        records.push(record);
+    }
+    Ok(records)
+}
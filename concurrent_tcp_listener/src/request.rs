@@ -0,0 +1,294 @@
+//! The requests the service handles and its responses, whatever protocol they came in.
+
+use tokio::sync::oneshot;
+use crate::auth::Access;
+use crate::metrics::Usage;
+use crate::pubsub::{ClientId, Outbox};
+use crate::store::Entry;
+use crate::wal::Record;
+
+/// what a client asks the service to do
+#[derive(Debug, Clone)]
+pub enum Request {
+    Set(String, Vec<u8>),   // set key := value -> OK
+    SetEx(String, Vec<u8>, u64), // set key := value ex seconds -> OK
+    Get(String),            // get key -> value
+    Del(Vec<String>),       // del key [key ...] -> number of keys removed
+    Exists(Vec<String>),    // exists key [key ...] -> number of keys present
+    Keys(String),           // keys pattern -> matching keys
+    Incr(String),           // incr key -> incremented value
+    Decr(String),           // decr key -> decremented value
+    Append(String, Vec<u8>), // append key value -> new length
+    Strlen(String),         // strlen key -> length of value
+    MSet(Vec<(String, Vec<u8>)>), // mset key value [key value ...] -> OK
+    MGet(Vec<String>),      // mget key [key ...] -> values
+    Expire(String, u64),    // expire key seconds -> 1 if the key exists, else 0
+    Ttl(String),            // ttl key -> remaining seconds, -1 without expiry, -2 if missing
+    PersistKey(String),     // persist key -> 1 if an expiry was removed, else 0
+    Publish(String, String), // publish channel message -> number of deliveries
+    Subscribe(ClientId, Outbox, Vec<String>),  // subscribe channel [channel ...] -> number of subscriptions
+    PSubscribe(ClientId, Outbox, Vec<String>), // psubscribe pattern [pattern ...] -> number of subscriptions
+    Unsubscribe(ClientId, Vec<String>),  // unsubscribe [channel ...] -> number of subscriptions left
+    PUnsubscribe(ClientId, Vec<String>), // punsubscribe [pattern ...] -> number of subscriptions left
+    Watch(ClientId, Outbox, Vec<String>),  // watch key-or-prefix* [...] -> number of subscriptions
+    Unwatch(ClientId, Vec<String>),        // unwatch [key-or-prefix* ...] -> number of subscriptions left
+    Cas(String, Vec<u8>, Vec<u8>), // cas key expected new -> OK if swapped, else the current value
+    Exec(Vec<Request>),     // requests queued between multi and exec -> their responses
+
+    // management requests, used internally
+    Persist(),              // persist hashmap to disk -> OK
+    ExpireSweep(),          // remove all expired keys -> OK
+    Disconnect(ClientId),   // a connection ended, drop its subscriptions -> OK
+    Close(),                // Close channel and terminate processing -> OK
+    Apply(Vec<Record>),     // write mutations replicated from the leader -> OK
+    Clear(),                // delete all keys before a full sync from the leader -> OK
+    Dump(),                 // all live entries for a follower's full sync -> entries
+    Usage(),                // key count, memory estimate and last snapshot for info -> usage
+}
+
+impl Request {
+    /// the command in metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Request::Set(..) => "set",
+            Request::SetEx(..) => "setex",
+            Request::Get(_) => "get",
+            Request::Del(_) => "del",
+            Request::Exists(_) => "exists",
+            Request::Keys(_) => "keys",
+            Request::Incr(_) => "incr",
+            Request::Decr(_) => "decr",
+            Request::Append(..) => "append",
+            Request::Strlen(_) => "strlen",
+            Request::MSet(_) => "mset",
+            Request::MGet(_) => "mget",
+            Request::Expire(..) => "expire",
+            Request::Ttl(_) => "ttl",
+            Request::PersistKey(_) => "persist",
+            Request::Publish(..) => "publish",
+            Request::Subscribe(..) => "subscribe",
+            Request::PSubscribe(..) => "psubscribe",
+            Request::Unsubscribe(..) => "unsubscribe",
+            Request::PUnsubscribe(..) => "punsubscribe",
+            Request::Watch(..) => "watch",
+            Request::Unwatch(..) => "unwatch",
+            Request::Cas(..) => "cas",
+            Request::Exec(_) => "exec",
+            Request::Persist() => "snapshot",
+            Request::ExpireSweep() => "expire_sweep",
+            Request::Disconnect(_) => "disconnect",
+            Request::Close() => "close",
+            Request::Apply(_) => "apply",
+            Request::Clear() => "clear",
+            Request::Dump() => "dump",
+            Request::Usage() => "usage",
+        }
+    }
+
+    /// true if the request changes keys, a follower only accepts those from its leader
+    pub fn is_write(&self) -> bool {
+        match self {
+            Request::Set(..) | Request::SetEx(..) | Request::Del(_) | Request::Incr(_) | Request::Decr(_)
+            | Request::Append(..) | Request::MSet(_) | Request::Expire(..) | Request::PersistKey(_)
+            | Request::Cas(..) => true,
+            Request::Exec(requests) => requests.iter().any(Request::is_write),
+            _ => false,
+        }
+    }
+
+    /// the keys the request reads or writes, checked against the rules of the logged in user.
+    /// `keys` is not listed, its reply is filtered instead.
+    pub(crate) fn accesses(&self) -> Vec<(Access, &str)> {
+        fn each(access: Access, keys: &[String]) -> Vec<(Access, &str)> {
+            keys.iter().map(|key| (access, key.as_str())).collect()
+        }
+        match self {
+            Request::Get(key) | Request::Strlen(key) | Request::Ttl(key) => vec![(Access::Read, key)],
+            Request::Exists(keys) | Request::MGet(keys) | Request::Watch(_, _, keys) => each(Access::Read, keys),
+            Request::Set(key, _) | Request::SetEx(key, ..) | Request::Expire(key, _)
+            | Request::PersistKey(key) => vec![(Access::Write, key)],
+            Request::Del(keys) => each(Access::Write, keys),
+            Request::MSet(pairs) => pairs.iter().map(|(k, _)| (Access::Write, k.as_str())).collect(),
+            Request::Incr(key) | Request::Decr(key) | Request::Append(key, _) | Request::Cas(key, ..) =>
+                vec![(Access::Read, key), (Access::Write, key)],
+            Request::Exec(requests) => requests.iter().flat_map(Request::accesses).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// the command and arguments `parse_command` turns into this request,
+    /// None for requests clients cannot send as a single command
+    pub fn command(&self) -> Option<Vec<Vec<u8>>> {
+        fn words(keys: &[String]) -> Vec<Vec<u8>> {
+            keys.iter().map(|key| word(key)).collect()
+        }
+        let (name, args) = match self {
+            Request::Set(key, value) => ("set", vec![word(key), value.clone()]),
+            Request::SetEx(key, value, seconds) =>
+                ("set", vec![word(key), value.clone(), word("ex"), word(&seconds.to_string())]),
+            Request::Get(key) => ("get", vec![word(key)]),
+            Request::Del(keys) => ("del", words(keys)),
+            Request::Exists(keys) => ("exists", words(keys)),
+            Request::Keys(pattern) => ("keys", vec![word(pattern)]),
+            Request::Incr(key) => ("incr", vec![word(key)]),
+            Request::Decr(key) => ("decr", vec![word(key)]),
+            Request::Append(key, value) => ("append", vec![word(key), value.clone()]),
+            Request::Strlen(key) => ("strlen", vec![word(key)]),
+            Request::MSet(pairs) => ("mset", pairs.iter().flat_map(|(k, v)| [word(k), v.clone()]).collect()),
+            Request::MGet(keys) => ("mget", words(keys)),
+            Request::Expire(key, seconds) => ("expire", vec![word(key), word(&seconds.to_string())]),
+            Request::Ttl(key) => ("ttl", vec![word(key)]),
+            Request::PersistKey(key) => ("persist", vec![word(key)]),
+            Request::Cas(key, expected, new) => ("cas", vec![word(key), expected.clone(), new.clone()]),
+            Request::Publish(channel, message) => ("publish", vec![word(channel), word(message)]),
+            _ => return None,
+        };
+        Some(std::iter::once(word(name)).chain(args).collect())
+    }
+}
+
+/// the answer of the service to a request
+#[derive(Debug)]
+pub enum Response {
+    Ok(),
+    Queued(),
+    NotFound(String),
+    Result(Vec<u8>),
+    Integer(i64),
+    List(Vec<String>),
+    Values(Vec<Option<Vec<u8>>>),
+    Multi(Vec<Response>),
+    Entries(Vec<(String, Entry)>),
+    Usage(Usage),
+    Error(ErrorKind, String),
+}
+
+/// why the service rejected a request
+#[derive(Debug)]
+pub enum ErrorKind {
+    BadRequest, // the request could not be parsed or is not allowed now
+    WrongType,  // the value does not fit the command, e.g. incr on a non-number
+    Overflow,   // the result does not fit into the value type
+    Storage,    // the storage engine failed to read or durably write
+    ExecAbort,  // a transaction was discarded because queueing a command failed
+    Unavailable, // the service is shutting down or has failed
+    CrossShard, // a transaction touches keys of different shards
+    Redirect,   // this server is a read-only follower, the message is the leader's address
+    NoAuth,     // the connection is not authenticated or the credentials are wrong
+    NoPerm,     // the user may not read or write one of the keys
+    Busy,       // the server has as many connections as it accepts
+    TooLong,    // the request is longer than max-line-length, the connection is closed
+    RateLimited, // the connection sent more requests per second than rate-limit allows
+}
+
+impl ErrorKind {
+    /// stable name of the error for clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::BadRequest => "BADREQUEST",
+            ErrorKind::WrongType => "WRONGTYPE",
+            ErrorKind::Overflow => "OVERFLOW",
+            ErrorKind::Storage => "STORAGE",
+            ErrorKind::ExecAbort => "EXECABORT",
+            ErrorKind::Unavailable => "UNAVAILABLE",
+            ErrorKind::CrossShard => "CROSSSHARD",
+            ErrorKind::Redirect => "REDIRECT",
+            ErrorKind::NoAuth => "NOAUTH",
+            ErrorKind::NoPerm => "NOPERM",
+            ErrorKind::Busy => "BUSY",
+            ErrorKind::TooLong => "TOOLONG",
+            ErrorKind::RateLimited => "RATELIMITED",
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<ErrorKind> {
+        [
+            ErrorKind::BadRequest, ErrorKind::WrongType, ErrorKind::Overflow, ErrorKind::Storage,
+            ErrorKind::ExecAbort, ErrorKind::Unavailable, ErrorKind::CrossShard, ErrorKind::Redirect,
+            ErrorKind::NoAuth, ErrorKind::NoPerm, ErrorKind::Busy, ErrorKind::TooLong, ErrorKind::RateLimited,
+        ].into_iter().find(|kind| kind.code() == code)
+    }
+}
+
+/// data transferred over the channel to our service
+pub type RequestTransport = (Request, oneshot::Sender<Response>);
+
+/// Parse a command and its arguments into a request, command names are case-insensitive.
+/// Values may be any bytes, keys, channels and numbers have to be UTF-8. Every value is
+/// a single argument, extra arguments are an error.
+pub fn parse_command<W: AsRef<[u8]>>(parts: &[W]) -> Result<Request, String> {
+    let request = parse_joined_command(parts)?;
+    match request.command() {
+        Some(command) if command.len() < parts.len() =>
+            Err(format!("too many arguments for {}", String::from_utf8_lossy(&command[0]))),
+        _ => Ok(request),
+    }
+}
+
+/// Like `parse_command`, but the words after the key are joined with spaces into the value,
+/// as lines were read before values could be quoted. Only the legacy reply format does this.
+pub(crate) fn parse_joined_command<W: AsRef<[u8]>>(parts: &[W]) -> Result<Request, String> {
+    let name = parts.first().map(|n| n.as_ref().to_ascii_lowercase()).unwrap_or_default();
+    let mut parts = parts.iter().map(AsRef::as_ref).collect::<Vec<&[u8]>>();
+    if let Some(first) = parts.first_mut() {
+        *first = &name;
+    }
+
+    match parts[..] {
+        [b"set", key, ref value @ .., ex, seconds] if !value.is_empty() && ex.eq_ignore_ascii_case(b"ex") =>
+            Ok(Request::SetEx(text(key)?, value.join(&b' '), parse_seconds(seconds)?)),
+        [b"set", key, ref value @ ..] if !value.is_empty() =>
+            Ok(Request::Set(text(key)?, value.join(&b' '))),
+        [b"get", key] =>
+            Ok(Request::Get(text(key)?)),
+        [b"del", ref keys @ ..] if !keys.is_empty() =>
+            Ok(Request::Del(to_strings(keys)?)),
+        [b"exists", ref keys @ ..] if !keys.is_empty() =>
+            Ok(Request::Exists(to_strings(keys)?)),
+        [b"keys", pattern] =>
+            Ok(Request::Keys(text(pattern)?)),
+        [b"incr", key] =>
+            Ok(Request::Incr(text(key)?)),
+        [b"decr", key] =>
+            Ok(Request::Decr(text(key)?)),
+        [b"append", key, ref value @ ..] if !value.is_empty() =>
+            Ok(Request::Append(text(key)?, value.join(&b' '))),
+        [b"strlen", key] =>
+            Ok(Request::Strlen(text(key)?)),
+        [b"mset", ref pairs @ ..] if !pairs.is_empty() && pairs.len() % 2 == 0 =>
+            Ok(Request::MSet(pairs.chunks(2).map(|p| Ok((text(p[0])?, p[1].to_vec()))).collect::<Result<_, String>>()?)),
+        [b"mget", ref keys @ ..] if !keys.is_empty() =>
+            Ok(Request::MGet(to_strings(keys)?)),
+        [b"expire", key, seconds] =>
+            Ok(Request::Expire(text(key)?, parse_seconds(seconds)?)),
+        [b"ttl", key] =>
+            Ok(Request::Ttl(text(key)?)),
+        [b"persist", key] =>
+            Ok(Request::PersistKey(text(key)?)),
+        [b"cas", key, expected, ref new @ ..] if !new.is_empty() =>
+            Ok(Request::Cas(text(key)?, expected.to_vec(), new.join(&b' '))),
+        [b"publish", channel, ref message @ ..] if !message.is_empty() =>
+            Ok(Request::Publish(text(channel)?, text(&message.join(&b' '))?)),
+        _ => Err(format!("not a valid request: {:?}", parts.iter().map(|p| String::from_utf8_lossy(p)).collect::<Vec<_>>()))
+    }
+}
+
+fn parse_seconds(seconds: &[u8]) -> Result<u64, String> {
+    match std::str::from_utf8(seconds).ok().and_then(|seconds| seconds.parse::<u64>().ok()) {
+        Some(seconds) if seconds > 0 => Ok(seconds),
+        _ => Err(format!("not a valid number of seconds: {:?}", String::from_utf8_lossy(seconds))),
+    }
+}
+
+/// a key, channel or name, which unlike values has to be UTF-8
+pub(crate) fn text(word: &[u8]) -> Result<String, String> {
+    String::from_utf8(word.to_vec()).map_err(|_| format!("not valid UTF-8: {}", crate::escape::quote(word)))
+}
+
+fn to_strings(parts: &[&[u8]]) -> Result<Vec<String>, String> {
+    parts.iter().map(|p| text(p)).collect()
+}
+
+fn word(text: &str) -> Vec<u8> {
+    text.as_bytes().to_vec()
+}
@@ -0,0 +1,365 @@
+use std::io;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tracing::{info, info_span, Instrument};
+use crate::auth::Login;
+use crate::error::ServiceError;
+use crate::escape;
+use crate::limits::{sleep_until, Limits, Stats};
+use crate::metrics::{self, Usage};
+use crate::shard::Service;
+use crate::shutdown::Shutdown;
+use crate::tls::Connection;
+use crate::transaction::Transaction;
+use crate::{next_request_id, parse_command, ErrorKind, Request, Response};
+
+/// largest bulk string accepted from a client, same as redis' default proto-max-bulk-len
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// largest number of arguments accepted in a single command
+const MAX_ARGUMENTS: usize = 1024 * 1024;
+
+/// RESP version negotiated with `hello`, every connection starts with RESP2
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Version {
+    Resp2,
+    Resp3,
+}
+
+/// Serve a client speaking the redis serialization protocol (RESP2/RESP3).
+pub async fn handle_client_connection(socket: Connection, peer: String, tx: &Service, login: Login,
+                                      limits: Limits, shutdown: Shutdown) {
+    info!("RESP connection from {}", peer);
+    match serve(socket, tx, login, limits, shutdown).await {
+        Ok(()) => info!("RESP connection from {} closed", peer),
+        Err(e) => info!("RESP connection from {} dropped: {}", peer, e),
+    }
+}
+
+async fn serve(socket: Connection, tx: &Service, mut login: Login, limits: Limits,
+               mut shutdown: Shutdown) -> Result<(), ServiceError> {
+    let mut stream = BufStream::new(socket);
+    let mut version = Version::Resp2;
+    let mut transaction = Transaction::default();
+    let mut rate = limits.rate_limiter();
+
+    loop {
+        let result = tokio::select! {
+            result = read_command(&mut stream, limits.max_line_length) => result,
+            _ = sleep_until(limits.idle_deadline()) => {
+                Stats::count(&limits.stats.idle_timeouts);
+                return Err(ServiceError::Idle(limits.idle_timeout.unwrap_or_default()));
+            },
+            _ = shutdown.wait() => {
+                stream.write_all(&error("server is shutting down")).await?;
+                stream.flush().await?;
+                return Ok(());
+            },
+        };
+        let args = match result {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            Err(ServiceError::Protocol(message)) => {
+                // after a framing error we cannot find the next command, so give up on the connection
+                let _ = stream.write_all(&error(&format!("Protocol error: {}", message))).await;
+                let _ = stream.flush().await;
+                return Err(ServiceError::Protocol(message));
+            },
+            Err(e @ ServiceError::TooLong(_)) => {
+                Stats::count(&limits.stats.oversized);
+                let _ = stream.write_all(&encode(&Response::Error(ErrorKind::TooLong, e.to_string()), version)).await;
+                let _ = stream.flush().await;
+                return Err(e);
+            },
+            Err(e) => return Err(e),
+        };
+        if args.is_empty() {
+            continue;
+        }
+        if !rate.allow() {
+            Stats::count(&limits.stats.rate_limited);
+            let message = format!("more than {} requests per second", limits.rate_limit.unwrap_or_default());
+            stream.write_all(&encode(&Response::Error(ErrorKind::RateLimited, message), version)).await?;
+            stream.flush().await?;
+            continue;
+        }
+
+        // values may be any bytes, only the connection level commands are matched as text
+        let text = args.iter().map(|arg| String::from_utf8_lossy(arg)).collect::<Vec<_>>();
+        let parts = text.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+        let name = parts[0].to_ascii_lowercase();
+        // ends the connection rather than producing a reply
+        if name == "quit" && parts.len() == 1 && !transaction.is_open() {
+            stream.write_all(&simple("OK")).await?;
+            stream.flush().await?;
+            return Ok(());
+        }
+        let span = info_span!("request", id = next_request_id(), command = %name);
+        let result = async {
+            match (name.as_str(), &parts[1..]) {
+                ("auth", [user, password]) => Ok(encode(&login.auth(user, password).await, version)),
+                ("stats", []) => Ok(match login.may_inspect() {
+                    Ok(()) => bulk(limits.report().as_bytes()),
+                    Err(response) => encode(&response, version),
+                }),
+                // clients may ask for a section, the reply always has all of them
+                ("info", [] | [_]) => match login.may_inspect() {
+                    Ok(()) => metrics::info(tx, &limits).await.map(|info| bulk(info.as_bytes())),
+                    Err(response) => Ok(encode(&response, version)),
+                },
+                ("multi", []) => Ok(encode(&transaction.begin(), version)),
+                ("exec", []) => match transaction.exec() {
+                    Ok(request) => execute(request, &login, tx, version).await,
+                    Err(response) => Ok(encode(&response, version)),
+                },
+                ("discard", []) => Ok(encode(&transaction.discard(), version)),
+                _ if transaction.is_open() => Ok(encode(&transaction.queue(parse_command(&args)), version)),
+                // connection level commands never reach the service
+                ("ping", []) => Ok(simple("PONG")),
+                ("ping", [_]) | ("echo", [_]) => Ok(bulk(&args[1])),
+                ("hello", []) => Ok(hello(version, tx)),
+                ("hello", [protover, options @ ..]) => {
+                    let requested = match *protover {
+                        "2" => Version::Resp2,
+                        "3" => Version::Resp3,
+                        _ => return Ok(b"-NOPROTO unsupported protocol version\r\n".to_vec()),
+                    };
+                    let credentials = match hello_credentials(options) {
+                        Ok(credentials) => credentials,
+                        Err(message) => return Ok(error(&message)),
+                    };
+                    // a failed login keeps the protocol version
+                    if let Some((user, password)) = credentials
+                        && let response @ Response::Error(..) = login.auth(user, password).await {
+                        return Ok(encode(&response, version));
+                    }
+                    version = requested;
+                    Ok(hello(version, tx))
+                },
+                ("select", ["0"]) => Ok(simple("OK")),
+                ("select", [_]) => Ok(error("DB index is out of range")),
+                // redis-cli and client libraries probe these on connect
+                ("command", _) => Ok(array_header(0)),
+                ("client", _) => Ok(simple("OK")),
+                _ => match parse_command(&args) {
+                    Ok(request) => execute(request, &login, tx, version).await,
+                    Err(message) => Ok(error(&message)),
+                },
+            }
+        }.instrument(span).await;
+        let reply = match result {
+            Ok(reply) => reply,
+            Err(e) => {
+                // tell the client why it is disconnected, if it still listens
+                let _ = stream.write_all(&error(&e.to_string())).await;
+                let _ = stream.flush().await;
+                return Err(e);
+            },
+        };
+
+        stream.write_all(&reply).await?;
+        stream.flush().await?;
+    }
+}
+
+/// the credentials of `hello <protover> [AUTH <user> <password>] [SETNAME <name>]`,
+/// the name is accepted like `client setname` and not kept
+fn hello_credentials<'a>(mut options: &[&'a str]) -> Result<Option<(&'a str, &'a str)>, String> {
+    let mut credentials = None;
+    loop {
+        match options {
+            [] => return Ok(credentials),
+            [option, user, password, rest @ ..] if option.eq_ignore_ascii_case("auth") => {
+                credentials = Some((*user, *password));
+                options = rest;
+            },
+            [option, _, rest @ ..] if option.eq_ignore_ascii_case("setname") => options = rest,
+            [option, ..] => return Err(format!("syntax error in HELLO option '{}'", option)),
+        }
+    }
+}
+
+async fn execute(request: Request, login: &Login, tx: &Service, version: Version) -> Result<Vec<u8>, ServiceError> {
+    Ok(encode(&login.send(request, tx).await?, version))
+}
+
+/// Read one command of at most `max` bytes, either a RESP array of bulk strings or an inline
+/// command line. Returns `None` on a clean end of stream.
+async fn read_command<R: AsyncBufRead + Unpin>(reader: &mut R, max: usize) -> Result<Option<Vec<Vec<u8>>>, ServiceError> {
+    // bytes of the command that may still follow
+    let mut room = max;
+    let mut line = String::new();
+    if read_line(reader, &mut line, &mut room, max).await? == 0 {
+        return Ok(None);
+    }
+    let line = line.trim_end_matches(['\r', '\n']);
+
+    let Some(count) = line.strip_prefix('*') else {
+        // inline command as typed into telnet, with the quoting of the text protocol
+        return escape::split(line.as_bytes()).map(Some).map_err(protocol_error);
+    };
+    let count = parse_length(count, MAX_ARGUMENTS)?;
+
+    let mut args = Vec::with_capacity(count.min(64));
+    for _ in 0..count {
+        let mut header = String::new();
+        if read_line(reader, &mut header, &mut room, max).await? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let Some(len) = header.trim_end_matches(['\r', '\n']).strip_prefix('$') else {
+            return Err(protocol_error(format!("expected '$', got {:?}", header.trim_end())));
+        };
+        let len = parse_length(len, MAX_BULK_LEN)?;
+        if len + 2 > room {
+            return Err(ServiceError::TooLong(max));
+        }
+        room -= len + 2;
+
+        let mut data = vec![0u8; len + 2];
+        reader.read_exact(&mut data).await?;
+        if !data.ends_with(b"\r\n") {
+            return Err(protocol_error("bulk string not terminated by CRLF".to_string()));
+        }
+        data.truncate(len);
+        args.push(data);
+    }
+    Ok(Some(args))
+}
+
+/// read a line of at most `room` bytes and take its length from `room`
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R, line: &mut String, room: &mut usize, max: usize)
+                                            -> Result<usize, ServiceError> {
+    let nr_bytes = match (&mut *reader).take(*room as u64).read_line(line).await {
+        Ok(nr_bytes) => nr_bytes,
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => return Err(protocol_error(e.to_string())),
+        Err(e) => return Err(e.into()),
+    };
+    if nr_bytes == *room && !line.ends_with('\n') {
+        return Err(ServiceError::TooLong(max));
+    }
+    *room -= nr_bytes;
+    Ok(nr_bytes)
+}
+
+fn parse_length(text: &str, max: usize) -> Result<usize, ServiceError> {
+    match text.parse::<usize>() {
+        Ok(len) if len <= max => Ok(len),
+        Ok(_) => Err(protocol_error(format!("length {} exceeds limit {}", text, max))),
+        Err(_) => Err(protocol_error(format!("invalid length {:?}", text))),
+    }
+}
+
+fn protocol_error(message: String) -> ServiceError {
+    ServiceError::Protocol(message)
+}
+
+/// serialize a service response
+fn encode(response: &Response, version: Version) -> Vec<u8> {
+    match response {
+        Response::Ok() => simple("OK"),
+        Response::Queued() => simple("QUEUED"),
+        Response::NotFound(_) => null(version),
+        Response::Result(value) => bulk(value),
+        Response::Integer(n) => format!(":{}\r\n", n).into_bytes(),
+        Response::List(items) => {
+            let mut reply = array_header(items.len());
+            for item in items {
+                reply.extend(bulk(item.as_bytes()));
+            }
+            reply
+        },
+        Response::Values(values) => {
+            let mut reply = array_header(values.len());
+            for value in values {
+                match value {
+                    Some(value) => reply.extend(bulk(value)),
+                    None => reply.extend(null(version)),
+                }
+            }
+            reply
+        },
+        Response::Multi(responses) => {
+            let mut reply = array_header(responses.len());
+            for response in responses {
+                reply.extend(encode(response, version));
+            }
+            reply
+        },
+        Response::Entries(entries) => {
+            let mut reply = array_header(entries.len() * 2);
+            for (key, entry) in entries {
+                reply.extend(bulk(key.as_bytes()));
+                reply.extend(bulk(&entry.value));
+            }
+            reply
+        },
+        Response::Usage(usage) => encode(&usage_numbers(usage), version),
+        // like redis, these errors start with their own code instead of ERR
+        Response::Error(kind @ (ErrorKind::ExecAbort | ErrorKind::Redirect | ErrorKind::NoAuth | ErrorKind::NoPerm
+                                | ErrorKind::Busy | ErrorKind::TooLong | ErrorKind::RateLimited), message) =>
+            format!("-{} {}\r\n", kind.code(), message).into_bytes(),
+        Response::Error(_, message) => error(message),
+    }
+}
+
+/// an internal usage report as the two numbers `keys` and `memory`
+fn usage_numbers(usage: &Usage) -> Response {
+    Response::Multi(vec![Response::Integer(usage.keys as i64), Response::Integer(usage.memory as i64)])
+}
+
+/// serialize a response for a client that has not negotiated a version yet
+pub fn encode_resp2(response: &Response) -> Vec<u8> {
+    encode(response, Version::Resp2)
+}
+
+/// reply to `hello`, a map in RESP3 and a flat array of key/value pairs in RESP2
+fn hello(version: Version, tx: &Service) -> Vec<u8> {
+    let role: &[u8] = if tx.leader().is_some() { b"replica" } else { b"master" };
+    let proto = match version {
+        Version::Resp2 => 2,
+        Version::Resp3 => 3,
+    };
+    let fields = [
+        ("server", bulk(b"concurrent_tcp_listener")),
+        ("version", bulk(env!("CARGO_PKG_VERSION").as_bytes())),
+        ("proto", format!(":{}\r\n", proto).into_bytes()),
+        ("mode", bulk(b"standalone")),
+        ("role", bulk(role)),
+        ("modules", array_header(0)),
+    ];
+    let mut reply = match version {
+        Version::Resp2 => array_header(fields.len() * 2),
+        Version::Resp3 => format!("%{}\r\n", fields.len()).into_bytes(),
+    };
+    for (name, value) in fields {
+        reply.extend(bulk(name.as_bytes()));
+        reply.extend(value);
+    }
+    reply
+}
+
+fn simple(text: &str) -> Vec<u8> {
+    format!("+{}\r\n", text).into_bytes()
+}
+
+fn error(message: &str) -> Vec<u8> {
+    // line breaks would end the error early
+    format!("-ERR {}\r\n", message.replace(['\r', '\n'], " ")).into_bytes()
+}
+
+fn bulk(data: &[u8]) -> Vec<u8> {
+    let mut reply = format!("${}\r\n", data.len()).into_bytes();
+    reply.extend_from_slice(data);
+    reply.extend_from_slice(b"\r\n");
+    reply
+}
+
+fn null(version: Version) -> Vec<u8> {
+    match version {
+        Version::Resp2 => b"$-1\r\n".to_vec(),
+        Version::Resp3 => b"_\r\n".to_vec(),
+    }
+}
+
+fn array_header(len: usize) -> Vec<u8> {
+    format!("*{}\r\n", len).into_bytes()
+}
@@ -0,0 +1,442 @@
+//! Running the service inside another program.
+//!
+//! `Server::builder()` starts from the defaults of `Config`, `start` binds the addresses and
+//! spawns the storage shards and connection handlers, `shutdown` stops them again the way a
+//! termination signal stops the binary. Port 0 picks a free port, e.g. for tests:
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! use concurrent_tcp_listener::Server;
+//!
+//! let server = Server::builder()
+//!     .listen("127.0.0.1:0".parse().unwrap())
+//!     .resp_listen("127.0.0.1:0".parse().unwrap())
+//!     .data_dir("/tmp/kv")
+//!     .start()
+//!     .await?;
+//! println!("serving on {:?}", server.local_addr());
+//! server.shutdown().await
+//! # }
+//! ```
+
+use std::fmt::Display;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use crate::auth::{self, Users};
+use crate::config::{Address, Config};
+use crate::limits::Limits;
+use crate::listener::Listener;
+use crate::metrics;
+use crate::replication::{self, Hub, Leader};
+use crate::resp;
+use crate::shard::{self, Service};
+use crate::shutdown::{Shutdown, ShutdownController};
+use crate::storage::Engine;
+use crate::store::{Files, Store};
+use crate::text::{self, ReplyFormat};
+use crate::tls::{self, Connection, Identity};
+use crate::{handle_single_request, send_request_and_wait_for_response, ErrorKind, Request, RequestTransport, Response};
+
+/// how long connections may take to finish their current request on shutdown
+const SHUTDOWN_DEADLINE: time::Duration = time::Duration::from_secs(10);
+
+/// pause after a failed accept before trying again
+pub(crate) const ACCEPT_ERROR_BACKOFF: time::Duration = time::Duration::from_millis(100);
+
+/// connections over the limit that are told so at the same time, further ones are just closed
+const MAX_REJECTING: usize = 16;
+
+/// how long telling a connection over the limit may take, including the TLS handshake
+const REJECT_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// how often expired keys are actively removed
+const EXPIRE_SWEEP_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/// The settings of a server to start, those of `Config::default()` unless changed.
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    config: Config,
+}
+
+impl Builder {
+    /// all settings at once, e.g. from `Config::load`
+    pub fn config(mut self, config: Config) -> Builder {
+        self.config = config;
+        self
+    }
+
+    /// serve the text protocol on `address` only
+    pub fn listen(mut self, address: Address) -> Builder {
+        self.config.listen = vec![address];
+        self
+    }
+
+    /// serve RESP on `address` only
+    pub fn resp_listen(mut self, address: Address) -> Builder {
+        self.config.resp_listen = vec![address];
+        self
+    }
+
+    /// resolve the snapshot, write-ahead log and data paths in `dir` instead of the working directory
+    pub fn data_dir(mut self, dir: impl AsRef<Path>) -> Builder {
+        let dir = dir.as_ref();
+        self.config.snapshot_path = dir.join(&self.config.snapshot_path);
+        self.config.wal_path = dir.join(&self.config.wal_path);
+        self.config.data_path = dir.join(&self.config.data_path);
+        self
+    }
+
+    pub fn shards(mut self, shards: usize) -> Builder {
+        self.config.shards = shards;
+        self
+    }
+
+    pub fn engine(mut self, engine: Engine) -> Builder {
+        self.config.engine = engine;
+        self
+    }
+
+    /// Bind every address and start serving.
+    /// Fails if an address, the users file, a certificate or the data files cannot be used.
+    pub async fn start(self) -> io::Result<Server> {
+        let config = self.config;
+        let users = users(&config)?;
+        let identity = identity(&config);
+        let tls = tls_acceptor(&config, identity.as_ref())?;
+        let leader_tls = tls_connector(&config, identity.as_ref())?;
+        let listeners = bind_all(&config.listen).await?;
+        let resp_listeners = bind_all(&config.resp_listen).await?;
+        let metrics_listener = match config.metrics_listen {
+            Some(address) => Some(TcpListener::bind(address).await
+                .map_err(|e| context(e, format!("Unable to serve metrics on {}", address)))?),
+            None => None,
+        };
+        let listen = local_addresses(&listeners)?;
+        let resp_listen = local_addresses(&resp_listeners)?;
+        let metrics_listen = metrics_listener.as_ref().map(TcpListener::local_addr).transpose()?;
+        for address in &listen {
+            info!("Listening on {}", address);
+        }
+        for address in &resp_listen {
+            info!("Listening for RESP clients on {}", address);
+        }
+        if let Some(address) = metrics_listen {
+            info!("Serving metrics on http://{}/metrics", address);
+        }
+
+        // build a channel to a handler processing each request in turn in order to prevent concurrency issues.
+        // With several shards every handler owns a part of the keys and its own snapshot and log files.
+        let (shards, engine) = (config.shards, config.engine);
+        let files = Files {
+            snapshot: config.snapshot_path.clone(),
+            wal: config.wal_path.clone(),
+            data: config.data_path.clone(),
+        };
+        shard::migrate_layout(engine, &files, shards)
+            .map_err(|e| context(e, "Unable to migrate data to the new number of shards"))?;
+        info!("Running {} storage shard(s) with the {:?} engine", shards, engine);
+        let hub = Arc::new(Hub::new());
+        let mut senders = Vec::with_capacity(shards);
+        let mut services = Vec::with_capacity(shards);
+        let stores = (0..shards)
+            .map(|i| info_span!("shard", shard = i).in_scope(|| Store::open(engine, &files.shard(i, shards))))
+            .collect::<io::Result<Vec<Store>>>()
+            .map_err(|e| context(e, "Unable to open the data files"))?;
+        for (i, store) in stores.into_iter().enumerate() {
+            let (shard_tx, rx) = mpsc::channel::<RequestTransport>(config.channel_capacity);
+            let hub = hub.clone();
+            services.push(tokio::spawn(async move {
+                handle_single_request(rx, store, hub).await;
+            }.instrument(info_span!("shard", shard = i))));
+            senders.push(shard_tx);
+        }
+        let service = Service::new(senders, hub);
+
+        // every task below finishes its current work and ends when the shutdown starts
+        let shutdown = ShutdownController::new();
+
+        // a follower takes writes only from its leader, clients are redirected there
+        let tx = match config.replicaof.clone() {
+            Some(leader) => {
+                info!("Replicating from {}", leader);
+                let credentials = config.leader_user.clone().zip(config.leader_password.clone());
+                let follower = Leader { address: leader.clone(), credentials, tls: leader_tls };
+                let span = info_span!("follower", leader = %leader);
+                tokio::spawn(replication::follow(follower, service.clone(), shutdown.subscribe()).instrument(span));
+                service.read_only(leader)
+            },
+            None => service,
+        };
+
+        // a timer triggering a Persist request, every 20s by default
+        spawn_timer(config.persist_interval, Request::Persist, tx.clone(), shutdown.subscribe());
+
+        // a timer triggering removal of expired keys
+        spawn_timer(EXPIRE_SWEEP_INTERVAL, Request::ExpireSweep, tx.clone(), shutdown.subscribe());
+
+        // accept loops, one per address
+        // existing clients parsing the Debug output can keep it with legacy-format
+        let format = if config.legacy_format { ReplyFormat::Legacy } else { ReplyFormat::Text };
+        if tls.is_some() {
+            info!("All addresses require TLS");
+        }
+        let settings = Settings { users, tls, limits: Limits::new(&config), rejecting: Arc::new(Semaphore::new(MAX_REJECTING)) };
+        if let Some(listener) = metrics_listener {
+            tokio::spawn(metrics::serve(listener, tx.clone(), settings.limits.clone(), shutdown.subscribe()));
+        }
+        let listeners = listeners.into_iter().map(|l| (l, Protocol::Text(format)))
+            .chain(resp_listeners.into_iter().map(|l| (l, Protocol::Resp)));
+        for (listener, protocol) in listeners {
+            tokio::spawn(accept_loop(listener, protocol, tx.clone(), settings.clone(), shutdown.subscribe()));
+        }
+
+        Ok(Server { listen, resp_listen, metrics_listen, service: tx, shutdown, shards: services })
+    }
+}
+
+/// A running server. It keeps running when dropped, until the runtime ends.
+pub struct Server {
+    listen: Vec<Address>,
+    resp_listen: Vec<Address>,
+    metrics_listen: Option<SocketAddr>,
+    service: Service,
+    shutdown: ShutdownController,
+    shards: Vec<JoinHandle<()>>,
+}
+
+impl Server {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// where the text protocol is served, with the ports picked for port 0
+    pub fn addresses(&self) -> &[Address] {
+        &self.listen
+    }
+
+    /// where RESP is served, with the ports picked for port 0
+    pub fn resp_addresses(&self) -> &[Address] {
+        &self.resp_listen
+    }
+
+    /// the first TCP address of the text protocol
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        first_tcp(&self.listen)
+    }
+
+    /// the first TCP address of RESP
+    pub fn resp_addr(&self) -> Option<SocketAddr> {
+        first_tcp(&self.resp_listen)
+    }
+
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_listen
+    }
+
+    /// the storage behind the listeners, for requests without a connection
+    pub fn service(&self) -> &Service {
+        &self.service
+    }
+
+    /// Stop accepting, let connections finish their current request,
+    /// then close the service which writes the final snapshot.
+    /// Fails if a storage shard did not end cleanly.
+    pub async fn shutdown(self) -> io::Result<()> {
+        info!("Shutting down, waiting up to {:?} for connections", SHUTDOWN_DEADLINE);
+        if !self.shutdown.shutdown(SHUTDOWN_DEADLINE).await {
+            warn!("Some connections did not finish in time");
+        }
+        if send_request_and_wait_for_response(Request::Close(), &self.service).await.is_err() {
+            error!("Service was already gone, the final snapshot may be missing");
+        }
+        let mut failed = 0;
+        for shard in self.shards {
+            if let Err(e) = shard.await {
+                warn!("Service failed: {}", e);
+                failed += 1;
+            }
+        }
+        match failed {
+            0 => Ok(()),
+            _ => Err(io::Error::other(format!("{} storage shard(s) failed", failed))),
+        }
+    }
+}
+
+fn first_tcp(addresses: &[Address]) -> Option<SocketAddr> {
+    addresses.iter().find_map(|address| match address {
+        Address::Tcp(address) => Some(*address),
+        Address::Unix(_) => None,
+    })
+}
+
+/// `e` with a description of what failed in front of it
+fn context(e: io::Error, what: impl Display) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", what, e))
+}
+
+/// users allowed to connect, everybody may do everything without a users file
+fn users(config: &Config) -> io::Result<Option<Arc<Users>>> {
+    let Some(path) = config.users.as_ref() else {
+        return Ok(None);
+    };
+    let users = Users::load(Path::new(path)).map_err(|e| context(e, "Unable to load users"))?;
+    Ok(Some(Arc::new(users)))
+}
+
+/// certificate and key presented to clients and to a leader
+fn identity(config: &Config) -> Option<Identity> {
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(Identity { cert: cert.clone(), key: key.clone() }),
+        _ => None,
+    }
+}
+
+/// TLS for the listening ports if there is a certificate
+fn tls_acceptor(config: &Config, identity: Option<&Identity>) -> io::Result<Option<TlsAcceptor>> {
+    let Some(identity) = identity else {
+        return Ok(None);
+    };
+    let acceptor = tls::acceptor(identity, config.tls_client_ca.as_deref())
+        .map_err(|e| context(e, "Unable to set up TLS"))?;
+    Ok(Some(acceptor))
+}
+
+/// TLS to the leader if its authority is known
+fn tls_connector(config: &Config, identity: Option<&Identity>) -> io::Result<Option<TlsConnector>> {
+    let Some(ca) = config.leader_tls_ca.as_deref() else {
+        return Ok(None);
+    };
+    let connector = tls::connector(ca, identity).map_err(|e| context(e, "Unable to set up TLS to the leader"))?;
+    Ok(Some(connector))
+}
+
+/// bind every address, a server missing some of them would surprise its clients
+async fn bind_all(addresses: &[Address]) -> io::Result<Vec<Listener>> {
+    let mut listeners = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let listener = Listener::bind(address).await
+            .map_err(|e| context(e, format!("Unable to listen on {}", address)))?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+fn local_addresses(listeners: &[Listener]) -> io::Result<Vec<Address>> {
+    listeners.iter().map(Listener::local_address).collect()
+}
+
+/// send a maintenance request periodically until the shutdown starts
+fn spawn_timer(interval: time::Duration, request: fn() -> Request, tx: Service, mut shutdown: Shutdown) {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = time::sleep(interval) => {},
+                _ = shutdown.wait() => break,
+            }
+            if send_request_and_wait_for_response(request(), &tx).await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// the wire protocol spoken on a listening port
+#[derive(Debug, Clone, Copy)]
+enum Protocol {
+    Text(ReplyFormat),
+    Resp,
+}
+
+/// what every connection on the listening ports is set up with
+#[derive(Clone)]
+struct Settings {
+    users: Option<Arc<Users>>,
+    tls: Option<TlsAcceptor>,
+    limits: Limits,
+    // bounds the tasks of `reject`
+    rejecting: Arc<Semaphore>,
+}
+
+async fn accept_loop(listener: Listener, protocol: Protocol, tx: Service, settings: Settings, mut shutdown: Shutdown) {
+    loop {
+        let (socket, peer) = tokio::select! {
+            result = listener.accept() => match result {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // e.g. out of file descriptors, give connections a moment to close
+                    warn!("Accepting a connection failed: {}", e);
+                    time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                },
+            },
+            _ = shutdown.wait() => break,
+        };
+        let Some(slot) = settings.limits.admit() else {
+            let Ok(permit) = settings.rejecting.clone().try_acquire_owned() else {
+                warn!("Too many connections, closing the one from {}", peer);
+                continue;
+            };
+            warn!("Too many connections, rejecting the one from {}", peer);
+            let span = info_span!("connection", %peer);
+            let tls = settings.tls.clone();
+            tokio::spawn(async move {
+                let _permit = permit;
+                reject(socket, peer, protocol, tls).await;
+            }.instrument(span));
+            continue;
+        };
+        // A new task is spawned for each inbound socket.
+        // The socket is moved to the new task and processed there.
+        let tx_clone = tx.clone();
+        let shutdown = shutdown.clone();
+        let login = auth::Login::new(settings.users.clone());
+        let (tls, limits) = (settings.tls.clone(), settings.limits.clone());
+        let span = info_span!("connection", %peer);
+        tokio::spawn(async move {
+            // held until the connection ends
+            let _slot = slot;
+            // the handshake runs here, so a slow client does not hold up accepting others
+            let socket = match tls::accept(socket, tls.as_ref()).await {
+                Ok(socket) => socket,
+                Err(e) => {
+                    warn!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                },
+            };
+            match protocol {
+                Protocol::Text(format) =>
+                    text::handle_client_connection(socket, peer, &tx_clone, format, login, limits, shutdown).await,
+                Protocol::Resp => resp::handle_client_connection(socket, peer, &tx_clone, login, limits, shutdown).await,
+            }
+        }.instrument(span));
+    }
+}
+
+/// tell a client over the connection limit to come back later, then close its connection
+async fn reject(socket: Connection, peer: String, protocol: Protocol, tls: Option<TlsAcceptor>) {
+    let busy = Response::Error(ErrorKind::Busy, "too many connections, try again later".to_string());
+    let reply = match protocol {
+        Protocol::Text(_) => text::encode(&busy),
+        Protocol::Resp => resp::encode_resp2(&busy),
+    };
+    let result = async {
+        let mut socket = tls::accept(socket, tls.as_ref()).await?;
+        socket.write_all(&reply).await?;
+        socket.shutdown().await
+    };
+    match time::timeout(REJECT_TIMEOUT, result).await {
+        Ok(Ok(())) => {},
+        Ok(Err(e)) => debug!("Rejecting {} failed: {}", peer, e),
+        Err(_) => debug!("Rejecting {} timed out", peer),
+    }
+}
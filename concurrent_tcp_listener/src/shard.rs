@@ -0,0 +1,415 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::oneshot;
+use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
+use tracing::{debug, info};
+use crate::error::ServiceError;
+use crate::metrics::{Metrics, Usage};
+use crate::replication::Hub;
+use crate::storage::Engine;
+use crate::store::{remove_if_exists, Files, Store};
+use crate::wal::Record;
+use crate::{ErrorKind, Request, RequestTransport, Response};
+
+/// How connections reach the storage: a single service task or several shards.
+///
+/// Every key belongs to exactly one shard, so requests for the same key are always
+/// handled by the same task in the order they arrive. Requests for several keys are
+/// split by shard and their responses merged; channel subscriptions live on shard 0,
+/// key watches and maintenance requests go to every shard.
+/// Every request is counted and timed in `metrics`.
+#[derive(Clone)]
+pub struct Service {
+    shards: Vec<Sender<RequestTransport>>,
+    // the mutations of all shards, streamed to followers
+    hub: Arc<Hub>,
+    metrics: Arc<Metrics>,
+    // address of the leader if this server is a read-only follower
+    leader: Option<String>,
+}
+
+impl Service {
+    pub fn new(shards: Vec<Sender<RequestTransport>>, hub: Arc<Hub>) -> Service {
+        assert!(!shards.is_empty(), "at least one shard is needed");
+        Service { shards, hub, metrics: Arc::new(Metrics::new()), leader: None }
+    }
+
+    /// the same service rejecting writes with a redirect to `leader`
+    pub fn read_only(&self, leader: String) -> Service {
+        Service { leader: Some(leader), ..self.clone() }
+    }
+
+    pub fn hub(&self) -> &Hub {
+        &self.hub
+    }
+
+    pub fn leader(&self) -> Option<&str> {
+        self.leader.as_deref()
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// send a request to the shards concerned and wait for the combined response
+    pub async fn request(&self, request: Request) -> Result<Response, ServiceError> {
+        self.send(request).await?.response().await
+    }
+
+    /// Send a request to the shards concerned without waiting for the response.
+    /// Requests sent one after another reach every shard in that order, so a connection may
+    /// send the next one before the response to the previous one arrived and still see its effect.
+    pub async fn send(&self, request: Request) -> Result<Pending, ServiceError> {
+        let (command, started) = (request.name(), Instant::now());
+        let (parts, combine) = self.plan(request);
+        let mut responses = Vec::with_capacity(parts.len());
+        for (shard, request) in parts {
+            let (response_tx, response_rx) = oneshot::channel::<Response>();
+            self.shards[shard].send((request, response_tx)).await.map_err(|_| ServiceError::Unavailable)?;
+            responses.push(response_rx);
+        }
+        Ok(Pending { responses, combine, recorded: Some((command, started, self.metrics.clone())) })
+    }
+
+    /// what all shards hold together
+    pub async fn usage(&self) -> Result<Usage, ServiceError> {
+        match self.request(Request::Usage()).await? {
+            Response::Usage(usage) => Ok(usage),
+            other => Err(ServiceError::Protocol(format!("unexpected usage response {:?}", other))),
+        }
+    }
+
+    /// the part of the request for each shard it concerns and how their responses combine
+    fn plan(&self, request: Request) -> (Vec<(usize, Request)>, Combine) {
+        if let Some(leader) = &self.leader
+            && request.is_write() {
+            return (Vec::new(), known(Response::Error(ErrorKind::Redirect, leader.clone())));
+        }
+        if self.shards.len() == 1 {
+            return (vec![(0, request)], Box::new(only));
+        }
+
+        match request {
+            Request::Del(keys) => {
+                let parts = self.split_keys(keys).into_iter().map(|(shard, keys)| (shard, Request::Del(keys)));
+                (parts.collect(), Box::new(sum))
+            },
+            Request::Exists(keys) => {
+                let parts = self.split_keys(keys).into_iter().map(|(shard, keys)| (shard, Request::Exists(keys)));
+                (parts.collect(), Box::new(sum))
+            },
+            Request::MGet(keys) => self.mget(keys),
+            Request::MSet(pairs) => {
+                let mut groups: BTreeMap<usize, Vec<(String, Vec<u8>)>> = BTreeMap::new();
+                for (key, value) in pairs {
+                    groups.entry(self.shard_of(&key)).or_default().push((key, value));
+                }
+                let parts = groups.into_iter().map(|(shard, pairs)| (shard, Request::MSet(pairs)));
+                (parts.collect(), Box::new(|responses| first_error(responses).unwrap_or(Response::Ok())))
+            },
+            Request::Apply(records) => {
+                let mut groups: BTreeMap<usize, Vec<Record>> = BTreeMap::new();
+                for record in records {
+                    groups.entry(self.shard_of(record.key())).or_default().push(record);
+                }
+                let parts = groups.into_iter().map(|(shard, records)| (shard, Request::Apply(records)));
+                (parts.collect(), Box::new(|responses| first_error(responses).unwrap_or(Response::Ok())))
+            },
+            Request::Dump() => (self.everywhere(Request::Dump()), Box::new(|responses| {
+                let mut entries = Vec::new();
+                for response in responses {
+                    match response {
+                        Response::Entries(part) => entries.extend(part),
+                        other => return other,
+                    }
+                }
+                Response::Entries(entries)
+            })),
+            Request::Usage() => (self.everywhere(Request::Usage()), Box::new(|responses| {
+                let mut usage = Usage::default();
+                for response in responses {
+                    match response {
+                        Response::Usage(part) => usage = usage.merge(part),
+                        other => return other,
+                    }
+                }
+                Response::Usage(usage)
+            })),
+            Request::Keys(pattern) => (self.everywhere(Request::Keys(pattern)), Box::new(|responses| {
+                let mut keys = Vec::new();
+                for response in responses {
+                    match response {
+                        Response::List(part) => keys.extend(part),
+                        other => return other,
+                    }
+                }
+                keys.sort();
+                Response::List(keys)
+            })),
+            Request::Exec(requests) => {
+                let mut touched = BTreeSet::new();
+                for request in &requests {
+                    touched.extend(self.shards_of(request));
+                }
+                match touched.len() {
+                    0 => (Vec::new(), known(Response::Multi(Vec::new()))),
+                    1 => (vec![(*touched.first().unwrap(), Request::Exec(requests))], Box::new(only)),
+                    _ => (Vec::new(), known(Response::Error(ErrorKind::CrossShard,
+                        "keys of a transaction must all belong to the same shard".to_string()))),
+                }
+            },
+            // every shard reports key events, the response of shard 0 counts the channel subscriptions too
+            request @ (Request::Watch(..) | Request::Unwatch(..) | Request::Disconnect(..)
+                | Request::Persist() | Request::ExpireSweep() | Request::Close() | Request::Clear()) =>
+                (self.everywhere(request), Box::new(|mut responses| {
+                    if let Some(failed) = responses.iter().position(|r| matches!(r, Response::Error(..))) {
+                        return responses.swap_remove(failed);
+                    }
+                    responses.into_iter().next().unwrap_or(Response::Ok())
+                })),
+            request => {
+                let shard = self.shards_of(&request).first().copied().unwrap_or(0);
+                (vec![(shard, request)], Box::new(only))
+            },
+        }
+    }
+
+    fn shard_of(&self, key: &str) -> usize {
+        shard_of(key, self.shards.len())
+    }
+
+    /// the shards a request has to run on
+    fn shards_of(&self, request: &Request) -> Vec<usize> {
+        match request {
+            Request::Set(key, _) | Request::SetEx(key, _, _) | Request::Get(key) | Request::Incr(key)
+            | Request::Decr(key) | Request::Append(key, _) | Request::Strlen(key) | Request::Expire(key, _)
+            | Request::Ttl(key) | Request::PersistKey(key) | Request::Cas(key, _, _) =>
+                vec![self.shard_of(key)],
+            Request::Del(keys) | Request::Exists(keys) | Request::MGet(keys) =>
+                keys.iter().map(|k| self.shard_of(k)).collect(),
+            Request::MSet(pairs) =>
+                pairs.iter().map(|(k, _)| self.shard_of(k)).collect(),
+            Request::Apply(records) =>
+                records.iter().map(|r| self.shard_of(r.key())).collect(),
+            Request::Exec(requests) =>
+                requests.iter().flat_map(|r| self.shards_of(r)).collect(),
+            Request::Publish(..) | Request::Subscribe(..) | Request::PSubscribe(..)
+            | Request::Unsubscribe(..) | Request::PUnsubscribe(..) =>
+                vec![0],
+            Request::Keys(_) | Request::Watch(..) | Request::Unwatch(..) | Request::Persist()
+            | Request::ExpireSweep() | Request::Disconnect(_) | Request::Close() | Request::Clear()
+            | Request::Dump() | Request::Usage() =>
+                (0..self.shards.len()).collect(),
+        }
+    }
+
+    fn split_keys(&self, keys: Vec<String>) -> BTreeMap<usize, Vec<String>> {
+        let mut groups: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for key in keys {
+            groups.entry(self.shard_of(&key)).or_default().push(key);
+        }
+        groups
+    }
+
+    fn mget(&self, keys: Vec<String>) -> (Vec<(usize, Request)>, Combine) {
+        // remember where each key came from to put the values back in request order
+        let mut positions: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        let mut groups: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for (position, key) in keys.into_iter().enumerate() {
+            let shard = self.shard_of(&key);
+            positions.entry(shard).or_default().push(position);
+            groups.entry(shard).or_default().push(key);
+        }
+
+        let count = positions.values().map(Vec::len).sum();
+        let parts = groups.into_iter().map(|(shard, keys)| (shard, Request::MGet(keys))).collect();
+        (parts, Box::new(move |responses| {
+            let mut values = vec![None; count];
+            for (response, positions) in responses.into_iter().zip(positions.into_values()) {
+                match response {
+                    Response::Values(part) => {
+                        for (position, value) in positions.into_iter().zip(part) {
+                            values[position] = value;
+                        }
+                    },
+                    other => return other,
+                }
+            }
+            Response::Values(values)
+        }))
+    }
+
+    /// the request once for every shard
+    fn everywhere(&self, request: Request) -> Vec<(usize, Request)> {
+        (0..self.shards.len()).map(|shard| (shard, request.clone())).collect()
+    }
+}
+
+/// turns the responses of the shards, in the order of the parts, into the response to the request
+type Combine = Box<dyn FnOnce(Vec<Response>) -> Response + Send>;
+
+/// A request sent to its shards, see `Service::send`.
+pub struct Pending {
+    responses: Vec<oneshot::Receiver<Response>>,
+    combine: Combine,
+    // the command and when it was sent, None if the service never saw the request
+    recorded: Option<(&'static str, Instant, Arc<Metrics>)>,
+}
+
+impl Pending {
+    /// a response known without asking the service, it is not counted in the metrics
+    pub fn ready(response: Response) -> Pending {
+        Pending { responses: Vec::new(), combine: known(response), recorded: None }
+    }
+
+    /// change the response once it arrives
+    pub fn map(self, f: impl FnOnce(Response) -> Response + Send + 'static) -> Pending {
+        let combine = self.combine;
+        Pending { combine: Box::new(move |responses| f(combine(responses))), ..self }
+    }
+
+    /// wait for the shards to respond, they work on their parts in parallel
+    pub async fn response(self) -> Result<Response, ServiceError> {
+        let response = collect(self.responses).await.map(self.combine);
+        if let Some((command, started, metrics)) = self.recorded {
+            let elapsed = started.elapsed();
+            metrics.record(command, elapsed);
+            debug!(command, ?elapsed, ok = response.is_ok(), "Request handled");
+        }
+        response
+    }
+}
+
+async fn collect(pending: Vec<oneshot::Receiver<Response>>) -> Result<Vec<Response>, ServiceError> {
+    let mut responses = Vec::with_capacity(pending.len());
+    for response_rx in pending {
+        responses.push(response_rx.await.map_err(|_| ServiceError::Unavailable)?);
+    }
+    Ok(responses)
+}
+
+fn known(response: Response) -> Combine {
+    Box::new(move |_| response)
+}
+
+/// the response of a request sent to a single shard
+fn only(responses: Vec<Response>) -> Response {
+    responses.into_iter().next().expect("a single part has a single response")
+}
+
+fn sum(responses: Vec<Response>) -> Response {
+    let mut total = 0;
+    for response in responses {
+        match response {
+            Response::Integer(n) => total += n,
+            other => return other,
+        }
+    }
+    Response::Integer(total)
+}
+
+fn first_error(responses: Vec<Response>) -> Option<Response> {
+    responses.into_iter().find(|r| matches!(r, Response::Error(..)))
+}
+
+/// the shard owning a key, FNV-1a keeps the assignment stable across builds and restarts
+pub fn shard_of(key: &str, shards: usize) -> usize {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    (hash % shards as u64) as usize
+}
+
+/// The snapshot and log file of one shard. A single shard uses the plain paths,
+/// otherwise the shard number and count go into the name, e.g. `kv_snapshot.2of4.dat`.
+pub fn shard_path(base: &Path, shard: usize, shards: usize) -> PathBuf {
+    if shards == 1 {
+        return base.to_path_buf();
+    }
+    let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+    match base.extension() {
+        Some(ext) => base.with_file_name(format!("{}.{}of{}.{}", stem, shard, shards, ext.to_string_lossy())),
+        None => base.with_file_name(format!("{}.{}of{}", stem, shard, shards)),
+    }
+}
+
+/// Move data persisted with a different shard count into the layout for `shards`,
+/// so changing the count does not hide keys. Old files are removed once the data
+/// is safely persisted in the new layout.
+pub fn migrate_layout(engine: Engine, files: &Files, shards: usize) -> io::Result<()> {
+    let mut old_layouts = BTreeSet::new();
+    for base in [&files.snapshot, &files.wal, &files.data] {
+        old_layouts.extend(find_layouts(base)?.into_iter().filter(|&count| count != shards));
+    }
+    if old_layouts.is_empty() {
+        return Ok(());
+    }
+
+    let mut targets = Vec::with_capacity(shards);
+    for shard in 0..shards {
+        targets.push(Store::open(engine, &files.shard(shard, shards))?);
+    }
+    for &count in &old_layouts {
+        info!("Migrating data from {} to {} shards", count, shards);
+        for shard in 0..count {
+            let old = Store::open(engine, &files.shard(shard, count))?;
+            for (key, entry) in old.entries()? {
+                let target = shard_of(&key, shards);
+                let mut records = vec![Record::Set(key.clone(), entry.value)];
+                if entry.expires_at.is_some() {
+                    records.push(Record::Expire(key, entry.expires_at));
+                }
+                targets[target].write(records)?;
+            }
+        }
+    }
+    // the records are already logged by the new shards, a failed snapshot loses nothing
+    for target in &mut targets {
+        target.persist();
+    }
+
+    for &count in &old_layouts {
+        for shard in 0..count {
+            let old = files.shard(shard, count);
+            for path in [&old.snapshot, &old.wal, &old.data] {
+                remove_if_exists(path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// shard counts of all layouts with files next to `base`
+fn find_layouts(base: &Path) -> io::Result<BTreeSet<usize>> {
+    let mut layouts = BTreeSet::new();
+    if base.exists() {
+        layouts.insert(1);
+    }
+    let dir = match base.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let stem = base.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let suffix = base.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        // <stem>.<shard>of<count><suffix>
+        let layout = name.strip_prefix(&stem)
+            .and_then(|rest| rest.strip_prefix('.'))
+            .and_then(|rest| rest.strip_suffix(&suffix))
+            .and_then(|rest| rest.split_once("of"))
+            .and_then(|(shard, count)| Some((shard.parse::<usize>().ok()?, count.parse::<usize>().ok()?)));
+        if let Some((shard, count)) = layout
+            && shard < count {
+            layouts.insert(count);
+        }
+    }
+    Ok(layouts)
+}
@@ -0,0 +1,46 @@
+use tokio::sync::{mpsc, watch};
+use tokio::time::{self, Duration};
+
+/// Held by every task that has to finish before the server exits.
+/// Tells the task when the shutdown starts; dropping it reports the task as finished.
+#[derive(Clone)]
+pub struct Shutdown {
+    started: watch::Receiver<bool>,
+    _alive: mpsc::Sender<()>,
+}
+
+impl Shutdown {
+    /// resolves once the shutdown has started, immediately if it already has
+    pub async fn wait(&mut self) {
+        // an error means the controller is gone, which only happens during shutdown as well
+        let _ = self.started.wait_for(|started| *started).await;
+    }
+}
+
+/// Starts the shutdown and waits for all `Shutdown` holders to finish.
+pub struct ShutdownController {
+    started: watch::Sender<bool>,
+    alive_tx: mpsc::Sender<()>,
+    alive_rx: mpsc::Receiver<()>,
+}
+
+impl ShutdownController {
+    pub fn new() -> ShutdownController {
+        let (started, _) = watch::channel(false);
+        // nothing is ever sent, the channel only tracks how many senders are left
+        let (alive_tx, alive_rx) = mpsc::channel(1);
+        ShutdownController { started, alive_tx, alive_rx }
+    }
+
+    pub fn subscribe(&self) -> Shutdown {
+        Shutdown { started: self.started.subscribe(), _alive: self.alive_tx.clone() }
+    }
+
+    /// signal the shutdown and wait at most `deadline` for all tasks, returns false on timeout
+    pub async fn shutdown(self, deadline: Duration) -> bool {
+        let ShutdownController { started, alive_tx, mut alive_rx } = self;
+        started.send_replace(true);
+        drop(alive_tx);
+        time::timeout(deadline, alive_rx.recv()).await.is_ok()
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::store::Entry;
+
+/// first line of every snapshot file, allows changing the format later
+const HEADER: &str = "KVSNAP 2";
+
+/// snapshots written before expiry deadlines were stored
+const HEADER_V1: &str = "KVSNAP 1";
+
+/// Load a snapshot written by `save`. A missing file is an empty store.
+pub fn load(path: &Path) -> io::Result<HashMap<String, Entry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let mut reader = BufReader::new(file);
+
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let with_expiry = match header.trim_end() {
+        HEADER => true,
+        HEADER_V1 => false,
+        other => return Err(invalid_data(format!("unknown snapshot header {:?}", other))),
+    };
+
+    // each entry: "<key length> <value length> <expiry deadline or ->\n<key><value>\n",
+    // version 1 entries lack the deadline
+    let mut storage = HashMap::new();
+    loop {
+        let mut lengths = String::new();
+        if reader.read_line(&mut lengths)? == 0 {
+            break;
+        }
+        let (key_len, value_len, expires_at) = parse_entry_header(&lengths, with_expiry)?;
+        let key = read_string(&mut reader, key_len)?;
+        let value = read_bytes(&mut reader, value_len)?;
+
+        let mut newline = [0u8; 1];
+        reader.read_exact(&mut newline)?;
+        if newline[0] != b'\n' {
+            return Err(invalid_data("entry not terminated by newline".to_string()));
+        }
+        storage.insert(key, Entry { value, expires_at });
+    }
+
+    Ok(storage)
+}
+
+/// Atomically write all entries: write a temp file next to `path`, sync it, rename it
+/// and sync the directory. The snapshot is durable once this returns.
+pub fn save(path: &Path, entries: &[(String, Entry)]) -> io::Result<()> {
+    let temp_path = temp_path(path);
+    {
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        writeln!(writer, "{}", HEADER)?;
+        for (key, entry) in entries {
+            match entry.expires_at {
+                Some(deadline) => writeln!(writer, "{} {} {}", key.len(), entry.value.len(), deadline)?,
+                None => writeln!(writer, "{} {} -", key.len(), entry.value.len())?,
+            }
+            writer.write_all(key.as_bytes())?;
+            writer.write_all(&entry.value)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.into_inner()?.sync_all()?;
+    }
+    rename_durably(&temp_path, path)
+}
+
+/// rename `from` to `to` and sync the directory, without that a crash can undo the rename
+pub(crate) fn rename_durably(from: &Path, to: &Path) -> io::Result<()> {
+    fs::rename(from, to)?;
+    let dir = match to.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+fn parse_entry_header(line: &str, with_expiry: bool) -> io::Result<(usize, usize, Option<u64>)> {
+    let bad_header = || invalid_data(format!("bad entry header {:?}", line.trim_end()));
+    let fields = line.split_whitespace().collect::<Vec<&str>>();
+    let (key_len, value_len, expires_at) = match (&fields[..], with_expiry) {
+        ([key_len, value_len], false) => (key_len, value_len, None),
+        ([key_len, value_len, "-"], true) => (key_len, value_len, None),
+        ([key_len, value_len, deadline], true) =>
+            (key_len, value_len, Some(deadline.parse().map_err(|_| bad_header())?)),
+        _ => return Err(bad_header()),
+    };
+    Ok((key_len.parse().map_err(|_| bad_header())?, value_len.parse().map_err(|_| bad_header())?, expires_at))
+}
+
+fn read_bytes(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut buffer = vec![0u8; len];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn read_string(reader: &mut impl Read, len: usize) -> io::Result<String> {
+    String::from_utf8(read_bytes(reader, len)?).map_err(|e| invalid_data(e.to_string()))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::ops::Bound;
+use std::path::Path;
+use crate::disk::DiskStorage;
+use crate::store::Entry;
+
+/// A key-value engine behind a `Store`.
+///
+/// Engines hold entries as they are, including expired ones; hiding and removing
+/// those is left to the store. Only engines that touch the disk can fail.
+pub trait Storage: Send {
+    fn get(&self, key: &str) -> io::Result<Option<Entry>>;
+
+    fn set(&mut self, key: String, entry: Entry) -> io::Result<()>;
+
+    /// remove a key, returns true if it was present
+    fn delete(&mut self, key: &str) -> io::Result<bool>;
+
+    /// entries with keys in the range, ordered by key if the engine keeps keys sorted
+    fn scan(&self, from: Bound<&str>, to: Bound<&str>) -> io::Result<Vec<(String, Entry)>>;
+
+    /// every entry, e.g. to write a snapshot
+    fn snapshot(&self) -> io::Result<Vec<(String, Entry)>> {
+        self.scan(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// rough number of bytes the engine holds in memory, visits every entry
+    fn memory(&self) -> usize;
+
+    /// keys having an expiry deadline, engines keeping values on disk can answer without reading them
+    fn deadlines(&self) -> io::Result<Vec<(String, u64)>> {
+        Ok(self.snapshot()?.into_iter()
+            .filter_map(|(key, entry)| entry.expires_at.map(|deadline| (key, deadline)))
+            .collect())
+    }
+
+    /// true if every write is durable on its own, otherwise the store keeps a snapshot and a log
+    fn is_durable(&self) -> bool {
+        false
+    }
+
+    /// reclaim space taken by overwritten and deleted entries
+    fn compact(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// the engines to choose from at startup
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    Memory,     // hash map, the fastest for single keys
+    Ordered,    // sorted map, prefix and range scans only visit matching keys
+    Disk,       // log-structured file with an index in memory, values stay on disk
+}
+
+impl Engine {
+    pub fn parse(name: &str) -> Option<Engine> {
+        match name {
+            "memory" => Some(Engine::Memory),
+            "ordered" => Some(Engine::Ordered),
+            "disk" => Some(Engine::Disk),
+            _ => None,
+        }
+    }
+
+    /// create the engine, `data_path` is only used by engines keeping their own file
+    pub fn open(&self, data_path: &Path) -> io::Result<Box<dyn Storage>> {
+        Ok(match self {
+            Engine::Memory => Box::new(MemoryStorage::default()),
+            Engine::Ordered => Box::new(OrderedStorage::default()),
+            Engine::Disk => Box::new(DiskStorage::open(data_path)?),
+        })
+    }
+}
+
+/// the range of all keys starting with `prefix`
+pub fn prefix_range(prefix: &str) -> (Bound<String>, Bound<String>) {
+    if prefix.is_empty() {
+        return (Bound::Unbounded, Bound::Unbounded);
+    }
+    // the end is the prefix with its last character incremented, dropping characters that can not be
+    let mut end: Vec<char> = prefix.chars().collect();
+    while let Some(last) = end.pop() {
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            end.push(next);
+            return (Bound::Included(prefix.to_string()), Bound::Excluded(end.into_iter().collect()));
+        }
+    }
+    (Bound::Included(prefix.to_string()), Bound::Unbounded)
+}
+
+fn in_range(key: &str, from: Bound<&str>, to: Bound<&str>) -> bool {
+    let after_start = match from {
+        Bound::Included(from) => key >= from,
+        Bound::Excluded(from) => key > from,
+        Bound::Unbounded => true,
+    };
+    let before_end = match to {
+        Bound::Included(to) => key <= to,
+        Bound::Excluded(to) => key < to,
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    map: HashMap<String, Entry>,
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &str) -> io::Result<Option<Entry>> {
+        Ok(self.map.get(key).cloned())
+    }
+
+    fn set(&mut self, key: String, entry: Entry) -> io::Result<()> {
+        self.map.insert(key, entry);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &str) -> io::Result<bool> {
+        Ok(self.map.remove(key).is_some())
+    }
+
+    fn scan(&self, from: Bound<&str>, to: Bound<&str>) -> io::Result<Vec<(String, Entry)>> {
+        Ok(self.map.iter()
+            .filter(|(key, _)| in_range(key, from, to))
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect())
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn memory(&self) -> usize {
+        self.map.iter().map(|(key, entry)| entry_size(key, entry)).sum()
+    }
+}
+
+#[derive(Default)]
+pub struct OrderedStorage {
+    map: BTreeMap<String, Entry>,
+}
+
+impl Storage for OrderedStorage {
+    fn get(&self, key: &str) -> io::Result<Option<Entry>> {
+        Ok(self.map.get(key).cloned())
+    }
+
+    fn set(&mut self, key: String, entry: Entry) -> io::Result<()> {
+        self.map.insert(key, entry);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &str) -> io::Result<bool> {
+        Ok(self.map.remove(key).is_some())
+    }
+
+    fn scan(&self, from: Bound<&str>, to: Bound<&str>) -> io::Result<Vec<(String, Entry)>> {
+        if !is_valid_range(from, to) {
+            return Ok(Vec::new());
+        }
+        Ok(self.map.range::<str, _>((from, to))
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect())
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn memory(&self) -> usize {
+        self.map.iter().map(|(key, entry)| entry_size(key, entry)).sum()
+    }
+}
+
+/// the heap and map slot taken by an entry, ignoring the unused capacity of the map
+fn entry_size(key: &str, entry: &Entry) -> usize {
+    size_of::<(String, Entry)>() + key.len() + entry.value.len()
+}
+
+/// `BTreeMap::range` panics on a start after the end, an empty range is what callers expect
+pub fn is_valid_range(from: Bound<&str>, to: Bound<&str>) -> bool {
+    match (from, to) {
+        (Bound::Included(from), Bound::Included(to)) => from <= to,
+        (Bound::Included(from) | Bound::Excluded(from), Bound::Included(to) | Bound::Excluded(to)) => from < to,
+        _ => true,
+    }
+}
@@ -0,0 +1,359 @@
+use std::fs;
+use std::io;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use crate::disk::DiskStorage;
+use crate::metrics::{Persisted, Usage};
+use crate::replication::Hub;
+use crate::shard::shard_path;
+use crate::snapshot;
+use crate::storage::{self, Engine, MemoryStorage, Storage};
+use crate::wal::{Record, Wal};
+
+/// a stored value with its optional expiry deadline in milliseconds since the unix epoch
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub value: Vec<u8>,
+    pub expires_at: Option<u64>,
+}
+
+impl Entry {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|deadline| deadline <= now)
+    }
+}
+
+/// a change of a key reported to watchers
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyEvent {
+    pub kind: EventKind,
+    pub key: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    Set,        // the value was written
+    Del,        // the key was deleted
+    Expire,     // an expiry deadline was set
+    Persist,    // the expiry deadline was removed
+    Expired,    // the key reached its deadline and is gone
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Set => "set",
+            EventKind::Del => "del",
+            EventKind::Expire => "expire",
+            EventKind::Persist => "persist",
+            EventKind::Expired => "expired",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<EventKind> {
+        [EventKind::Set, EventKind::Del, EventKind::Expire, EventKind::Persist, EventKind::Expired]
+            .into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+/// the files a store may use, which ones depends on the engine
+#[derive(Debug, Clone)]
+pub struct Files {
+    pub snapshot: PathBuf,  // last persisted state of an engine in memory
+    pub wal: PathBuf,       // mutations of an engine in memory since the snapshot
+    pub data: PathBuf,      // the log-structured file of the disk engine
+}
+
+impl Files {
+    /// the files of one of `shards` shards
+    pub fn shard(&self, shard: usize, shards: usize) -> Files {
+        Files {
+            snapshot: shard_path(&self.snapshot, shard, shards),
+            wal: shard_path(&self.wal, shard, shards),
+            data: shard_path(&self.data, shard, shards),
+        }
+    }
+}
+
+/// snapshot and write-ahead log making an engine in memory durable
+struct Journal {
+    wal: Wal,
+    snapshot_path: PathBuf,
+    // true if the engine differs from the last snapshot written
+    dirty: bool,
+}
+
+/// The key-value engine together with what makes it durable.
+/// Every mutation goes through `write`: engines in memory log it before it becomes visible,
+/// durable engines write it themselves.
+/// Expired entries are invisible to readers and removed by `remove_if_expired` or `sweep`.
+pub struct Store {
+    storage: Box<dyn Storage>,
+    // None if the engine is durable on its own
+    journal: Option<Journal>,
+    // changes not yet collected by `take_events`
+    events: Vec<KeyEvent>,
+    // receives every record written, for followers
+    hub: Option<Arc<Hub>>,
+    // the last snapshot written, durable engines never write one
+    last_persist: Option<Persisted>,
+}
+
+impl Store {
+    /// Open the engine with the data persisted last. Data left behind by an engine
+    /// of the other kind, on disk or in memory, is moved over first.
+    pub fn open(engine: Engine, files: &Files) -> io::Result<Store> {
+        let mut storage = engine.open(&files.data)?;
+        let mut store = if storage.is_durable() {
+            if files.snapshot.exists() || files.wal.exists() {
+                let entries = load_journal(&files.snapshot, &files.wal)?;
+                info!("Moving {} keys from {} into {}", entries.len(), files.snapshot.display(), files.data.display());
+                for (key, entry) in entries {
+                    storage.set(key, entry)?;
+                }
+                remove_if_exists(&files.snapshot)?;
+                remove_if_exists(&files.wal)?;
+            }
+            Store { storage, journal: None, events: Vec::new(), hub: None, last_persist: None }
+        } else {
+            for (key, entry) in snapshot::load(&files.snapshot)? {
+                storage.set(key, entry)?;
+            }
+            info!("Loaded {} keys from {}", storage.len(), files.snapshot.display());
+
+            let (wal, records) = Wal::open(&files.wal)?;
+            info!("Replaying {} records from {}", records.len(), files.wal.display());
+            let mut dirty = !records.is_empty();
+            for record in records {
+                apply(storage.as_mut(), record)?;
+            }
+
+            let moved = files.data.exists();
+            if moved {
+                let entries = DiskStorage::open(&files.data)?.snapshot()?;
+                info!("Moving {} keys from {} into {}", entries.len(), files.data.display(), files.snapshot.display());
+                for (key, entry) in entries {
+                    storage.set(key, entry)?;
+                }
+                dirty = true;
+            }
+
+            let journal = Journal { wal, snapshot_path: files.snapshot.clone(), dirty };
+            let mut store = Store { storage, journal: Some(journal), events: Vec::new(), hub: None, last_persist: None };
+            if moved {
+                // the data file is only removed once its content is in a snapshot
+                store.persist();
+                if store.journal.as_ref().is_some_and(|j| j.dirty) {
+                    return Err(io::Error::other("unable to persist the keys moved from the disk engine"));
+                }
+                remove_if_exists(&files.data)?;
+            }
+            store
+        };
+
+        store.sweep()?;
+        store.events.clear();
+        Ok(store)
+    }
+
+    /// pass every record written from now on to the hub
+    pub fn replicate_to(&mut self, hub: Arc<Hub>) {
+        self.hub = Some(hub);
+    }
+
+    fn entry(&self, key: &str) -> io::Result<Option<Entry>> {
+        let now = now_millis();
+        Ok(self.storage.get(key)?.filter(|e| !e.is_expired(now)))
+    }
+
+    pub fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.entry(key)?.map(|e| e.value))
+    }
+
+    pub fn contains(&self, key: &str) -> io::Result<bool> {
+        Ok(self.entry(key)?.is_some())
+    }
+
+    /// live keys starting with `prefix`, engines with sorted keys only visit those
+    pub fn keys(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let now = now_millis();
+        let (from, to) = storage::prefix_range(prefix);
+        Ok(self.storage.scan(as_str(&from), as_str(&to))?.into_iter()
+            .filter(|(_, e)| !e.is_expired(now))
+            .map(|(k, _)| k)
+            .collect())
+    }
+
+    /// all live keys with their values and deadlines
+    pub fn entries(&self) -> io::Result<Vec<(String, Entry)>> {
+        let now = now_millis();
+        Ok(self.storage.snapshot()?.into_iter().filter(|(_, e)| !e.is_expired(now)).collect())
+    }
+
+    /// expiry deadline of a live key, `None` for missing keys
+    pub fn expires_at(&self, key: &str) -> io::Result<Option<Option<u64>>> {
+        Ok(self.entry(key)?.map(|e| e.expires_at))
+    }
+
+    /// Log the records as one batch and apply them, nothing is applied if logging fails.
+    /// A durable engine writes record by record, a failure leaves the earlier ones applied.
+    pub fn write(&mut self, records: Vec<Record>) -> io::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.wal.append(&records)?;
+            journal.dirty = true;
+        }
+        for (applied, record) in records.iter().enumerate() {
+            if let Err(e) = apply(self.storage.as_mut(), record.clone()) {
+                self.replicate(&records[..applied]);
+                return Err(e);
+            }
+            self.events.push(event_for(record));
+        }
+        self.replicate(&records);
+        Ok(())
+    }
+
+    fn replicate(&self, records: &[Record]) {
+        if let Some(hub) = &self.hub {
+            hub.push(records);
+        }
+    }
+
+    /// lazily drop a key whose deadline has passed, returns true if it was removed.
+    /// No log record is needed: replaying the deadline expires the key again.
+    pub fn remove_if_expired(&mut self, key: &str) -> io::Result<bool> {
+        let now = now_millis();
+        if self.storage.get(key)?.is_some_and(|e| e.is_expired(now)) {
+            self.storage.delete(key)?;
+            self.events.push(KeyEvent { kind: EventKind::Expired, key: key.to_string() });
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// actively drop all keys whose deadline has passed, returns the removed keys
+    pub fn sweep(&mut self) -> io::Result<Vec<String>> {
+        let now = now_millis();
+        let expired: Vec<String> = self.storage.deadlines()?.into_iter()
+            .filter(|&(_, deadline)| deadline <= now)
+            .map(|(k, _)| k)
+            .collect();
+        for key in &expired {
+            self.storage.delete(key)?;
+            self.events.push(KeyEvent { kind: EventKind::Expired, key: key.clone() });
+        }
+        Ok(expired)
+    }
+
+    /// the changes made since the last call, in order
+    pub fn take_events(&mut self) -> Vec<KeyEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn usage(&self) -> Usage {
+        Usage { keys: self.storage.len(), memory: self.storage.memory(), last_persist: self.last_persist }
+    }
+
+    /// Write a snapshot if anything changed and compact the log it covers,
+    /// only a successful write makes the store clean again.
+    /// A durable engine has nothing to write and only compacts its file.
+    pub fn persist(&mut self) {
+        let Some(journal) = &mut self.journal else {
+            if let Err(e) = self.storage.compact() {
+                warn!("Compacting storage failed: {}", e);
+            }
+            return;
+        };
+        if !journal.dirty {
+            return;
+        }
+        let started = Instant::now();
+        let saved = self.storage.snapshot()
+            .and_then(|entries| snapshot::save(&journal.snapshot_path, &entries).map(|()| entries.len()));
+        match saved {
+            Ok(count) => {
+                info!("Persisted {} keys to {}", count, journal.snapshot_path.display());
+                self.last_persist = Some(Persisted { at: now_millis(), duration: started.elapsed() });
+            },
+            Err(e) => {
+                warn!("Persisting to {} failed: {}", journal.snapshot_path.display(), e);
+                return;
+            },
+        }
+        journal.dirty = false;
+
+        // `save` has synced the snapshot and its directory entry, only now may the log be emptied.
+        // a failed compaction is harmless: replaying records already in the snapshot yields the same state
+        if let Err(e) = journal.wal.compact() {
+            warn!("Compacting write-ahead log failed: {}", e);
+        }
+    }
+}
+
+/// the entries persisted by an engine in memory
+fn load_journal(snapshot_path: &Path, wal_path: &Path) -> io::Result<Vec<(String, Entry)>> {
+    let mut storage = MemoryStorage::default();
+    for (key, entry) in snapshot::load(snapshot_path)? {
+        storage.set(key, entry)?;
+    }
+    for record in Wal::open(wal_path)?.1 {
+        apply(&mut storage, record)?;
+    }
+    storage.snapshot()
+}
+
+fn as_str(bound: &Bound<String>) -> Bound<&str> {
+    match bound {
+        Bound::Included(s) => Bound::Included(s),
+        Bound::Excluded(s) => Bound::Excluded(s),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+pub fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn event_for(record: &Record) -> KeyEvent {
+    let (kind, key) = match record {
+        Record::Set(key, _) => (EventKind::Set, key),
+        Record::Del(key) => (EventKind::Del, key),
+        Record::Expire(key, Some(_)) => (EventKind::Expire, key),
+        Record::Expire(key, None) => (EventKind::Persist, key),
+    };
+    KeyEvent { kind, key: key.clone() }
+}
+
+/// apply a logged mutation to the engine
+fn apply(storage: &mut dyn Storage, record: Record) -> io::Result<()> {
+    match record {
+        Record::Set(key, value) => {
+            // like in redis, overwriting a value clears its expiry
+            storage.set(key, Entry { value, expires_at: None })
+        },
+        Record::Del(key) => {
+            storage.delete(&key).map(|_| ())
+        },
+        Record::Expire(key, deadline) => {
+            match storage.get(&key)? {
+                Some(entry) => storage.set(key, Entry { expires_at: deadline, ..entry }),
+                None => Ok(()),
+            }
+        },
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
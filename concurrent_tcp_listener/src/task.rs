@@ -0,0 +1,230 @@
+//! The storage task: owns one shard of the keys and its subscriptions, and handles
+//! the requests sent to it one at a time.
+
+use std::io;
+use std::sync::Arc;
+use tokio::sync::mpsc::Receiver;
+use tracing::{debug, info};
+use crate::glob;
+use crate::pubsub::{Kind, PubSub};
+use crate::replication::Hub;
+use crate::store::{self, Store};
+use crate::wal::Record;
+use crate::{ErrorKind, Request, RequestTransport, Response};
+
+/// Serve the requests arriving on `rx` from `store` until `Request::Close`, then write the final snapshot.
+pub async fn handle_single_request(mut rx: Receiver<RequestTransport>, mut store: Store, hub: Arc<Hub>) {
+    store.replicate_to(hub);
+    let mut pubsub = PubSub::default();
+
+    while let Some((command, response_channel)) = rx.recv().await {
+        // the sweep runs every second, only report what it removes
+        if !matches!(command, Request::ExpireSweep()) {
+            debug!("Service received: {:?}", command);
+        }
+        if let Request::Close() = command {
+            // the final snapshot is written after the remaining requests are processed
+            rx.close();
+        }
+        let response = execute(command, &mut store, &mut pubsub);
+        for event in store.take_events() {
+            pubsub.notify(&event);
+        }
+        // the client may have disconnected while waiting, its response is simply dropped
+        let _ = response_channel.send(response);
+    }
+
+    store.persist();
+    info!("Service is finished");
+}
+
+/// execute a request against the storage and the subscriptions
+fn execute(request: Request, store: &mut Store, pubsub: &mut PubSub) -> Response {
+    run(request, store, pubsub)
+        .unwrap_or_else(|e| Response::Error(ErrorKind::Storage, format!("storage failed: {}", e)))
+}
+
+fn run(request: Request, store: &mut Store, pubsub: &mut PubSub) -> io::Result<Response> {
+    Ok(match request {
+        Request::Set(key, value) => {
+            write(store, vec![Record::Set(key, value)], Response::Ok())?
+        },
+        Request::SetEx(key, value, seconds) => {
+            let deadline = deadline_in(seconds);
+            write(store, vec![Record::Set(key.clone(), value), Record::Expire(key, Some(deadline))], Response::Ok())?
+        },
+        Request::Get(key) => {
+            store.remove_if_expired(&key)?;
+            store.get(&key)?
+                .map(Response::Result)
+                .unwrap_or(Response::NotFound(key))
+        },
+        Request::Del(mut keys) => {
+            // a key given twice is only removed once
+            keys.sort();
+            keys.dedup();
+            let mut records = Vec::new();
+            for key in keys {
+                if store.contains(&key)? {
+                    records.push(Record::Del(key));
+                }
+            }
+            let count = records.len() as i64;
+            write(store, records, Response::Integer(count))?
+        },
+        Request::Exists(keys) => {
+            let mut count = 0;
+            for key in &keys {
+                if store.contains(key)? {
+                    count += 1;
+                }
+            }
+            Response::Integer(count)
+        },
+        Request::Keys(pattern) => {
+            // only keys starting with the literal part of the pattern can match
+            let prefix = pattern.split(['*', '?', '[', '\\']).next().unwrap_or_default();
+            let mut keys: Vec<String> = store.keys(prefix)?.into_iter()
+                .filter(|k| glob::matches(&pattern, k))
+                .collect();
+            keys.sort();
+            Response::List(keys)
+        },
+        Request::Incr(key) => increment(store, key, 1)?,
+        Request::Decr(key) => increment(store, key, -1)?,
+        Request::Append(key, value) => {
+            let mut new_value = store.get(&key)?.unwrap_or_default();
+            new_value.extend_from_slice(&value);
+            let len = new_value.len() as i64;
+            let records = keeping_expiry(store, key, new_value)?;
+            write(store, records, Response::Integer(len))?
+        },
+        Request::Strlen(key) => {
+            Response::Integer(store.get(&key)?.map_or(0, |v| v.len()) as i64)
+        },
+        Request::MSet(pairs) => {
+            let records = pairs.into_iter().map(|(k, v)| Record::Set(k, v)).collect();
+            write(store, records, Response::Ok())?
+        },
+        Request::MGet(keys) => {
+            Response::Values(keys.iter().map(|k| store.get(k)).collect::<io::Result<_>>()?)
+        },
+        Request::Expire(key, seconds) => {
+            if store.contains(&key)? {
+                write(store, vec![Record::Expire(key, Some(deadline_in(seconds)))], Response::Integer(1))?
+            } else {
+                Response::Integer(0)
+            }
+        },
+        Request::Ttl(key) => {
+            let ttl = match store.expires_at(&key)? {
+                None => -2,
+                Some(None) => -1,
+                // round up so a key is never reported with 0 seconds left while still alive
+                Some(Some(deadline)) => deadline.saturating_sub(store::now_millis()).div_ceil(1000) as i64,
+            };
+            Response::Integer(ttl)
+        },
+        Request::PersistKey(key) => {
+            match store.expires_at(&key)? {
+                Some(Some(_)) => write(store, vec![Record::Expire(key, None)], Response::Integer(1))?,
+                _ => Response::Integer(0),
+            }
+        },
+        Request::Cas(key, expected, new) => {
+            match store.get(&key)? {
+                None => Response::NotFound(key),
+                Some(current) if current == expected => {
+                    let records = keeping_expiry(store, key, new)?;
+                    write(store, records, Response::Ok())?
+                },
+                Some(current) => Response::Result(current),
+            }
+        },
+        Request::Exec(requests) => {
+            // nothing else runs in between, the service only handles one request at a time
+            Response::Multi(requests.into_iter().map(|r| execute(r, store, pubsub)).collect())
+        },
+        Request::Publish(channel, message) => {
+            Response::Integer(pubsub.publish(&channel, &message) as i64)
+        },
+        Request::Subscribe(client, outbox, channels) => {
+            Response::Integer(pubsub.subscribe(client, outbox, channels, Kind::Channel) as i64)
+        },
+        Request::PSubscribe(client, outbox, patterns) => {
+            Response::Integer(pubsub.subscribe(client, outbox, patterns, Kind::Pattern) as i64)
+        },
+        Request::Unsubscribe(client, channels) => {
+            Response::Integer(pubsub.unsubscribe(client, channels, Kind::Channel) as i64)
+        },
+        Request::PUnsubscribe(client, patterns) => {
+            Response::Integer(pubsub.unsubscribe(client, patterns, Kind::Pattern) as i64)
+        },
+        Request::Watch(client, outbox, keys) => {
+            Response::Integer(pubsub.subscribe(client, outbox, keys, Kind::Watch) as i64)
+        },
+        Request::Unwatch(client, keys) => {
+            Response::Integer(pubsub.unsubscribe(client, keys, Kind::Watch) as i64)
+        },
+
+        // Maintenance requests
+        Request::Close() => Response::Ok(),
+        Request::Apply(records) => write(store, records, Response::Ok())?,
+        Request::Clear() => {
+            let records = store.entries()?.into_iter().map(|(key, _)| Record::Del(key)).collect();
+            write(store, records, Response::Ok())?
+        },
+        Request::Dump() => Response::Entries(store.entries()?),
+        Request::Usage() => Response::Usage(store.usage()),
+        Request::Persist() => {
+            store.persist();
+            Response::Ok()
+        },
+        Request::Disconnect(client) => {
+            pubsub.disconnect(client);
+            Response::Ok()
+        },
+        Request::ExpireSweep() => {
+            let expired = store.sweep()?;
+            if !expired.is_empty() {
+                info!("Expired {} keys", expired.len());
+            }
+            Response::Ok()
+        }
+    })
+}
+
+/// log and apply the records, answering with `response` once they are durable
+fn write(store: &mut Store, records: Vec<Record>, response: Response) -> io::Result<Response> {
+    store.write(records)?;
+    Ok(response)
+}
+
+/// add `delta` to an integer value, a missing key counts as 0
+fn increment(store: &mut Store, key: String, delta: i64) -> io::Result<Response> {
+    let current = match store.get(&key)?.map(|v| std::str::from_utf8(&v).ok().and_then(|v| v.parse::<i64>().ok())) {
+        None => 0,
+        Some(Some(n)) => n,
+        Some(None) => return Ok(Response::Error(ErrorKind::WrongType, format!("value of '{}' is not an integer", key))),
+    };
+    match current.checked_add(delta) {
+        Some(n) => {
+            let records = keeping_expiry(store, key, n.to_string().into_bytes())?;
+            write(store, records, Response::Integer(n))
+        },
+        None => Ok(Response::Error(ErrorKind::Overflow, format!("value of '{}' would overflow", key))),
+    }
+}
+
+/// records replacing a value without touching its expiry, `Set` alone would clear it
+fn keeping_expiry(store: &Store, key: String, value: Vec<u8>) -> io::Result<Vec<Record>> {
+    Ok(match store.expires_at(&key)? {
+        Some(Some(deadline)) => vec![Record::Set(key.clone(), value), Record::Expire(key, Some(deadline))],
+        _ => vec![Record::Set(key, value)],
+    })
+}
+
+/// absolute deadline `seconds` from now in unix milliseconds
+fn deadline_in(seconds: u64) -> u64 {
+    store::now_millis().saturating_add(seconds.saturating_mul(1000))
+}
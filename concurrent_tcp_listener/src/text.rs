@@ -0,0 +1,398 @@
+//! The line based text protocol.
+//!
+//! Every request is one line: a command followed by whitespace separated arguments.
+//! Keys and values with whitespace, line breaks or any other bytes are put in double quotes
+//! with escapes, as in `set "my key" "two\r\nlines"`, see `escape`. Keys have to be UTF-8,
+//! values may be any bytes. Keys and channels in replies and pushes are quoted the same way.
+//! Every reply is one of:
+//!
+//! ```text
+//! +OK                      success without a value
+//! +QUEUED                  the command will run on exec
+//! $<len> <value>           a value of <len> bytes, sent as they are
+//! $-1                      no value (only inside a list)
+//! :<integer>               a number
+//! *<count>                 a list or the results of exec, followed by <count> replies
+//! NOTFOUND <key>           the requested key does not exist
+//! -ERR <CODE> <message>    an error, <CODE> is one of BADREQUEST, WRONGTYPE,
+//!                          OVERFLOW, STORAGE, EXECABORT, UNAVAILABLE, CROSSSHARD,
+//!                          REDIRECT, NOAUTH, NOPERM, BUSY, TOOLONG, RATELIMITED
+//! ```
+//!
+//! each terminated by CRLF. While subscribed or watching, pushes may arrive between replies:
+//!
+//! ```text
+//! >message <channel> $<len> <payload>
+//! >pmessage <pattern> <channel> $<len> <payload>
+//! >event <set|del|expire|persist|expired> <key>
+//! >lagged <count> events dropped       the connection fell behind, pushes were lost
+//! ```
+//!
+//! Before the server closes a connection on shutdown it sends `>shutdown server is going away`.
+//! Followers of this server open their replication stream with `sync`, see `replication`.
+//! A server running as a follower rejects writes with `-ERR REDIRECT <leader address>`.
+//! A server started with `--users` expects `auth <name> <password>` first, see `auth`.
+//! `stats` replies with the counters of `limits` as `name:value` lines, `info` with those and
+//! everything else described in `metrics`. A request longer than `max-line-length` is answered
+//! with `-ERR TOOLONG` and closes the connection, requests beyond the `rate-limit` are answered
+//! with `-ERR RATELIMITED`.
+//!
+//! Requests may be pipelined: all complete lines a client sent are passed on to the service
+//! before the first reply is awaited, the replies are written in the order of the requests.
+//! Each request sees the effects of the ones before it.
+//! The legacy format sends `consumed N bytes` and the Debug
+//! rendering of the response instead, it is only kept for existing clients.
+
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::Poll;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::sync::mpsc::Receiver;
+use tracing::{info, info_span, Instrument, Span};
+use crate::auth::Login;
+use crate::error::ServiceError;
+use crate::escape;
+use crate::limits::{sleep_until, Limits, Stats};
+use crate::metrics;
+use crate::pubsub::{self, ClientId, Outbox, Push};
+use crate::replication;
+use crate::request::{parse_joined_command, text};
+use crate::shard::{Pending, Service};
+use crate::shutdown::Shutdown;
+use crate::tls::Connection;
+use crate::transaction::Transaction;
+use crate::{next_client_id, next_request_id, parse_command, send_request_and_wait_for_response, ErrorKind, Request, Response};
+
+/// requests passed on to the service at most before their replies are written
+const PIPELINE_DEPTH: usize = 128;
+
+/// how replies are rendered on a text connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplyFormat {
+    Text,
+    Legacy,
+}
+
+pub async fn handle_client_connection(socket: Connection, peer: String, tx: &Service, format: ReplyFormat,
+                                      login: Login, limits: Limits, shutdown: Shutdown) {
+    info!("Connection from {}", peer);
+    let (outbox, pushes) = pubsub::outbox();
+    let mut session = Session { client: next_client_id(), outbox, transaction: Transaction::default(), login, limits };
+
+    match serve(socket, &mut session, pushes, tx, format, shutdown).await {
+        Ok(()) => info!("Connection from {} closed", peer),
+        Err(e) => info!("Connection from {} dropped: {}", peer, e),
+    }
+
+    // the service may already be gone during shutdown, then there is nothing left to clean up
+    let _ = send_request_and_wait_for_response(Request::Disconnect(session.client), tx).await;
+}
+
+async fn serve(socket: Connection, session: &mut Session, mut pushes: Receiver<Push>, tx: &Service,
+               format: ReplyFormat, mut shutdown: Shutdown) -> Result<(), ServiceError> {
+    let limits = session.limits.clone();
+    let mut rate = limits.rate_limiter();
+    let mut stream = BufStream::new(socket);
+    // read_until keeps a partial line here if a push interrupts reading
+    let mut line = Vec::new();
+    // replies to requests already sent to the service, in the order of the requests
+    let mut replies = VecDeque::new();
+
+    loop {
+        // every request and push restarts the idle timeout
+        let idle_at = limits.idle_deadline();
+        // one byte more than allowed tells a line that is too long from one that is just long enough
+        let room = (limits.max_line_length + 1 - line.len()) as u64;
+        tokio::select! {
+            result = read_line(&mut stream, &mut line, room) => {
+                let nr_bytes = result?;
+                if line.len() > limits.max_line_length {
+                    write_replies(&mut stream, &mut replies, format).await?;
+                    Stats::count(&limits.stats.oversized);
+                    let e = ServiceError::TooLong(limits.max_line_length);
+                    stream.write_all(&error(ErrorKind::TooLong.code(), &e.to_string())).await?;
+                    stream.flush().await?;
+                    return Err(e);
+                }
+                if nr_bytes == 0 {
+                    write_replies(&mut stream, &mut replies, format).await?;
+                    if format == ReplyFormat::Legacy {
+                        stream.write_all(b"bye\r\n").await?;
+                        stream.flush().await?;
+                    }
+                    return Ok(());
+                }
+                if format == ReplyFormat::Legacy {
+                    stream.write_all(format!("consumed {} bytes\r\n", line.len()).as_bytes()).await?;
+                }
+
+                if let Ok(text) = std::str::from_utf8(&line)
+                    && replication::is_sync(text) {
+                    write_replies(&mut stream, &mut replies, format).await?;
+                    if let Err(response) = session.login.may_replicate() {
+                        stream.write_all(&encode(&response)).await?;
+                        stream.flush().await?;
+                        return Ok(());
+                    }
+                    // the connection belongs to a follower from now on
+                    let text = text.to_string();
+                    return replication::serve_follower(stream, &text, tx, shutdown).await;
+                }
+                let result = if !rate.allow() {
+                    Stats::count(&limits.stats.rate_limited);
+                    let message = format!("more than {} requests per second", limits.rate_limit.unwrap_or_default());
+                    Ok(Reply::Ready(render(&Response::Error(ErrorKind::RateLimited, message), format)))
+                } else {
+                    let span = info_span!("request", id = next_request_id());
+                    handle_line(&line, session, tx, format).instrument(span).await
+                };
+                line.clear();
+                let written = match result {
+                    Ok(reply) => {
+                        replies.push_back(reply);
+                        // send on the requests the client already sent before waiting for a response
+                        if format == ReplyFormat::Text && replies.len() < PIPELINE_DEPTH && has_line(&mut stream).await {
+                            continue;
+                        }
+                        write_replies(&mut stream, &mut replies, format).await
+                    },
+                    // the replies to the requests before still reach the client
+                    Err(e) => write_replies(&mut stream, &mut replies, format).await.and(Err(e)),
+                };
+                if let Err(e) = written {
+                    // tell the client why it is disconnected, if it still listens
+                    if !matches!(e, ServiceError::Io(_)) {
+                        let _ = stream.write_all(&error(ErrorKind::Unavailable.code(), &e.to_string())).await;
+                        let _ = stream.flush().await;
+                    }
+                    return Err(e);
+                }
+            },
+            Some(push) = pushes.recv() => {
+                // replies to requests read before the push go out first
+                write_replies(&mut stream, &mut replies, format).await?;
+                write_push(&mut stream, &push, format).await?;
+            },
+            _ = sleep_until(idle_at) => {
+                Stats::count(&limits.stats.idle_timeouts);
+                return Err(ServiceError::Idle(limits.idle_timeout.unwrap_or_default()));
+            },
+            _ = shutdown.wait() => {
+                write_replies(&mut stream, &mut replies, format).await?;
+                write_push(&mut stream, &Push::Shutdown, format).await?;
+                return Ok(());
+            },
+        }
+    }
+}
+
+/// read until the end of the line or `room` more bytes, whichever comes first
+async fn read_line(stream: &mut BufStream<Connection>, line: &mut Vec<u8>, room: u64) -> std::io::Result<usize> {
+    stream.take(room).read_until(b'\n', line).await
+}
+
+/// true if a whole request line is buffered or can be read without waiting
+async fn has_line(stream: &mut BufStream<Connection>) -> bool {
+    poll_fn(|cx| Poll::Ready(match Pin::new(&mut *stream).poll_fill_buf(cx) {
+        Poll::Ready(Ok(buffered)) => buffered.contains(&b'\n'),
+        _ => false,
+    })).await
+}
+
+/// the reply to a request line
+enum Reply {
+    Ready(Vec<u8>),
+    // the response of the service is still to come, it is awaited in the span of its request
+    Pending(Pending, Span),
+}
+
+/// write the replies in the order of their requests, waiting for the responses still pending
+async fn write_replies(stream: &mut BufStream<Connection>, replies: &mut VecDeque<Reply>, format: ReplyFormat)
+    -> Result<(), ServiceError> {
+    if replies.is_empty() {
+        return Ok(());
+    }
+    while let Some(reply) = replies.pop_front() {
+        let reply = match reply {
+            Reply::Ready(reply) => reply,
+            Reply::Pending(pending, span) => render(&pending.response().instrument(span).await?, format),
+        };
+        stream.write_all(&reply).await?;
+    }
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn write_push(stream: &mut BufStream<Connection>, push: &Push, format: ReplyFormat) -> Result<(), ServiceError> {
+    let message = match format {
+        ReplyFormat::Text => encode_push(push),
+        ReplyFormat::Legacy => format!("push: {:?}\r\n", push).into_bytes(),
+    };
+    stream.write_all(&message).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// state of a single connection
+struct Session {
+    client: ClientId,
+    outbox: Outbox,
+    transaction: Transaction,
+    login: Login,
+    limits: Limits,
+}
+
+/// process a single request line, requests for the service are sent without waiting for the response
+async fn handle_line(line: &[u8], session: &mut Session, tx: &Service, format: ReplyFormat) -> Result<Reply, ServiceError> {
+    let parts = match escape::split(line) {
+        Ok(parts) => parts,
+        Err(message) => return Ok(Reply::Ready(bad_request(&message, format))),
+    };
+    let name = parts.first().map(|n| String::from_utf8_lossy(n).to_ascii_lowercase()).unwrap_or_default();
+    let names = || parts[1..].iter().map(|p| text(p)).collect::<Result<Vec<String>, String>>();
+    if name == "auth" && parts.len() == 3 {
+        let response = session.login.auth(&String::from_utf8_lossy(&parts[1]), &String::from_utf8_lossy(&parts[2])).await;
+        return Ok(Reply::Ready(render(&response, format)));
+    }
+    if (name == "stats" || name == "info") && parts.len() == 1 {
+        let response = match session.login.may_inspect() {
+            Ok(()) if name == "stats" => Response::Result(session.limits.report().into_bytes()),
+            Ok(()) => Response::Result(metrics::info(tx, &session.limits).await?.into_bytes()),
+            Err(response) => response,
+        };
+        return Ok(Reply::Ready(render(&response, format)));
+    }
+    let (client, outbox) = (session.client, &session.outbox);
+
+    let response = match name.as_str() {
+        "multi" if parts.len() == 1 => session.transaction.begin(),
+        "exec" if parts.len() == 1 => match session.transaction.exec() {
+            Ok(request) => return Ok(Reply::Pending(session.login.submit(request, tx).await?, Span::current())),
+            Err(response) => response,
+        },
+        "discard" if parts.len() == 1 => session.transaction.discard(),
+        _ if session.transaction.is_open() => session.transaction.queue(parse(&parts, format)),
+        _ => {
+            // subscriptions need to know the connection, so they are not part of parse_command
+            let maybe_request = match name.as_str() {
+                "subscribe" if parts.len() > 1 => names().map(|names| Request::Subscribe(client, outbox.clone(), names)),
+                "psubscribe" if parts.len() > 1 => names().map(|names| Request::PSubscribe(client, outbox.clone(), names)),
+                "unsubscribe" => names().map(|names| Request::Unsubscribe(client, names)),
+                "punsubscribe" => names().map(|names| Request::PUnsubscribe(client, names)),
+                "watch" if parts.len() > 1 => names().map(|names| Request::Watch(client, outbox.clone(), names)),
+                "unwatch" => names().map(|names| Request::Unwatch(client, names)),
+                _ => parse(&parts, format),
+            };
+            match maybe_request {
+                Ok(request) => return Ok(Reply::Pending(session.login.submit(request, tx).await?, Span::current())),
+                Err(message) => return Ok(Reply::Ready(bad_request(&message, format))),
+            }
+        },
+    };
+    Ok(Reply::Ready(render(&response, format)))
+}
+
+/// the legacy format keeps joining the words of an unquoted value
+fn parse(parts: &[Vec<u8>], format: ReplyFormat) -> Result<Request, String> {
+    match format {
+        ReplyFormat::Text => parse_command(parts),
+        ReplyFormat::Legacy => parse_joined_command(parts),
+    }
+}
+
+fn render(response: &Response, format: ReplyFormat) -> Vec<u8> {
+    match format {
+        ReplyFormat::Text => encode(response),
+        ReplyFormat::Legacy => format!("response: {:?}\r\n", response).into_bytes(),
+    }
+}
+
+fn bad_request(message: &str, format: ReplyFormat) -> Vec<u8> {
+    match format {
+        ReplyFormat::Text => error(ErrorKind::BadRequest.code(), message),
+        ReplyFormat::Legacy => format!("bad request - {}\r\n", message).into_bytes(),
+    }
+}
+
+/// serialize a service response
+pub fn encode(response: &Response) -> Vec<u8> {
+    match response {
+        Response::Ok() => b"+OK\r\n".to_vec(),
+        Response::Queued() => b"+QUEUED\r\n".to_vec(),
+        Response::NotFound(key) => format!("NOTFOUND {}\r\n", escape::quote(key.as_bytes())).into_bytes(),
+        Response::Result(value) => value_reply(Some(value)),
+        Response::Integer(n) => format!(":{}\r\n", n).into_bytes(),
+        Response::List(items) => {
+            let mut reply = format!("*{}\r\n", items.len()).into_bytes();
+            for item in items {
+                reply.extend(value_reply(Some(item.as_bytes())));
+            }
+            reply
+        },
+        Response::Values(values) => {
+            let mut reply = format!("*{}\r\n", values.len()).into_bytes();
+            for value in values {
+                reply.extend(value_reply(value.as_deref()));
+            }
+            reply
+        },
+        Response::Multi(responses) => {
+            let mut reply = format!("*{}\r\n", responses.len()).into_bytes();
+            for response in responses {
+                reply.extend(encode(response));
+            }
+            reply
+        },
+        Response::Entries(entries) => {
+            // key and value of each entry in turn
+            let mut reply = format!("*{}\r\n", entries.len() * 2).into_bytes();
+            for (key, entry) in entries {
+                reply.extend(value_reply(Some(key.as_bytes())));
+                reply.extend(value_reply(Some(&entry.value)));
+            }
+            reply
+        },
+        // only used internally, rendered as the key count and the memory estimate
+        Response::Usage(usage) =>
+            format!("*2\r\n:{}\r\n:{}\r\n", usage.keys, usage.memory).into_bytes(),
+        Response::Error(kind, message) => error(kind.code(), message),
+    }
+}
+
+/// serialize a message pushed to a subscriber
+pub fn encode_push(push: &Push) -> Vec<u8> {
+    match push {
+        Push::Message { channel, payload } =>
+            format!(">message {} ${} {}\r\n", quote(channel), payload.len(), payload).into_bytes(),
+        Push::PatternMessage { pattern, channel, payload } =>
+            format!(">pmessage {} {} ${} {}\r\n", quote(pattern), quote(channel), payload.len(), payload).into_bytes(),
+        Push::Event(event) =>
+            format!(">event {} {}\r\n", event.kind.name(), quote(&event.key)).into_bytes(),
+        Push::Lagged(dropped) =>
+            format!(">lagged {} events dropped\r\n", dropped).into_bytes(),
+        Push::Shutdown =>
+            b">shutdown server is going away\r\n".to_vec(),
+    }
+}
+
+fn quote(name: &str) -> String {
+    escape::quote(name.as_bytes())
+}
+
+fn value_reply(value: Option<&[u8]>) -> Vec<u8> {
+    match value {
+        Some(value) => {
+            let mut reply = format!("${} ", value.len()).into_bytes();
+            reply.extend_from_slice(value);
+            reply.extend_from_slice(b"\r\n");
+            reply
+        },
+        None => b"$-1\r\n".to_vec(),
+    }
+}
+
+fn error(code: &str, message: &str) -> Vec<u8> {
+    // line breaks would end the reply early
+    format!("-ERR {} {}\r\n", code, message.replace(['\r', '\n'], " ")).into_bytes()
+}
@@ -0,0 +1,110 @@
+//! Optional TLS for client connections and for the connection of a follower to its leader.
+//!
+//! The listening addresses speak TLS when `tls-cert` and `tls-key` are set, see `config`,
+//! with `tls-client-ca` clients also have to present a certificate signed by that authority.
+//! A follower trusts the leader's certificate with `leader-tls-ca` and presents its own
+//! certificate, if it has one, in case the leader requires client certificates.
+
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::{self, Duration};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// how long a client may take for the handshake before it is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// anything a connection can be served over
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// a connection in plaintext or over TLS
+pub type Connection = Box<dyn Stream>;
+
+/// PEM files with a certificate chain and its private key
+pub struct Identity {
+    pub cert: String,
+    pub key: String,
+}
+
+/// settings for the listening ports, clients need a certificate signed by `client_ca` if given
+pub fn acceptor(identity: &Identity, client_ca: Option<&str>) -> io::Result<TlsAcceptor> {
+    let builder = ServerConfig::builder();
+    let builder = match client_ca {
+        None => builder.with_no_client_auth(),
+        Some(path) => {
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots(path)?)).build()
+                .map_err(|e| invalid(format!("client CA {}: {}", path, e)))?;
+            builder.with_client_cert_verifier(verifier)
+        },
+    };
+    let (chain, key) = load_identity(identity)?;
+    let config = builder.with_single_cert(chain, key).map_err(|e| invalid(e.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// settings for a follower trusting the authorities in `ca`, presenting `identity` if the leader asks
+pub fn connector(ca: &str, identity: Option<&Identity>) -> io::Result<TlsConnector> {
+    let builder = ClientConfig::builder().with_root_certificates(roots(ca)?);
+    let config = match identity {
+        None => builder.with_no_client_auth(),
+        Some(identity) => {
+            let (chain, key) = load_identity(identity)?;
+            builder.with_client_auth_cert(chain, key).map_err(|e| invalid(e.to_string()))?
+        },
+    };
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// finish accepting a client, doing the handshake if TLS is configured
+pub async fn accept(socket: Connection, tls: Option<&TlsAcceptor>) -> io::Result<Connection> {
+    let Some(acceptor) = tls else {
+        return Ok(socket);
+    };
+    match time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+        Ok(stream) => Ok(Box::new(stream?)),
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out")),
+    }
+}
+
+/// connect to `address` given as host:port, verifying the host name if TLS is configured
+pub async fn connect(address: &str, tls: Option<&TlsConnector>) -> io::Result<Connection> {
+    let socket = TcpStream::connect(address).await?;
+    let Some(connector) = tls else {
+        return Ok(Box::new(socket));
+    };
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    let name = ServerName::try_from(host.trim_start_matches('[').trim_end_matches(']').to_string())
+        .map_err(|e| invalid(format!("{}: {}", address, e)))?;
+    Ok(Box::new(connector.connect(name, socket).await?))
+}
+
+fn load_identity(identity: &Identity) -> io::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let chain = CertificateDer::pem_file_iter(&identity.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(format!("certificate {}: {}", identity.cert, e)))?;
+    let key = PrivateKeyDer::from_pem_file(&identity.key)
+        .map_err(|e| invalid(format!("private key {}: {}", identity.key, e)))?;
+    Ok((chain, key))
+}
+
+fn roots(path: &str) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(format!("CA {}: {}", path, e)))?;
+    for cert in certs {
+        roots.add(cert).map_err(|e| invalid(format!("CA {}: {}", path, e)))?;
+    }
+    Ok(roots)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
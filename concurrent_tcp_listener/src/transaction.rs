@@ -0,0 +1,61 @@
+use crate::{ErrorKind, Request, Response};
+
+/// Requests queued on a connection between `multi` and `exec`.
+/// `exec` hands them to the service as one `Request::Exec`, which runs them without
+/// any other request in between.
+#[derive(Default)]
+pub struct Transaction {
+    // Some while a transaction is open
+    queued: Option<Vec<Request>>,
+    // a command could not be queued, exec will discard the transaction
+    aborted: bool,
+}
+
+impl Transaction {
+    pub fn is_open(&self) -> bool {
+        self.queued.is_some()
+    }
+
+    pub fn begin(&mut self) -> Response {
+        if self.is_open() {
+            return Response::Error(ErrorKind::BadRequest, "multi calls can not be nested".to_string());
+        }
+        self.queued = Some(Vec::new());
+        self.aborted = false;
+        Response::Ok()
+    }
+
+    /// queue a parsed request, a request that failed to parse aborts the transaction
+    pub fn queue(&mut self, maybe_request: Result<Request, String>) -> Response {
+        let Some(queued) = self.queued.as_mut() else {
+            return Response::Error(ErrorKind::BadRequest, "no transaction is open".to_string());
+        };
+        match maybe_request {
+            Ok(request) => {
+                queued.push(request);
+                Response::Queued()
+            },
+            Err(message) => {
+                self.aborted = true;
+                Response::Error(ErrorKind::BadRequest, message)
+            },
+        }
+    }
+
+    /// close the transaction, returns the request to send or the reply if there is nothing to run
+    pub fn exec(&mut self) -> Result<Request, Response> {
+        match self.queued.take() {
+            None => Err(Response::Error(ErrorKind::BadRequest, "exec without multi".to_string())),
+            Some(_) if self.aborted => Err(Response::Error(
+                ErrorKind::ExecAbort, "transaction discarded because of previous errors".to_string())),
+            Some(requests) => Ok(Request::Exec(requests)),
+        }
+    }
+
+    pub fn discard(&mut self) -> Response {
+        match self.queued.take() {
+            Some(_) => Response::Ok(),
+            None => Response::Error(ErrorKind::BadRequest, "discard without multi".to_string()),
+        }
+    }
+}
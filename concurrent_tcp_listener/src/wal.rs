@@ -0,0 +1,210 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tracing::warn;
+
+/// a mutation recorded in the write-ahead log
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    Set(String, Vec<u8>),
+    Del(String),
+    /// set or clear (`None`) the expiry deadline in unix milliseconds
+    Expire(String, Option<u64>),
+}
+
+impl Record {
+    pub fn key(&self) -> &str {
+        match self {
+            Record::Set(key, _) | Record::Del(key) | Record::Expire(key, _) => key,
+        }
+    }
+}
+
+const OP_SET: u8 = 1;
+const OP_DEL: u8 = 2;
+const OP_EXPIRE: u8 = 3;
+
+/// each record is framed as "<payload length: u32 LE><crc32 of payload: u32 LE><payload>"
+pub const FRAME_HEADER_LEN: usize = 8;
+
+/// Append-only log of mutations applied since the last snapshot.
+pub struct Wal {
+    file: File,
+}
+
+impl Wal {
+    /// Open (or create) the log and return all intact records for replay.
+    /// A torn or corrupted tail is truncated instead of failing.
+    pub fn open(path: &Path) -> io::Result<(Wal, Vec<Record>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        let (records, valid_len) = decode_all(&content);
+        if valid_len < content.len() {
+            warn!("Write-ahead log {} has a corrupted tail, truncating {} bytes",
+                     path.display(), content.len() - valid_len);
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        Ok((Wal { file }, records))
+    }
+
+    /// Append records with a single write and make sure they reached the disk.
+    pub fn append(&mut self, records: &[Record]) -> io::Result<()> {
+        let mut frames = Vec::new();
+        for record in records {
+            put_frame(&mut frames, &encode(record));
+        }
+        let len = self.file.metadata()?.len();
+        if let Err(e) = self.file.write_all(&frames).and_then(|()| self.file.sync_data()) {
+            // cut off a partial batch, later appends would be lost behind it on the next open
+            let _ = self.file.set_len(len);
+            let _ = self.file.seek(SeekFrom::End(0));
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Drop all records, called after a snapshot covering them was written.
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.sync_all()
+    }
+}
+
+/// decode records until the first incomplete or damaged one, returns the records and the intact length
+fn decode_all(content: &[u8]) -> (Vec<Record>, usize) {
+    let mut records = Vec::new();
+    let mut valid_len = 0;
+    for (start, payload) in read_frames(content).0 {
+        match decode(payload) {
+            Some(record) => records.push(record),
+            None => break,
+        }
+        valid_len = start + payload.len();
+    }
+    (records, valid_len)
+}
+
+/// append a payload framed with its length and checksum
+pub fn put_frame(out: &mut Vec<u8>, payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(&crc32(payload).to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Split framed content until the first incomplete or damaged frame.
+/// Returns each payload with its offset in `content`, and the intact length.
+pub fn read_frames(content: &[u8]) -> (Vec<(usize, &[u8])>, usize) {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while content.len() - offset >= FRAME_HEADER_LEN {
+        let len = u32::from_le_bytes(content[offset..offset + 4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(content[offset + 4..offset + 8].try_into().unwrap());
+        let start = offset + FRAME_HEADER_LEN;
+        if content.len() - start < len {
+            break;
+        }
+        let payload = &content[start..start + len];
+        if crc32(payload) != crc {
+            break;
+        }
+        frames.push((start, payload));
+        offset = start + len;
+    }
+    (frames, offset)
+}
+
+pub fn encode(record: &Record) -> Vec<u8> {
+    let mut payload = Vec::new();
+    match record {
+        Record::Set(key, value) => {
+            payload.push(OP_SET);
+            put_bytes(&mut payload, key.as_bytes());
+            put_bytes(&mut payload, value);
+        }
+        Record::Del(key) => {
+            payload.push(OP_DEL);
+            put_bytes(&mut payload, key.as_bytes());
+        }
+        Record::Expire(key, deadline) => {
+            payload.push(OP_EXPIRE);
+            put_bytes(&mut payload, key.as_bytes());
+            put_deadline(&mut payload, *deadline);
+        }
+    }
+    payload
+}
+
+pub fn decode(payload: &[u8]) -> Option<Record> {
+    let (&op, mut rest) = payload.split_first()?;
+    let record = match op {
+        OP_SET => Record::Set(take_string(&mut rest)?, take_bytes(&mut rest)?),
+        OP_DEL => Record::Del(take_string(&mut rest)?),
+        OP_EXPIRE => Record::Expire(take_string(&mut rest)?, take_deadline(&mut rest)?),
+        _ => return None,
+    };
+    rest.is_empty().then_some(record)
+}
+
+pub fn put_bytes(payload: &mut Vec<u8>, bytes: &[u8]) {
+    payload.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    payload.extend_from_slice(bytes);
+}
+
+pub fn put_deadline(payload: &mut Vec<u8>, deadline: Option<u64>) {
+    match deadline {
+        Some(deadline) => {
+            payload.push(1);
+            payload.extend_from_slice(&deadline.to_le_bytes());
+        }
+        None => payload.push(0),
+    }
+}
+
+pub fn take_bytes(rest: &mut &[u8]) -> Option<Vec<u8>> {
+    let len = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+    let bytes = rest.get(4..4 + len)?;
+    *rest = &rest[4 + len..];
+    Some(bytes.to_vec())
+}
+
+pub fn take_string(rest: &mut &[u8]) -> Option<String> {
+    String::from_utf8(take_bytes(rest)?).ok()
+}
+
+pub fn take_deadline(rest: &mut &[u8]) -> Option<Option<u64>> {
+    let (&present, tail) = rest.split_first()?;
+    *rest = tail;
+    match present {
+        0 => Some(None),
+        1 => {
+            let deadline = u64::from_le_bytes(rest.get(..8)?.try_into().ok()?);
+            *rest = &rest[8..];
+            Some(Some(deadline))
+        }
+        _ => None,
+    }
+}
+
+/// CRC-32 (IEEE), bitwise; records are small so a table is not worth it
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+use common::{Client, Server, TempFile, RESP_PORT, TEXT_PORT};
+
+const FOLLOWER_TEXT_PORT: u16 = 8100;
+const FOLLOWER_RESP_PORT: u16 = 6480;
+
+/// hash a password the way an operator would, with `--hash-password`
+fn hash(password: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_concurrent_tcp_listener"))
+        .arg("--hash-password")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    writeln!(child.stdin.take().unwrap(), "{}", password).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+fn users() -> TempFile {
+    TempFile::new("users", &format!("\
+        # an administrator and an application limited to its own keys\n\
+        user admin {}\n\
+        allow admin readwrite *\n\
+        user app {}\n\
+        allow app readwrite app:*\n\
+        allow app read config\n\
+        user replica {}\n\
+        allow replica read *\n",
+        hash("admin-secret"), hash("app-secret"), hash("replica secret with spaces")))
+}
+
+#[test]
+fn commands_need_authentication() {
+    let users = users();
+    let _server = Server::start_with(&["--users", users.path()]);
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("get config"), "-ERR NOAUTH authentication required\r\n");
+    assert_eq!(client.request("auth app wrong"), "-ERR NOAUTH invalid user name or password\r\n");
+    assert_eq!(client.request("auth nobody app-secret"), "-ERR NOAUTH invalid user name or password\r\n");
+    assert_eq!(client.request("set app:1 x"), "-ERR NOAUTH authentication required\r\n");
+    assert_eq!(client.request("auth app app-secret"), "+OK\r\n");
+    assert_eq!(client.request("set app:1 x"), "+OK\r\n");
+
+    let mut resp = Client::connect(RESP_PORT);
+    resp.send(b"*2\r\n$3\r\nGET\r\n$5\r\napp:1\r\n");
+    assert_eq!(resp.read_line(), "-NOAUTH authentication required\r\n");
+    resp.send(b"*3\r\n$4\r\nAUTH\r\n$3\r\napp\r\n$10\r\napp-secret\r\n");
+    assert_eq!(resp.read_line(), "+OK\r\n");
+    resp.send(b"*2\r\n$3\r\nGET\r\n$5\r\napp:1\r\n");
+    assert_eq!(resp.read_line(), "$1\r\n");
+}
+
+#[test]
+fn rules_limit_keys_per_user() {
+    let users = users();
+    let _server = Server::start_with(&["--users", users.path()]);
+    let mut admin = Client::connect(TEXT_PORT);
+    assert_eq!(admin.request("auth admin admin-secret"), "+OK\r\n");
+    assert_eq!(admin.request("mset config on secret 42 app:a 1"), "+OK\r\n");
+
+    let mut app = Client::connect(TEXT_PORT);
+    assert_eq!(app.request("auth app app-secret"), "+OK\r\n");
+    assert_eq!(app.request("get config"), "$2 on\r\n");
+    assert_eq!(app.request("set config off"), "-ERR NOPERM user app may not write 'config'\r\n");
+    assert_eq!(app.request("get secret"), "-ERR NOPERM user app may not read 'secret'\r\n");
+    assert_eq!(app.request("incr config"), "-ERR NOPERM user app may not write 'config'\r\n");
+    assert_eq!(app.request("mget app:a secret"), "-ERR NOPERM user app may not read 'secret'\r\n");
+    assert_eq!(app.request("watch app:*"), ":1\r\n");
+    assert_eq!(app.request("watch *"), "-ERR NOPERM user app may not read '*'\r\n");
+
+    // a transaction is checked as a whole, nothing of it runs
+    assert_eq!(app.request("multi"), "+OK\r\n");
+    assert_eq!(app.request("set app:b 2"), "+QUEUED\r\n");
+    assert_eq!(app.request("del secret"), "+QUEUED\r\n");
+    assert_eq!(app.request("exec"), "-ERR NOPERM user app may not write 'secret'\r\n");
+    assert_eq!(app.request("exists app:b"), ":0\r\n");
+
+    // keys only lists what the user may read
+    assert_eq!(app.request("keys *"), "*2\r\n");
+    let listed = [app.read_line(), app.read_line()];
+    assert_eq!(listed, ["$5 app:a\r\n", "$6 config\r\n"]);
+
+    assert_eq!(admin.request("get secret"), "$2 42\r\n");
+}
+
+#[test]
+fn follower_authenticates_at_the_leader() {
+    let users = users();
+    let _leader = Server::start_with(&["--users", users.path()]);
+    let mut admin = Client::connect(TEXT_PORT);
+    assert_eq!(admin.request("auth admin admin-secret"), "+OK\r\n");
+    assert_eq!(admin.request("set replicated yes"), "+OK\r\n");
+
+    let mut stranger = Client::connect(TEXT_PORT);
+    assert_eq!(stranger.request("sync"), "-ERR NOAUTH authentication required\r\n");
+    let mut app = Client::connect(TEXT_PORT);
+    assert_eq!(app.request("auth app app-secret"), "+OK\r\n");
+    assert_eq!(app.request("sync"), "-ERR NOPERM user app may not read '*'\r\n");
+
+    let leader_address = format!("127.0.0.1:{}", TEXT_PORT);
+    let _follower = Server::start_second(FOLLOWER_TEXT_PORT, FOLLOWER_RESP_PORT,
+        &["--replicaof", &leader_address, "--leader-user", "replica", "--leader-password", "replica secret with spaces"]);
+    let mut follower = Client::connect(FOLLOWER_TEXT_PORT);
+    let started = std::time::Instant::now();
+    while follower.request("get replicated") != "$3 yes\r\n" {
+        assert!(started.elapsed() < std::time::Duration::from_secs(5), "nothing replicated");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn hello_logs_in_with_auth() {
+    let users = users();
+    let _server = Server::start_with(&["--users", users.path()]);
+    let mut resp = Client::connect(RESP_PORT);
+    resp.send(b"*5\r\n$5\r\nHELLO\r\n$1\r\n3\r\n$4\r\nAUTH\r\n$3\r\napp\r\n$5\r\nwrong\r\n");
+    assert_eq!(resp.read_line(), "-NOAUTH invalid user name or password\r\n");
+    resp.send(b"*2\r\n$3\r\nGET\r\n$6\r\nconfig\r\n");
+    assert_eq!(resp.read_line(), "-NOAUTH authentication required\r\n");
+
+    resp.send(b"*7\r\n$5\r\nHELLO\r\n$1\r\n3\r\n$4\r\nAUTH\r\n$3\r\napp\r\n$10\r\napp-secret\r\n$7\r\nSETNAME\r\n$2\r\nme\r\n");
+    assert_eq!(resp.read_line(), "%6\r\n");
+    while resp.read_line() != "modules\r\n" {}
+    assert_eq!(resp.read_line(), "*0\r\n");
+    resp.send(b"*2\r\n$3\r\nGET\r\n$6\r\nconfig\r\n");
+    assert_eq!(resp.read_line(), "_\r\n");
+}
+
+#[test]
+fn unknown_users_take_as_long_as_wrong_passwords() {
+    let users = users();
+    let _server = Server::start_with(&["--users", users.path()]);
+    let mut client = Client::connect(TEXT_PORT);
+    let time = |client: &mut Client, line: &str| {
+        let started = Instant::now();
+        assert_eq!(client.request(line), "-ERR NOAUTH invalid user name or password\r\n");
+        started.elapsed()
+    };
+    let wrong_password = time(&mut client, "auth app wrong");
+    let unknown_user = time(&mut client, "auth nobody wrong");
+    // both check a password hash, without one the reply comes back in microseconds
+    assert!(unknown_user * 3 > wrong_password, "{:?} for an unknown user, {:?} for a wrong password", unknown_user, wrong_password);
+}
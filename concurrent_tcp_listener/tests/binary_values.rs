@@ -0,0 +1,150 @@
+mod common;
+
+use std::path::Path;
+use std::time::Duration;
+use common::{data_dir, start_in_process};
+use concurrent_tcp_listener::client::Client;
+use concurrent_tcp_listener::escape;
+use concurrent_tcp_listener::pubsub::Push;
+use concurrent_tcp_listener::storage::Engine;
+use concurrent_tcp_listener::store::{EventKind, Files, Store};
+use concurrent_tcp_listener::wal::Record;
+use concurrent_tcp_listener::{Request, Response, Server};
+use proptest::prelude::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+
+/// a server and a client connected to it
+async fn start(dir: &Path) -> (Server, Client) {
+    let server = start_in_process(dir).await;
+    let client = Client::connect(&server.local_addr().unwrap().to_string()).await.unwrap();
+    (server, client)
+}
+
+fn bytes() -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec(any::<u8>(), 0..256)
+}
+
+proptest! {
+    #[test]
+    fn quoted_words_split_back_into_the_same_bytes(words in prop::collection::vec(bytes(), 1..8)) {
+        let line = words.iter().map(|word| escape::quote(word)).collect::<Vec<String>>().join(" ");
+        prop_assert_eq!(escape::split(line.as_bytes()).unwrap(), words);
+    }
+}
+
+#[test]
+fn arbitrary_keys_and_values_survive_set_and_get() {
+    let runtime = Runtime::new().unwrap();
+    let dir = data_dir("set-get");
+    let (server, client) = runtime.block_on(start(&dir));
+
+    proptest!(|(key in any::<String>(), value in bytes(), appended in bytes())| {
+        runtime.block_on(async {
+            client.set(&key, &value).await.unwrap();
+            prop_assert_eq!(client.get(&key).await.unwrap(), Some(value.clone()));
+
+            let response = client.request(Request::Append(key.clone(), appended.clone())).await.unwrap();
+            prop_assert!(matches!(response, Response::Integer(n) if n as usize == value.len() + appended.len()));
+            let response = client.request(Request::MGet(vec![key.clone(), format!("{} missing", key)])).await.unwrap();
+            match response {
+                Response::Values(values) => prop_assert_eq!(values, vec![Some([value, appended].concat()), None]),
+                other => prop_assert!(false, "{:?}", other),
+            }
+            Ok(())
+        })?;
+    });
+
+    runtime.block_on(server.shutdown()).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn values_set_over_resp_read_back_over_text() {
+    let runtime = Runtime::new().unwrap();
+    let dir = data_dir("resp");
+    let (server, client) = runtime.block_on(start(&dir));
+    // proptest runs the cases in a closure that may not mutate what it captures
+    let resp = Mutex::new(runtime.block_on(TcpStream::connect(server.resp_addr().unwrap())).unwrap());
+
+    proptest!(|(key in any::<String>(), value in bytes())| {
+        runtime.block_on(async {
+            let mut resp = resp.lock().await;
+            let mut command = b"*3\r\n$3\r\nset\r\n".to_vec();
+            for arg in [key.as_bytes(), &value] {
+                command.extend(format!("${}\r\n", arg.len()).into_bytes());
+                command.extend_from_slice(arg);
+                command.extend_from_slice(b"\r\n");
+            }
+            resp.write_all(&command).await.unwrap();
+            let mut reply = [0u8; 5];
+            resp.read_exact(&mut reply).await.unwrap();
+            prop_assert_eq!(&reply, b"+OK\r\n");
+            prop_assert_eq!(client.get(&key).await.unwrap(), Some(value));
+            Ok(())
+        })?;
+    });
+
+    runtime.block_on(server.shutdown()).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn arbitrary_values_survive_the_log_and_the_snapshot(entries in prop::collection::hash_map(any::<String>(), bytes(), 1..16)) {
+        for engine in [Engine::Memory, Engine::Ordered, Engine::Disk] {
+            let dir = data_dir(&format!("restart-{:?}", engine));
+            let files = Files { snapshot: dir.join("snapshot"), wal: dir.join("wal"), data: dir.join("data") };
+
+            let mut store = Store::open(engine, &files).unwrap();
+            store.write(entries.iter().map(|(key, value)| Record::Set(key.clone(), value.clone())).collect()).unwrap();
+            drop(store);
+            // replayed from the write-ahead log or read from the data file
+            let mut store = Store::open(engine, &files).unwrap();
+            for (key, value) in &entries {
+                prop_assert_eq!(store.get(key).unwrap(), Some(value.clone()), "{:?}", engine);
+            }
+            store.persist();
+            drop(store);
+            // loaded from the snapshot or the compacted data file
+            let store = Store::open(engine, &files).unwrap();
+            for (key, value) in &entries {
+                prop_assert_eq!(store.get(key).unwrap(), Some(value.clone()), "{:?}", engine);
+            }
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+    }
+}
+
+#[tokio::test]
+async fn keys_and_channels_with_spaces_are_quoted_in_pushes() {
+    let dir = data_dir("pushes");
+    let (server, client) = start(&dir).await;
+
+    let mut events = client.watch(&["my key"]).await.unwrap();
+    let mut messages = client.subscribe(&["a \"quoted\" channel"]).await.unwrap();
+    client.set("my key", "x").await.unwrap();
+    assert_eq!(client.publish("a \"quoted\" channel", "two\r\nlines").await.unwrap(), 1);
+
+    match timeout(Duration::from_secs(5), events.next()).await.unwrap() {
+        Some(Push::Event(event)) => {
+            assert_eq!(event.kind, EventKind::Set);
+            assert_eq!(event.key, "my key");
+        },
+        other => panic!("{:?}", other),
+    }
+    match timeout(Duration::from_secs(5), messages.next()).await.unwrap() {
+        Some(Push::Message { channel, payload }) => {
+            assert_eq!(channel, "a \"quoted\" channel");
+            assert_eq!(payload, "two\r\nlines");
+        },
+        other => panic!("{:?}", other),
+    }
+    server.shutdown().await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
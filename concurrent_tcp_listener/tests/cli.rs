@@ -0,0 +1,134 @@
+mod common;
+
+use std::process::{Output, Stdio};
+use common::{data_dir, start_in_process};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// run kv-cli with `args`, feeding it `input` on stdin
+async fn kv_cli(address: &str, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kv-cli"))
+        .args(["--address", address])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(input.as_bytes()).await.unwrap();
+    drop(stdin);
+    child.wait_with_output().await.unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[tokio::test]
+async fn one_shot_commands_print_their_reply() {
+    let dir = data_dir("one-shot");
+    let server = start_in_process(&dir).await;
+    let address = server.local_addr().unwrap().to_string();
+
+    let output = kv_cli(&address, &["set", "greeting", "hello world"], "").await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "OK\n");
+    assert_eq!(stdout(&kv_cli(&address, &["get", "greeting"], "").await), "\"hello world\"\n");
+    assert_eq!(stdout(&kv_cli(&address, &["--output", "raw", "get", "greeting"], "").await), "hello world\n");
+    assert_eq!(stdout(&kv_cli(&address, &["get", "missing"], "").await), "(nil)\n");
+    assert_eq!(stdout(&kv_cli(&address, &["mget", "greeting", "missing"], "").await), "1) \"hello world\"\n2) (nil)\n");
+    assert_eq!(stdout(&kv_cli(&address, &["-o", "json", "del", "greeting"], "").await), "1\n");
+
+    // errors of the server and of the command line fail the run
+    kv_cli(&address, &["set", "word", "abc"], "").await;
+    let output = kv_cli(&address, &["incr", "word"], "").await;
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("(error) WRONGTYPE"), "{}", stderr(&output));
+    let output = kv_cli(&address, &["frobnicate", "it"], "").await;
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("(error) INVALID"), "{}", stderr(&output));
+
+    server.shutdown().await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn scripts_run_from_stdin_and_files() {
+    let dir = data_dir("script");
+    let server = start_in_process(&dir).await;
+    let address = server.local_addr().unwrap().to_string();
+
+    // one JSON line per command, comments and empty lines are skipped, quit ends the script
+    let script = "set a 1\n# a comment\n\nincr a\nmget a b\nkeys *\nincr\nquit\nset never run\n";
+    let output = kv_cli(&address, &["--output", "json"], script).await;
+    assert_eq!(stdout(&output).lines().collect::<Vec<&str>>(), [
+        "\"OK\"",
+        "2",
+        "[\"2\",null]",
+        "[\"a\"]",
+        "{\"error\":\"INVALID\",\"message\":\"not a valid request: [\\\"incr\\\"]\"}",
+    ]);
+    assert!(!output.status.success());
+
+    let file = dir.join("commands.txt");
+    std::fs::write(&file, "append a 23\nget a\nget never\n").unwrap();
+    let output = kv_cli(&address, &["--output", "raw", "--file", file.to_str().unwrap()], "").await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "3\n223\n\n");
+
+    // quoted keys and values, human output escapes values the same way
+    let output = kv_cli(&address, &[], "set \"my key\" \"tab\\there\\xff\"\nget \"my key\"\n").await;
+    assert_eq!(stdout(&output), "OK\n\"tab\\there\\xff\"\n");
+
+    server.shutdown().await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn transactions_and_server_commands_are_sent_as_they_are() {
+    let dir = data_dir("raw");
+    let server = start_in_process(&dir).await;
+    let address = server.local_addr().unwrap().to_string();
+
+    let script = "multi\nset a 1\nincr a\nmget a b\nexec\nmulti\nset a 5\ndiscard\nget a\nexec\n";
+    let output = kv_cli(&address, &["--output", "json"], script).await;
+    assert_eq!(stdout(&output).lines().collect::<Vec<&str>>(), [
+        "\"OK\"",
+        "\"QUEUED\"",
+        "\"QUEUED\"",
+        "\"QUEUED\"",
+        "[\"OK\",2,[\"2\",null]]",
+        "\"OK\"",
+        "\"QUEUED\"",
+        "\"OK\"",
+        "\"2\"",
+        "{\"error\":\"BADREQUEST\",\"message\":\"exec without multi\"}",
+    ]);
+
+    let output = kv_cli(&address, &["--output", "raw", "info"], "").await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("keys"), "{}", stdout(&output));
+    let output = kv_cli(&address, &["auth", "nobody", "secret"], "").await;
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("(error) "), "{}", stderr(&output));
+
+    server.shutdown().await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn unreachable_server_fails_with_a_message() {
+    // a port nobody listens on any more
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    drop(listener);
+
+    let output = kv_cli(&address, &["get", "a"], "").await;
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains(&format!("unable to connect to {}", address)), "{}", stderr(&output));
+}
@@ -0,0 +1,196 @@
+mod common;
+
+use std::time::Duration;
+use common::{data_dir, start_in_process, start_in_process_with};
+use concurrent_tcp_listener::client::{Client, Options};
+use concurrent_tcp_listener::config::{Address, Config};
+use concurrent_tcp_listener::error::ClientError;
+use concurrent_tcp_listener::pubsub::Push;
+use concurrent_tcp_listener::store::EventKind;
+use concurrent_tcp_listener::{Request, Response, Server};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_stream::StreamExt;
+
+async fn start_ephemeral(name: &str) -> (Server, String) {
+    let server = start_in_process(&data_dir(name)).await;
+    let address = server.local_addr().unwrap().to_string();
+    (server, address)
+}
+
+#[tokio::test]
+async fn get_set_and_del() {
+    let (server, address) = start_ephemeral("basics").await;
+    let client = Client::connect(&address).await.unwrap();
+
+    client.set("greeting", "hello world").await.unwrap();
+    assert_eq!(client.get("greeting").await.unwrap().as_deref(), Some(&b"hello world"[..]));
+    assert_eq!(client.get("missing").await.unwrap(), None);
+    assert_eq!(client.del(&["greeting", "missing"]).await.unwrap(), 1);
+    assert_eq!(client.get("greeting").await.unwrap(), None);
+
+    // keys and values with whitespace, quotes and bytes that are not UTF-8 are quoted on the wire
+    client.set("two words", "x").await.unwrap();
+    client.set("key", b"line\nbreak \"quoted\" \xff\x00").await.unwrap();
+    assert_eq!(client.get("two words").await.unwrap().as_deref(), Some(&b"x"[..]));
+    assert_eq!(client.get("key").await.unwrap().as_deref(), Some(&b"line\nbreak \"quoted\" \xff\x00"[..]));
+    let response = client.request(Request::Get("no such key".to_string())).await.unwrap();
+    assert!(matches!(response, Response::NotFound(ref key) if key == "no such key"), "{:?}", response);
+
+    // the server's errors and requests the text protocol cannot carry
+    client.set("word", "abc").await.unwrap();
+    let response = client.request(Request::Incr("word".to_string())).await.unwrap();
+    assert!(matches!(response, Response::Error(..)), "{:?}", response);
+    assert!(matches!(client.request(Request::Expire("word".to_string(), 0)).await, Err(ClientError::Invalid(_))));
+    assert!(matches!(client.request(Request::Persist()).await, Err(ClientError::Invalid(_))));
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn pipeline_answers_in_order() {
+    let (server, address) = start_ephemeral("pipeline").await;
+    let client = Client::connect(&address).await.unwrap();
+
+    let mut requests = (0..100).map(|_| Request::Incr("counter".to_string())).collect::<Vec<Request>>();
+    requests.push(Request::MSet(vec![("a".to_string(), b"1".to_vec()), ("b".to_string(), b"2".to_vec())]));
+    requests.push(Request::MGet(vec!["a".to_string(), "nothing".to_string(), "b".to_string()]));
+    let responses = client.pipeline(requests).await.unwrap();
+
+    assert_eq!(responses.len(), 102);
+    for (i, response) in responses[..100].iter().enumerate() {
+        assert!(matches!(response, Response::Integer(n) if *n == i as i64 + 1), "{}: {:?}", i, response);
+    }
+    assert!(matches!(responses[100], Response::Ok()));
+    match &responses[101] {
+        Response::Values(values) =>
+            assert_eq!(values, &[Some(b"1".to_vec()), None, Some(b"2".to_vec())]),
+        other => panic!("{:?}", other),
+    }
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn pool_stays_within_its_size() {
+    let config = Config { max_connections: 3, ..Config::default() };
+    let server = start_in_process_with(&data_dir("pool"), "127.0.0.1:0".parse().unwrap(), config).await;
+    let options = Options { pool_size: 3, ..Options::default() };
+    let client = Client::connect_with(&server.local_addr().unwrap().to_string(), options).await.unwrap();
+
+    // more concurrent requests than connections, none of them is rejected as busy
+    let tasks = (0..50).map(|i| {
+        let client = client.clone();
+        tokio::spawn(async move { client.set(&format!("key{}", i), "value").await })
+    }).collect::<Vec<_>>();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+    let response = client.request(Request::Exists((0..50).map(|i| format!("key{}", i)).collect())).await.unwrap();
+    assert!(matches!(response, Response::Integer(50)), "{:?}", response);
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn subscription_streams_messages_and_events() {
+    let (server, address) = start_ephemeral("subscribe").await;
+    let client = Client::connect(&address).await.unwrap();
+
+    let mut messages = client.subscribe(&["news"]).await.unwrap();
+    let mut events = client.watch(&["user:*"]).await.unwrap();
+    assert_eq!(client.publish("news", "hello subscribers").await.unwrap(), 1);
+    client.set("user:1", "alice").await.unwrap();
+
+    match timeout(Duration::from_secs(5), messages.next()).await.unwrap() {
+        Some(Push::Message { channel, payload }) => assert_eq!((channel.as_str(), payload.as_str()), ("news", "hello subscribers")),
+        other => panic!("{:?}", other),
+    }
+    // the subscription is a stream as well
+    match timeout(Duration::from_secs(5), StreamExt::next(&mut events)).await.unwrap() {
+        Some(Push::Event(event)) => assert_eq!((event.kind, event.key.as_str()), (EventKind::Set, "user:1")),
+        other => panic!("{:?}", other),
+    }
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn client_reconnects_after_a_server_restart() {
+    let dir = data_dir("restart");
+    let server = start_in_process(&dir).await;
+    let address = server.local_addr().unwrap();
+    let client = Client::connect(&address.to_string()).await.unwrap();
+    let mut messages = client.subscribe(&["news"]).await.unwrap();
+    client.set("kept", "yes").await.unwrap();
+
+    server.shutdown().await.unwrap();
+    let server = start_in_process_with(&dir, Address::Tcp(address), Config::default()).await;
+
+    // the pooled connection is gone, the write goes over a new one
+    client.set("after", "restart").await.unwrap();
+    assert_eq!(client.get("kept").await.unwrap().as_deref(), Some(&b"yes"[..]));
+
+    // the subscription comes back on its own, publish until it is there again
+    let message = timeout(Duration::from_secs(5), async {
+        loop {
+            if client.publish("news", "again").await.unwrap() == 1 {
+                return messages.next().await;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }).await.unwrap();
+    assert!(matches!(message, Some(Push::Message { ref payload, .. }) if payload == "again"), "{:?}", message);
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn only_reads_are_repeated_when_a_connection_fails() {
+    // a server that reads one request per connection and closes it without a reply
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let received = tokio::spawn(async move {
+        let mut lines = Vec::new();
+        while let Ok(Ok((socket, _))) = timeout(Duration::from_millis(500), listener.accept()).await {
+            let mut line = String::new();
+            BufReader::new(socket).read_line(&mut line).await.unwrap();
+            lines.push(line);
+        }
+        lines
+    });
+
+    let writer = Client::connect(&address).await.unwrap();
+    assert!(writer.request(Request::Incr("n".to_string())).await.is_err());
+    let reader = Client::connect(&address).await.unwrap();
+    assert!(reader.get("n").await.is_err());
+    assert_eq!(received.await.unwrap(), ["incr n\n", "get n\n", "get n\n"]);
+}
+
+#[tokio::test]
+async fn transactions_keep_their_connection_and_fail_once_it_is_lost() {
+    let dir = data_dir("session");
+    let server = start_in_process(&dir).await;
+    let address = server.local_addr().unwrap();
+    let client = Client::connect_with(&address.to_string(), Options { pool_size: 2, ..Options::default() }).await.unwrap();
+
+    // state of the connection is never left behind in the pool
+    assert!(matches!(client.command(&["multi"]).await, Err(ClientError::Invalid(_))));
+    assert!(matches!(client.command(&["auth", "user", "password"]).await, Err(ClientError::Invalid(_))));
+
+    let mut session = client.session().await.unwrap();
+    assert!(matches!(session.command(&["multi"]).await.unwrap(), Response::Ok()));
+    assert!(matches!(session.request(Request::Incr("n".to_string())).await.unwrap(), Response::Queued()));
+    // other requests of the client meanwhile go over the other connection
+    client.set("n", "5").await.unwrap();
+    assert!(matches!(session.command(&["exec"]).await.unwrap(), Response::Multi(ref replies) if matches!(replies[..], [Response::Integer(6)])));
+
+    assert!(matches!(session.command(&["multi"]).await.unwrap(), Response::Ok()));
+    assert!(matches!(session.request(Request::Incr("n".to_string())).await.unwrap(), Response::Queued()));
+    server.shutdown().await.unwrap();
+    let server = start_in_process_with(&dir, Address::Tcp(address), Config::default()).await;
+
+    // the queued increment went with the connection, it is not silently dropped or repeated
+    assert!(session.command(&["exec"]).await.is_err());
+    assert!(session.is_lost());
+    assert!(matches!(session.command(&["exec"]).await, Err(ClientError::SessionLost)));
+    drop(session);
+    assert_eq!(client.get("n").await.unwrap().as_deref(), Some(&b"6"[..]));
+    server.shutdown().await.unwrap();
+}
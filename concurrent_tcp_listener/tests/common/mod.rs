@@ -0,0 +1,250 @@
+// shared by all test files, each of which uses only some of the helpers
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::path::{Path, PathBuf};
+use std::fs::OpenOptions;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use concurrent_tcp_listener::config::{Address, Config};
+
+pub const TEXT_PORT: u16 = 8000;
+pub const RESP_PORT: u16 = 6380;
+
+const LOG_FILE: &str = "server.log";
+
+// the server binds fixed ports, so only one may run at a time
+static SERVER_LOCK: Mutex<()> = Mutex::new(());
+
+/// A server process running in its own temporary directory, killed on drop.
+pub struct Server {
+    child: Child,
+    dir: PathBuf,
+    ports: (u16, u16),
+    // None for a second server started next to the locked one
+    _lock: Option<MutexGuard<'static, ()>>,
+}
+
+impl Server {
+    pub fn start() -> Server {
+        Server::start_with(&[])
+    }
+
+    /// start with extra command line arguments, e.g. `--shards 4`
+    pub fn start_with(args: &[&str]) -> Server {
+        Server::start_with_env(args, &[])
+    }
+
+    /// start with extra command line arguments and environment variables, e.g. `KV_SHARDS=4`
+    pub fn start_with_env(args: &[&str], env: &[(&str, &str)]) -> Server {
+        let lock = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        Server::launch((TEXT_PORT, RESP_PORT), args, env, Some(lock))
+    }
+
+    /// start another server on other ports while one started with `start` is running,
+    /// e.g. a follower of it
+    pub fn start_second(text_port: u16, resp_port: u16, args: &[&str]) -> Server {
+        let mut args = args.to_vec();
+        let ports = (text_port.to_string(), resp_port.to_string());
+        args.extend(["--port", &ports.0, "--resp-port", &ports.1]);
+        Server::launch((text_port, resp_port), &args, &[], None)
+    }
+
+    fn launch(ports: (u16, u16), args: &[&str], env: &[(&str, &str)], lock: Option<MutexGuard<'static, ()>>) -> Server {
+        let dir = std::env::temp_dir()
+            .join(format!("kv-test-{}-{:?}-{}", std::process::id(), thread::current().id(), ports.0));
+        std::fs::create_dir_all(&dir).unwrap();
+        let child = spawn(&dir, args, env);
+        let server = Server { child, dir, ports, _lock: lock };
+        server.wait_until_listening();
+        server
+    }
+
+    /// stop the server gracefully and start it again on the same data with other arguments
+    pub fn restart_with(&mut self, args: &[&str]) {
+        terminate(&mut self.child);
+        self.child = spawn(&self.dir, args, &[]);
+        self.wait_until_listening();
+    }
+
+    /// send SIGTERM and wait for the server to exit
+    pub fn terminate(&mut self) -> ExitStatus {
+        terminate(&mut self.child)
+    }
+
+    /// stop the server the way a crash would, without the final snapshot
+    pub fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+
+    fn wait_until_listening(&self) {
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(10) {
+            if TcpStream::connect(("127.0.0.1", self.ports.0)).is_ok() && TcpStream::connect(("127.0.0.1", self.ports.1)).is_ok() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("server did not start listening");
+    }
+
+    /// the temporary directory the server runs in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// what the server wrote to stdout so far
+    pub fn log(&self) -> String {
+        std::fs::read_to_string(self.dir.join(LOG_FILE)).unwrap()
+    }
+
+    pub fn is_running(&mut self) -> bool {
+        self.child.try_wait().unwrap().is_none()
+    }
+}
+
+fn spawn(dir: &Path, args: &[&str], env: &[(&str, &str)]) -> Child {
+    // appended to, so a restarted server keeps the earlier output
+    let log = OpenOptions::new().create(true).append(true).open(dir.join(LOG_FILE)).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_concurrent_tcp_listener"))
+        .args(args)
+        .envs(env.iter().copied())
+        .current_dir(dir)
+        .stdout(log)
+        .spawn()
+        .expect("Unable to start server")
+}
+
+/// send SIGTERM and wait for the final snapshot to be written
+fn terminate(child: &mut Child) -> ExitStatus {
+    // unless it was killed before
+    if child.try_wait().unwrap().is_none() {
+        let _ = Command::new("kill").arg(child.id().to_string()).status();
+    }
+    child.wait().unwrap()
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A blocking client reading replies line by line.
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    pub fn connect(port: u16) -> Client {
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        Client { reader: BufReader::new(stream.try_clone().unwrap()), writer: stream }
+    }
+
+    pub fn send(&mut self, bytes: &[u8]) {
+        self.writer.write_all(bytes).unwrap();
+    }
+
+    pub fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        line
+    }
+
+    /// send a request line and return the first reply line
+    pub fn request(&mut self, line: &str) -> String {
+        self.send(format!("{}\n", line).as_bytes());
+        self.read_line()
+    }
+
+    /// send a request answered with a value that may span several lines, e.g. `info`,
+    /// and return the value
+    pub fn request_value(&mut self, line: &str) -> String {
+        let first = self.request(line);
+        let (len, value) = first.strip_prefix('$').and_then(|rest| rest.split_once(' '))
+            .unwrap_or_else(|| panic!("expected a value, got {:?}", first));
+        let len = len.parse::<usize>().unwrap();
+        let mut value = value.to_string();
+        while value.len() < len + 2 {
+            value.push_str(&self.read_line());
+        }
+        value.truncate(len);
+        value
+    }
+
+    /// read until the server closes the connection
+    pub fn read_to_end(&mut self) -> String {
+        let mut rest = String::new();
+        let _ = self.reader.read_to_string(&mut rest);
+        rest
+    }
+
+    pub fn close_write(&mut self) {
+        self.writer.shutdown(Shutdown::Write).unwrap();
+    }
+}
+
+/// a number in the `name:value` lines of `stats` or `info`
+pub fn stat(lines: &str, name: &str) -> u64 {
+    lines.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+        .unwrap_or_else(|| panic!("{} missing in {}", name, lines))
+        .trim_end()
+        .parse()
+        .unwrap()
+}
+
+/// A file outside the server's directory, e.g. a config or users file, removed on drop.
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    pub fn new(name: &str, content: &str) -> TempFile {
+        let path = std::env::temp_dir()
+            .join(format!("kv-test-{}-{:?}-{}", std::process::id(), thread::current().id(), name));
+        std::fs::write(&path, content).unwrap();
+        TempFile(path)
+    }
+
+    pub fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// an empty directory for the data files of a server started with `start_in_process`
+pub fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("kv-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// a server running in the test itself on ephemeral ports, with its data files in `dir`
+pub async fn start_in_process(dir: &Path) -> concurrent_tcp_listener::Server {
+    start_in_process_with(dir, "127.0.0.1:0".parse().unwrap(), Config::default()).await
+}
+
+/// like `start_in_process`, serving the text protocol on `address` and with the settings of `config`
+pub async fn start_in_process_with(dir: &Path, address: Address, config: Config) -> concurrent_tcp_listener::Server {
+    concurrent_tcp_listener::Server::builder()
+        .config(config)
+        .listen(address)
+        .resp_listen("127.0.0.1:0".parse().unwrap())
+        .data_dir(dir)
+        .start()
+        .await
+        .unwrap()
+}
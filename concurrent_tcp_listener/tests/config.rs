@@ -0,0 +1,106 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use common::{Client, Server, TempFile, TEXT_PORT};
+
+fn request<S: Read + Write>(stream: &mut BufReader<S>, line: &str) -> String {
+    stream.get_mut().write_all(format!("{}\n", line).as_bytes()).unwrap();
+    let mut reply = String::new();
+    stream.read_line(&mut reply).unwrap();
+    reply
+}
+
+#[test]
+fn config_file_listens_on_every_address() {
+    let file = TempFile::new("addresses.toml", r#"
+        listen = ["127.0.0.1:8000", "[::1]:8000", "unix:kv.sock"]
+        resp-listen = ["127.0.0.1:6380"]
+    "#);
+    let server = Server::start_with(&["--config", file.path()]);
+
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("set shared value"), "+OK\r\n");
+
+    let mut ipv6 = BufReader::new(TcpStream::connect("[::1]:8000").unwrap());
+    assert_eq!(request(&mut ipv6, "get shared"), "$5 value\r\n");
+
+    let mut unix = BufReader::new(UnixStream::connect(server.dir().join("kv.sock")).unwrap());
+    assert_eq!(request(&mut unix, "get shared"), "$5 value\r\n");
+}
+
+#[test]
+fn environment_overrides_file_and_command_line_overrides_environment() {
+    let file = TempFile::new("precedence.toml", r#"
+        listen = ["127.0.0.1:8401"]
+        max-connections = 1
+    "#);
+    // the file alone would allow one connection, the environment allows two
+    let _server = Server::start_with_env(&["--listen", "127.0.0.1:8000"],
+                                         &[("KV_CONFIG", file.path()), ("KV_LISTEN", "127.0.0.1:8402"),
+                                           ("KV_MAX_CONNECTIONS", "2")]);
+
+    assert!(TcpStream::connect("127.0.0.1:8401").is_err());
+    assert!(TcpStream::connect("127.0.0.1:8402").is_err());
+    // the connections probing for the start may still be counted
+    thread::sleep(Duration::from_millis(100));
+    let mut first = Client::connect(TEXT_PORT);
+    let mut second = Client::connect(TEXT_PORT);
+    assert_eq!(first.request("set a 1"), "+OK\r\n");
+    assert_eq!(second.request("get a"), "$1 1\r\n");
+}
+
+#[test]
+fn invalid_configuration_lists_every_problem() {
+    let output = Command::new(env!("CARGO_BIN_EXE_concurrent_tcp_listener"))
+        .args(["--persist-interval", "0", "--engine", "paper", "--listen", "localhost"])
+        .env("KV_SHARDS", "0")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let message = String::from_utf8(output.stderr).unwrap();
+    assert!(message.starts_with("error: Invalid configuration:"), "{}", message);
+    for problem in ["--persist-interval: \"0\" is no positive number",
+                    "--engine: expected one of memory, ordered or disk",
+                    "--listen: \"localhost\" is no address",
+                    "KV_SHARDS: \"0\" is no positive number"] {
+        assert!(message.contains(problem), "{:?} missing in {}", problem, message);
+    }
+}
+
+#[test]
+fn command_line_has_help_and_rejects_unknown_arguments() {
+    let output = Command::new(env!("CARGO_BIN_EXE_concurrent_tcp_listener")).arg("--help").output().unwrap();
+    assert!(output.status.success());
+    let help = String::from_utf8(output.stdout).unwrap();
+    for setting in ["--max-connections <N>", "[env: KV_MAX_CONNECTIONS=]", "--legacy-format", "--hash-password"] {
+        assert!(help.contains(setting), "{:?} missing in {}", setting, help);
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_concurrent_tcp_listener"))
+        .args(["--colour", "blue"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let message = String::from_utf8(output.stderr).unwrap();
+    assert!(message.contains("unexpected argument '--colour'"), "{}", message);
+}
+
+#[test]
+fn idle_connections_are_closed() {
+    let _server = Server::start_with(&["--idle-timeout", "1"]);
+
+    let mut active = Client::connect(TEXT_PORT);
+    let mut idle = Client::connect(TEXT_PORT);
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(active.request("set alive yes"), "+OK\r\n");
+    }
+    assert_eq!(idle.read_to_end(), "");
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use common::{Client, Server, RESP_PORT, TEXT_PORT};
+
+#[test]
+fn disconnect_in_the_middle_of_a_line_keeps_server_running() {
+    let mut server = Server::start();
+
+    let mut client = Client::connect(TEXT_PORT);
+    client.send(b"set half");
+    drop(client);
+
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("get half"), "NOTFOUND half\r\n");
+    assert!(server.is_running());
+}
+
+#[test]
+fn disconnect_without_reading_replies_keeps_server_running() {
+    let mut server = Server::start();
+
+    // closing with unread replies makes the server's writes fail with a reset
+    let mut client = Client::connect(TEXT_PORT);
+    for i in 0..1000 {
+        client.send(format!("set key{} value{}\n", i, i).as_bytes());
+    }
+    drop(client);
+
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("set after reset"), "+OK\r\n");
+    assert_eq!(client.request("get after"), "$5 reset\r\n");
+    assert!(server.is_running());
+}
+
+#[test]
+fn invalid_utf8_key_is_a_bad_request_and_connection_stays_usable() {
+    let _server = Server::start();
+
+    let mut client = Client::connect(TEXT_PORT);
+    // values may be any bytes, keys have to be UTF-8
+    client.send(b"set \xff\xfe value\n");
+    assert_eq!(client.read_line(), "-ERR BADREQUEST not valid UTF-8: \"\\xff\\xfe\"\r\n");
+    assert_eq!(client.request("set valid value"), "+OK\r\n");
+}
+
+#[test]
+fn malformed_lines_are_bad_requests() {
+    let _server = Server::start();
+
+    let mut client = Client::connect(TEXT_PORT);
+    for line in ["", "   ", "get", "set onlykey", "frobnicate a b", "mset a", "expire a soon"] {
+        let reply = client.request(line);
+        assert!(reply.starts_with("-ERR BADREQUEST "), "{:?} -> {:?}", line, reply);
+    }
+    assert_eq!(client.request("set still works"), "+OK\r\n");
+}
+
+#[test]
+fn unquoted_words_after_the_value_are_rejected() {
+    let _server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+    for line in ["set k a   b", "set k a b ex 10", "append k a b", "cas k a b c", "publish ch a b"] {
+        assert_eq!(client.request(line), format!("-ERR BADREQUEST too many arguments for {}\r\n", line.split(' ').next().unwrap()));
+    }
+    assert_eq!(client.request("get k"), "NOTFOUND k\r\n");
+    assert_eq!(client.request("set k \"a   b\""), "+OK\r\n");
+    assert_eq!(client.request("get k"), "$5 a   b\r\n");
+}
+
+#[test]
+fn legacy_format_still_joins_the_words_of_a_value() {
+    let _server = Server::start_with(&["--legacy-format"]);
+    let mut client = Client::connect(TEXT_PORT);
+    client.send(b"set k a   b\nstrlen k\n");
+    assert_eq!(client.read_line(), "consumed 12 bytes\r\n");
+    assert_eq!(client.read_line(), "response: Ok\r\n");
+    assert_eq!(client.read_line(), "consumed 9 bytes\r\n");
+    assert_eq!(client.read_line(), "response: Integer(3)\r\n");
+}
+
+#[test]
+fn end_of_stream_closes_connection_cleanly() {
+    let _server = Server::start();
+
+    let mut client = Client::connect(TEXT_PORT);
+    client.send(b"set a 1\nget a\n");
+    client.close_write();
+    assert_eq!(client.read_to_end(), "+OK\r\n$1 1\r\n");
+}
+
+#[test]
+fn resp_framing_error_is_reported_and_connection_closed() {
+    let mut server = Server::start();
+
+    let mut client = Client::connect(RESP_PORT);
+    client.send(b"*2\r\n$abc\r\n");
+    assert!(client.read_line().starts_with("-ERR Protocol error: "));
+    assert_eq!(client.read_to_end(), "");
+
+    let mut client = Client::connect(RESP_PORT);
+    client.send(b"*1\r\n$4\r\nPING\r\n");
+    assert_eq!(client.read_line(), "+PONG\r\n");
+    assert!(server.is_running());
+}
+
+#[test]
+fn resp_truncated_command_keeps_server_running() {
+    let mut server = Server::start();
+
+    let mut client = Client::connect(RESP_PORT);
+    client.send(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$100\r\nshort");
+    drop(client);
+
+    let mut client = Client::connect(RESP_PORT);
+    client.send(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n");
+    assert_eq!(client.read_line(), "$-1\r\n");
+    assert!(server.is_running());
+}
+
+#[test]
+fn resp_rejects_arguments_the_command_does_not_take() {
+    let _server = Server::start();
+
+    let mut client = Client::connect(RESP_PORT);
+    client.send(b"*4\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n$2\r\nNX\r\n");
+    assert_eq!(client.read_line(), "-ERR too many arguments for set\r\n");
+    client.send(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n");
+    assert_eq!(client.read_line(), "$-1\r\n");
+
+    // each argument is one value, even with spaces in it
+    client.send(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$3\r\nv w\r\n");
+    assert_eq!(client.read_line(), "+OK\r\n");
+    client.send(b"*5\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n$2\r\nEX\r\n$2\r\n10\r\n");
+    assert_eq!(client.read_line(), "+OK\r\n");
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+use common::{stat, Client, Server, TEXT_PORT};
+
+#[test]
+fn expire_ttl_and_persist() {
+    let _server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+
+    assert_eq!(client.request("set k v"), "+OK\r\n");
+    assert_eq!(client.request("ttl k"), ":-1\r\n");
+    assert_eq!(client.request("ttl missing"), ":-2\r\n");
+    assert_eq!(client.request("expire missing 10"), ":0\r\n");
+
+    assert_eq!(client.request("expire k 100"), ":1\r\n");
+    assert_eq!(client.request("ttl k"), ":100\r\n");
+    assert_eq!(client.request("persist k"), ":1\r\n");
+    assert_eq!(client.request("ttl k"), ":-1\r\n");
+    assert_eq!(client.request("persist k"), ":0\r\n");
+
+    assert_eq!(client.request("set short lived ex 1"), "+OK\r\n");
+    assert_eq!(client.request("ttl short"), ":1\r\n");
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(client.request("get short"), "NOTFOUND short\r\n");
+    assert_eq!(client.request("ttl short"), ":-2\r\n");
+    assert!(client.request("expire k 0").starts_with("-ERR BADREQUEST "));
+}
+
+#[test]
+fn expired_keys_are_removed_without_being_read() {
+    let _server = Server::start();
+    let mut watcher = Client::connect(TEXT_PORT);
+    assert_eq!(watcher.request("watch a"), ":1\r\n");
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("set a x ex 1"), "+OK\r\n");
+    assert_eq!(client.request("set b y"), "+OK\r\n");
+    assert_eq!(watcher.read_line(), ">event set a\r\n");
+    assert_eq!(watcher.read_line(), ">event expire a\r\n");
+    assert_eq!(stat(&client.request_value("info"), "keys"), 2);
+
+    // the sweep removes the key and tells its watchers
+    assert_eq!(watcher.read_line(), ">event expired a\r\n");
+    assert_eq!(stat(&client.request_value("info"), "keys"), 1);
+}
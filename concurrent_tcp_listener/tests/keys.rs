@@ -0,0 +1,45 @@
+mod common;
+
+use std::time::{Duration, Instant};
+use common::{Client, Server, TEXT_PORT};
+
+/// the sorted names returned by `keys`
+fn keys(client: &mut Client, pattern: &str) -> Vec<String> {
+    client.send(format!("keys {}\n", pattern).as_bytes());
+    let count: usize = client.read_line().trim_end().trim_start_matches('*').parse().unwrap();
+    let mut names = (0..count)
+        .map(|_| client.read_line().trim_end().split_once(' ').unwrap().1.to_string())
+        .collect::<Vec<String>>();
+    names.sort();
+    names
+}
+
+#[test]
+fn keys_match_glob_patterns() {
+    let _server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("mset hello 1 hallo 2 hxllo 3 hllo 4 heeello 5 h*llo 6"), "+OK\r\n");
+
+    assert_eq!(keys(&mut client, "h?llo"), ["h*llo", "hallo", "hello", "hxllo"]);
+    assert_eq!(keys(&mut client, "h*llo"), ["h*llo", "hallo", "heeello", "hello", "hllo", "hxllo"]);
+    assert_eq!(keys(&mut client, "h[ae]llo"), ["hallo", "hello"]);
+    assert_eq!(keys(&mut client, "h[^e]llo"), ["h*llo", "hallo", "hxllo"]);
+    assert_eq!(keys(&mut client, "h[a-f]llo"), ["hallo", "hello"]);
+    assert_eq!(keys(&mut client, "h\\*llo"), ["h*llo"]);
+    assert_eq!(keys(&mut client, "*e*"), ["heeello", "hello"]);
+    assert!(keys(&mut client, "hello?").is_empty());
+}
+
+#[test]
+fn pathological_patterns_take_linear_time() {
+    let _server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+    let key = "a".repeat(200);
+    assert_eq!(client.request(&format!("set {} 1", key)), "+OK\r\n");
+
+    // backtracking into every `*` would take longer than the age of the universe
+    let started = Instant::now();
+    assert!(keys(&mut client, "*a*a*a*a*a*a*a*a*a*a*b").is_empty());
+    assert_eq!(keys(&mut client, "*a*a*a*a*a*a*a*a*a*a"), [key]);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
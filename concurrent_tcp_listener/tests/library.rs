@@ -0,0 +1,144 @@
+mod common;
+
+use std::sync::Arc;
+use common::{data_dir, start_in_process};
+use concurrent_tcp_listener::config::Address;
+use concurrent_tcp_listener::replication::Hub;
+use concurrent_tcp_listener::storage::Engine;
+use concurrent_tcp_listener::store::{Files, Store};
+use concurrent_tcp_listener::wal::Record;
+use concurrent_tcp_listener::{handle_single_request, parse_command, Request, Response, Server};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+
+async fn request<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut BufReader<S>, line: &str) -> String {
+    stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+    let mut reply = String::new();
+    stream.read_line(&mut reply).await.unwrap();
+    reply
+}
+
+#[tokio::test]
+async fn server_runs_on_ephemeral_ports_until_shut_down() {
+    let dir = data_dir("ephemeral");
+    let server = start_in_process(&dir).await;
+    let address = server.local_addr().unwrap();
+    assert_ne!(address.port(), 0);
+
+    let mut text = BufReader::new(TcpStream::connect(address).await.unwrap());
+    assert_eq!(request(&mut text, "set a 1\n").await, "+OK\r\n");
+    let mut resp = BufReader::new(TcpStream::connect(server.resp_addr().unwrap()).await.unwrap());
+    assert_eq!(request(&mut resp, "*2\r\n$3\r\nGET\r\n$1\r\na\r\n").await, "$1\r\n");
+
+    server.shutdown().await.unwrap();
+    assert!(TcpStream::connect(address).await.is_err());
+
+    // the final snapshot keeps the key for the next server on the same data
+    let server = start_in_process(&dir).await;
+    let response = server.service().request(Request::Get("a".to_string())).await.unwrap();
+    assert!(matches!(response, Response::Result(ref value) if value == b"1"), "{:?}", response);
+    server.shutdown().await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn unix_socket_of_a_running_server_is_not_taken_over() {
+    let dir = data_dir("socket");
+    let socket = dir.join("kv.sock");
+    // a stale socket nobody listens on any more is replaced
+    drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+    let server = Server::builder().listen(Address::Unix(socket.clone())).data_dir(&dir).start().await.unwrap();
+
+    let result = Server::builder().listen(Address::Unix(socket.clone())).data_dir(dir.join("second")).start().await;
+    let Err(e) = result else { panic!("a second server took over {}", socket.display()) };
+    assert_eq!(e.kind(), std::io::ErrorKind::AddrInUse);
+    let mut unix = BufReader::new(UnixStream::connect(&socket).await.unwrap());
+    assert_eq!(request(&mut unix, "set still 1\n").await, "+OK\r\n");
+
+    server.shutdown().await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn start_fails_if_the_data_files_cannot_be_used() {
+    let dir = data_dir("unusable");
+    std::fs::write(dir.join("kv_snapshot.dat"), "not a snapshot\n").unwrap();
+
+    let result = Server::builder().listen("127.0.0.1:0".parse().unwrap()).data_dir(&dir).start().await;
+    let Err(e) = result else { panic!("started on a broken snapshot") };
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    assert!(e.to_string().contains("Unable to open the data files"), "{}", e);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn storage_task_answers_parsed_requests() {
+    let dir = data_dir("task");
+    let files = Files { snapshot: dir.join("snapshot.dat"), wal: dir.join("wal.log"), data: dir.join("data.log") };
+    let (tx, rx) = mpsc::channel(10);
+    let store = Store::open(Engine::Memory, &files).unwrap();
+    let task = tokio::spawn(handle_single_request(rx, store, Arc::new(Hub::new())));
+
+    let ask = async |parts: &[&str]| {
+        let request = parse_command(parts).unwrap();
+        let (reply_tx, reply_rx) = oneshot::channel();
+        tx.send((request, reply_tx)).await.unwrap();
+        reply_rx.await.unwrap()
+    };
+    assert!(matches!(ask(&["SET", "k", "v"]).await, Response::Ok()));
+    assert!(matches!(ask(&["incr", "n"]).await, Response::Integer(1)));
+    assert!(matches!(ask(&["get", "k"]).await, Response::Result(value) if value == b"v"));
+    assert!(matches!(ask(&["get", "missing"]).await, Response::NotFound(_)));
+    assert!(parse_command(&["get"]).is_err());
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+    tx.send((Request::Close(), reply_tx)).await.unwrap();
+    assert!(matches!(reply_rx.await.unwrap(), Response::Ok()));
+    task.await.unwrap();
+    assert!(dir.join("snapshot.dat").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn persisting_replaces_the_snapshot_and_empties_the_log() {
+    let dir = data_dir("persist");
+    let files = Files { snapshot: dir.join("snapshot.dat"), wal: dir.join("wal.log"), data: dir.join("data.log") };
+    let mut store = Store::open(Engine::Memory, &files).unwrap();
+    store.write(vec![Record::Set("k".to_string(), b"v".to_vec())]).unwrap();
+    assert!(std::fs::metadata(dir.join("wal.log")).unwrap().len() > 0);
+
+    store.persist();
+    assert!(std::fs::read(dir.join("snapshot.dat")).unwrap().starts_with(b"KVSNAP 2\n"));
+    assert!(!dir.join("snapshot.dat.tmp").exists());
+    assert_eq!(std::fs::metadata(dir.join("wal.log")).unwrap().len(), 0);
+
+    drop(store);
+    let store = Store::open(Engine::Memory, &files).unwrap();
+    assert_eq!(store.get("k").unwrap(), Some(b"v".to_vec()));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn compaction_keeps_only_the_live_records_of_the_disk_engine() {
+    let dir = data_dir("compact");
+    let files = Files { snapshot: dir.join("snapshot.dat"), wal: dir.join("wal.log"), data: dir.join("data.log") };
+    let mut store = Store::open(Engine::Disk, &files).unwrap();
+    // every overwrite turns the previous 64 KiB record into garbage
+    for round in 0..40u8 {
+        store.write(vec![Record::Set("big".to_string(), vec![round; 64 * 1024])]).unwrap();
+    }
+    store.write(vec![Record::Set("small".to_string(), b"kept".to_vec())]).unwrap();
+    assert!(std::fs::metadata(dir.join("data.log")).unwrap().len() > 40 * 64 * 1024);
+
+    store.persist();
+    let len = std::fs::metadata(dir.join("data.log")).unwrap().len();
+    assert!(len < 2 * 64 * 1024, "{} bytes left", len);
+    assert!(!dir.join("data.log.tmp").exists());
+
+    drop(store);
+    let store = Store::open(Engine::Disk, &files).unwrap();
+    assert_eq!(store.get("big").unwrap(), Some(vec![39; 64 * 1024]));
+    assert_eq!(store.get("small").unwrap(), Some(b"kept".to_vec()));
+    let _ = std::fs::remove_dir_all(&dir);
+}
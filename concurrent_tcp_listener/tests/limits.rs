@@ -0,0 +1,104 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+use common::{stat, Client, Server, RESP_PORT, TEXT_PORT};
+
+/// the `name:value` lines of `stats`
+fn stats(client: &mut Client) -> String {
+    client.request_value("stats")
+}
+
+#[test]
+fn connections_beyond_the_maximum_are_rejected_politely() {
+    let _server = Server::start_with(&["--max-connections", "1"]);
+    // the connections probing for the start may still be counted
+    thread::sleep(Duration::from_millis(100));
+
+    let mut first = Client::connect(TEXT_PORT);
+    assert_eq!(first.request("set a 1"), "+OK\r\n");
+    let mut text = Client::connect(TEXT_PORT);
+    assert_eq!(text.read_to_end(), "-ERR BUSY too many connections, try again later\r\n");
+    let mut resp = Client::connect(RESP_PORT);
+    assert_eq!(resp.read_to_end(), "-BUSY too many connections, try again later\r\n");
+
+    let stats = stats(&mut first);
+    assert_eq!(stat(&stats, "connected_clients"), 1);
+    // the connections probing for the start may have been rejected as well
+    assert!(stat(&stats, "rejected_connections") >= 2, "{}", stats);
+    assert_eq!(stat(&stats, "max_connections"), 1);
+
+    // the slot is free again once the first client is gone
+    drop(first);
+    thread::sleep(Duration::from_millis(100));
+    let mut third = Client::connect(TEXT_PORT);
+    assert_eq!(third.request("get a"), "$1 1\r\n");
+}
+
+#[test]
+fn lines_longer_than_the_maximum_close_the_connection() {
+    let _server = Server::start_with(&["--max-line-length", "64"]);
+
+    // 64 bytes including the line break still fit
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request(&format!("set k {}", "x".repeat(57))), "+OK\r\n");
+    client.send(format!("set k {}\n", "x".repeat(100)).as_bytes());
+    assert_eq!(client.read_to_end(), "-ERR TOOLONG request longer than 64 bytes\r\n");
+
+    // a line without an end is cut off just the same
+    let mut client = Client::connect(TEXT_PORT);
+    client.send("x".repeat(200).as_bytes());
+    assert_eq!(client.read_to_end(), "-ERR TOOLONG request longer than 64 bytes\r\n");
+
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(stat(&stats(&mut client), "oversized_requests"), 2);
+}
+
+#[test]
+fn resp_commands_longer_than_the_maximum_close_the_connection() {
+    let _server = Server::start_with(&["--max-line-length", "64"]);
+
+    let mut client = Client::connect(RESP_PORT);
+    client.send(b"*3\r\n$3\r\nset\r\n$1\r\nk\r\n$5\r\nsmall\r\n");
+    assert_eq!(client.read_line(), "+OK\r\n");
+    client.send(format!("*3\r\n$3\r\nset\r\n$1\r\nk\r\n$100\r\n{}\r\n", "x".repeat(100)).as_bytes());
+    assert_eq!(client.read_to_end(), "-TOOLONG request longer than 64 bytes\r\n");
+
+    let mut client = Client::connect(RESP_PORT);
+    client.send(format!("set k {}", "x".repeat(200)).as_bytes());
+    assert_eq!(client.read_to_end(), "-TOOLONG request longer than 64 bytes\r\n");
+}
+
+#[test]
+fn requests_beyond_the_rate_limit_are_refused() {
+    let _server = Server::start_with(&["--rate-limit", "5"]);
+
+    let mut client = Client::connect(TEXT_PORT);
+    for i in 0..20 {
+        client.send(format!("set key{} value\n", i).as_bytes());
+    }
+    let replies = (0..20).map(|_| client.read_line()).collect::<Vec<String>>();
+    let refused = replies.iter().filter(|r| *r == "-ERR RATELIMITED more than 5 requests per second\r\n").count();
+    assert!(refused >= 10, "{:?}", replies);
+    assert_eq!(refused + replies.iter().filter(|r| *r == "+OK\r\n").count(), 20);
+
+    // the connection stays open and gets new requests after a while
+    thread::sleep(Duration::from_secs(1));
+    let stats = stats(&mut client);
+    assert_eq!(stat(&stats, "rate_limited_requests"), refused as u64);
+    assert_eq!(stat(&stats, "rate_limit"), 5);
+}
+
+#[test]
+fn idle_timeouts_are_counted() {
+    let _server = Server::start_with(&["--idle-timeout", "1"]);
+
+    let mut idle = Client::connect(TEXT_PORT);
+    assert_eq!(idle.read_to_end(), "");
+
+    let mut client = Client::connect(TEXT_PORT);
+    let stats = stats(&mut client);
+    assert_eq!(stat(&stats, "idle_timeout_seconds"), 1);
+    // the connections probing for the start may have timed out as well
+    assert!(stat(&stats, "idle_timeouts") >= 1, "{}", stats);
+}
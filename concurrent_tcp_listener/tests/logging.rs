@@ -0,0 +1,41 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+use common::{Client, Server, TEXT_PORT};
+
+#[test]
+fn json_log_has_connection_and_request_spans() {
+    let server = Server::start_with(&["--log-format", "json", "--log-level", "debug"]);
+
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("set a 1"), "+OK\r\n");
+    drop(client);
+    thread::sleep(Duration::from_millis(200));
+
+    let log = server.log();
+    let handled = log.lines()
+        .find(|line| line.contains("\"message\":\"Request handled\"") && line.contains("\"command\":\"set\""))
+        .unwrap_or_else(|| panic!("no handled request in {}", log));
+    assert!(handled.starts_with('{'), "{}", handled);
+    for field in ["\"name\":\"connection\"", "\"peer\":\"127.0.0.1:", "\"name\":\"request\""] {
+        assert!(handled.contains(field), "{} missing in {}", field, handled);
+    }
+}
+
+#[test]
+fn command_line_log_level_overrides_rust_log() {
+    let quiet = Server::start_with_env(&[], &[("RUST_LOG", "warn")]);
+    Client::connect(TEXT_PORT).request("get a");
+    thread::sleep(Duration::from_millis(200));
+    assert!(!quiet.log().contains("Connection from"), "{}", quiet.log());
+    drop(quiet);
+
+    let verbose = Server::start_with_env(&["--log-level", "info"], &[("RUST_LOG", "warn")]);
+    Client::connect(TEXT_PORT).request("get a");
+    thread::sleep(Duration::from_millis(200));
+    let log = verbose.log();
+    let line = log.lines().find(|line| line.contains("Connection from")).unwrap_or_else(|| panic!("{}", log));
+    // human output names the spans an event happened in
+    assert!(line.contains("connection{peer=127.0.0.1:"), "{}", line);
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use common::{stat, Client, Server, RESP_PORT, TEXT_PORT};
+
+const METRICS_PORT: u16 = 9400;
+
+/// status line and body of a GET request to the metrics port
+fn http_get(path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", METRICS_PORT)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").expect("a complete response");
+    (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+#[test]
+fn info_reports_keys_and_commands() {
+    let _server = Server::start();
+
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("set a 1"), "+OK\r\n");
+    assert_eq!(client.request("set b 22"), "+OK\r\n");
+    assert_eq!(client.request("get a"), "$1 1\r\n");
+
+    let info = client.request_value("info");
+    for section in ["# Server", "# Clients", "# Keyspace", "# Persistence", "# Commands"] {
+        assert!(info.lines().any(|line| line == section), "{} missing in {}", section, info);
+    }
+    assert_eq!(stat(&info, "keys"), 2);
+    assert!(stat(&info, "memory_estimate_bytes") >= 5, "{}", info);
+    assert!(stat(&info, "connected_clients") >= 1, "{}", info);
+    assert_eq!(stat(&info, "cmd_set"), 2);
+    assert_eq!(stat(&info, "cmd_get"), 1);
+    assert_eq!(stat(&info, "last_persist_time"), 0);
+}
+
+#[test]
+fn info_reports_the_last_snapshot() {
+    let _server = Server::start_with(&["--persist-interval", "1"]);
+
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("set a 1"), "+OK\r\n");
+    thread::sleep(Duration::from_millis(1500));
+
+    let info = client.request_value("info");
+    assert!(stat(&info, "last_persist_time") > 1_600_000_000, "{}", info);
+    assert!(stat(&info, "cmd_snapshot") >= 1, "{}", info);
+}
+
+#[test]
+fn info_over_resp_is_a_bulk_string() {
+    let _server = Server::start_with(&["--shards", "4"]);
+
+    let mut client = Client::connect(RESP_PORT);
+    client.send(b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n");
+    assert_eq!(client.read_line(), "+OK\r\n");
+    assert_eq!(client.read_line(), "+OK\r\n");
+    client.send(b"*2\r\n$4\r\nINFO\r\n$6\r\nserver\r\n");
+    let header = client.read_line();
+    let len = header.trim_end().strip_prefix('$').unwrap().parse::<usize>().unwrap();
+    let mut info = String::new();
+    while info.len() < len + 2 {
+        info.push_str(&client.read_line());
+    }
+    // keys of all shards are counted
+    assert_eq!(stat(&info, "keys"), 2);
+    assert_eq!(stat(&info, "cmd_set"), 2);
+}
+
+#[test]
+fn metrics_endpoint_serves_prometheus_text() {
+    let port = METRICS_PORT.to_string();
+    let _server = Server::start_with(&["--metrics-listen", &format!("127.0.0.1:{}", port)]);
+
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("set a 1"), "+OK\r\n");
+
+    let (status, body) = http_get("/metrics");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    for line in ["# TYPE kv_keys gauge",
+                 "kv_keys 1",
+                 "kv_commands_total{command=\"set\"} 1",
+                 "# TYPE kv_request_duration_seconds histogram",
+                 "kv_request_duration_seconds_bucket{command=\"set\",le=\"+Inf\"} 1",
+                 "kv_request_duration_seconds_count{command=\"set\"} 1"] {
+        assert!(body.lines().any(|l| l == line), "{:?} missing in {}", line, body);
+    }
+    assert!(body.lines().any(|l| l.starts_with("kv_connected_clients ")), "{}", body);
+
+    let (status, _) = http_get("/");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::{Client, Server, TEXT_PORT};
+
+#[test]
+fn pipelined_requests_are_answered_in_order() {
+    let _server = Server::start_with(&["--shards", "4"]);
+    let mut client = Client::connect(TEXT_PORT);
+
+    // every request depends on the ones before it, some of them on several shards
+    let mut batch = String::new();
+    for i in 0..50 {
+        batch.push_str(&format!("set key{} {}\nincr key{}\n", i, i, i));
+    }
+    batch.push_str("mget key0 key49 missing\ndel key0 key1 key2 key3\nexists key0 key1 key2 key3 key4\n");
+    batch.push_str("bogus\nmulti\nincr key4\nexec\nget key4\n");
+    client.send(batch.as_bytes());
+
+    for i in 0..50 {
+        assert_eq!(client.read_line(), "+OK\r\n");
+        assert_eq!(client.read_line(), format!(":{}\r\n", i + 1));
+    }
+    for expected in ["*3\r\n", "$1 1\r\n", "$2 50\r\n", "$-1\r\n", ":4\r\n", ":1\r\n"] {
+        assert_eq!(client.read_line(), expected);
+    }
+    assert!(client.read_line().starts_with("-ERR BADREQUEST"));
+    for expected in ["+OK\r\n", "+QUEUED\r\n", "*1\r\n", ":6\r\n", "$1 6\r\n"] {
+        assert_eq!(client.read_line(), expected);
+    }
+}
+
+#[test]
+fn partial_line_does_not_hold_back_replies() {
+    let _server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+
+    client.send(b"set a 1\nget a\nget");
+    assert_eq!(client.read_line(), "+OK\r\n");
+    assert_eq!(client.read_line(), "$1 1\r\n");
+    client.send(b" a\n");
+    assert_eq!(client.read_line(), "$1 1\r\n");
+}
+
+#[test]
+fn replies_are_written_before_the_connection_closes() {
+    let _server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+
+    client.send(b"set a 1\nincr a\nget a\n");
+    client.close_write();
+    assert_eq!(client.read_to_end(), "+OK\r\n:2\r\n$1 2\r\n");
+}
+
+#[test]
+fn pushes_follow_the_replies_to_earlier_requests() {
+    let _server = Server::start();
+    for i in 0..50 {
+        let mut client = Client::connect(TEXT_PORT);
+        // the message is published while the reply to subscribe may still be queued
+        client.send(format!("subscribe ch{i}\npublish ch{i} hi\nget x\n").as_bytes());
+        assert_eq!(client.read_line(), ":1\r\n", "{}", i);
+        assert_eq!(client.read_line(), ":1\r\n", "{}", i);
+        let mut rest = [client.read_line(), client.read_line()];
+        rest.sort();
+        assert_eq!(rest, [format!(">message ch{i} $2 hi\r\n"), "NOTFOUND x\r\n".to_string()], "{}", i);
+    }
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+use common::{Client, Server, TEXT_PORT};
+
+#[test]
+fn messages_reach_channel_and_pattern_subscribers() {
+    let _server = Server::start();
+    let mut subscriber = Client::connect(TEXT_PORT);
+    let mut other = Client::connect(TEXT_PORT);
+    let mut publisher = Client::connect(TEXT_PORT);
+
+    assert_eq!(subscriber.request("subscribe chat"), ":1\r\n");
+    assert_eq!(subscriber.request("psubscribe news.*"), ":2\r\n");
+    assert_eq!(other.request("subscribe chat news.sport"), ":2\r\n");
+
+    assert_eq!(publisher.request("publish chat \"hello there\""), ":2\r\n");
+    assert_eq!(publisher.request("publish news.sport goal"), ":2\r\n");
+    assert_eq!(publisher.request("publish elsewhere nobody"), ":0\r\n");
+
+    assert_eq!(subscriber.read_line(), ">message chat $11 hello there\r\n");
+    assert_eq!(subscriber.read_line(), ">pmessage news.* news.sport $4 goal\r\n");
+    assert_eq!(other.read_line(), ">message chat $11 hello there\r\n");
+    assert_eq!(other.read_line(), ">message news.sport $4 goal\r\n");
+}
+
+#[test]
+fn unsubscribing_and_disconnecting_end_the_subscriptions() {
+    let _server = Server::start();
+    let mut subscriber = Client::connect(TEXT_PORT);
+    let mut publisher = Client::connect(TEXT_PORT);
+
+    assert_eq!(subscriber.request("subscribe a b"), ":2\r\n");
+    assert_eq!(subscriber.request("psubscribe c*"), ":3\r\n");
+    assert_eq!(subscriber.request("unsubscribe a"), ":2\r\n");
+    assert_eq!(subscriber.request("punsubscribe"), ":1\r\n");
+    assert_eq!(publisher.request("publish a x"), ":0\r\n");
+    assert_eq!(publisher.request("publish c x"), ":0\r\n");
+    assert_eq!(publisher.request("publish b x"), ":1\r\n");
+    assert_eq!(subscriber.read_line(), ">message b $1 x\r\n");
+
+    // the subscriptions of a closed connection are dropped soon after
+    drop(subscriber);
+    let started = Instant::now();
+    while publisher.request("publish b x") != ":0\r\n" {
+        assert!(started.elapsed() < Duration::from_secs(5), "subscription outlived its connection");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
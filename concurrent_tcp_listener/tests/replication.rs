@@ -0,0 +1,144 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use common::{Client, Server, TEXT_PORT};
+
+const FOLLOWER_TEXT_PORT: u16 = 8100;
+const FOLLOWER_RESP_PORT: u16 = 6480;
+
+/// ask the follower until it gives the expected reply, replication is asynchronous
+fn eventually(client: &mut Client, line: &str, expected: &str) {
+    let started = Instant::now();
+    loop {
+        let reply = client.request(line);
+        if reply == expected {
+            return;
+        }
+        assert!(started.elapsed() < Duration::from_secs(5), "{:?} still answers {:?}, expected {:?}", line, reply, expected);
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn follower_receives_snapshot_and_changes_and_redirects_writes() {
+    let _leader = Server::start();
+    let mut leader = Client::connect(TEXT_PORT);
+    assert_eq!(leader.request("mset a 1 b 2"), "+OK\r\n");
+    assert_eq!(leader.request("set temp x ex 100"), "+OK\r\n");
+
+    let leader_address = format!("127.0.0.1:{}", TEXT_PORT);
+    let _follower = Server::start_second(FOLLOWER_TEXT_PORT, FOLLOWER_RESP_PORT, &["--replicaof", &leader_address]);
+    let mut follower = Client::connect(FOLLOWER_TEXT_PORT);
+    eventually(&mut follower, "get a", "$1 1\r\n");
+    assert_eq!(follower.request("ttl temp"), ":100\r\n");
+
+    assert_eq!(follower.request("set a 5"), format!("-ERR REDIRECT {}\r\n", leader_address));
+    assert_eq!(follower.request("get a"), "$1 1\r\n");
+
+    assert_eq!(leader.request("del b"), ":1\r\n");
+    assert_eq!(leader.request("incr counter"), ":1\r\n");
+    eventually(&mut follower, "get counter", "$1 1\r\n");
+    assert_eq!(follower.request("exists b"), ":0\r\n");
+
+    let mut resp_follower = Client::connect(FOLLOWER_RESP_PORT);
+    resp_follower.send(b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n9\r\n");
+    assert_eq!(resp_follower.read_line(), format!("-REDIRECT {}\r\n", leader_address));
+}
+
+/// Forwards connections to the leader and remembers the first line of each side,
+/// so a test can cut the replication stream and see how it is resumed.
+struct Proxy {
+    port: u16,
+    connections: Arc<Mutex<Vec<TcpStream>>>,
+    handshakes: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl Proxy {
+    fn start(target: u16) -> Proxy {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let handshakes = Arc::new(Mutex::new(Vec::new()));
+        let (open, seen) = (connections.clone(), handshakes.clone());
+        thread::spawn(move || {
+            for follower in listener.incoming() {
+                let Ok(follower) = follower else { break };
+                let leader = TcpStream::connect(("127.0.0.1", target)).unwrap();
+                open.lock().unwrap().push(follower.try_clone().unwrap());
+                let seen = seen.clone();
+                thread::spawn(move || forward_with_handshake(follower, leader, seen));
+            }
+        });
+        Proxy { port, connections, handshakes }
+    }
+
+    /// cut all connections, the follower has to reconnect
+    fn disconnect(&self) {
+        for stream in self.connections.lock().unwrap().drain(..) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn handshakes(&self) -> Vec<(String, String)> {
+        self.handshakes.lock().unwrap().clone()
+    }
+}
+
+fn forward_with_handshake(follower: TcpStream, leader: TcpStream, seen: Arc<Mutex<Vec<(String, String)>>>) {
+    let mut from_follower = BufReader::new(follower.try_clone().unwrap());
+    let mut from_leader = BufReader::new(leader.try_clone().unwrap());
+    let (mut to_leader, mut to_follower) = (leader, follower);
+
+    let mut request = String::new();
+    if from_follower.read_line(&mut request).unwrap_or(0) == 0 {
+        return;
+    }
+    to_leader.write_all(request.as_bytes()).unwrap();
+    let mut reply = String::new();
+    if from_leader.read_line(&mut reply).unwrap_or(0) == 0 {
+        return;
+    }
+    seen.lock().unwrap().push((request.trim_end().to_string(), reply.trim_end().to_string()));
+    if to_follower.write_all(reply.as_bytes()).is_err() {
+        return;
+    }
+
+    thread::spawn(move || copy(from_follower, to_leader));
+    copy(from_leader, to_follower);
+}
+
+fn copy(mut from: impl Read, mut to: TcpStream) {
+    let _ = std::io::copy(&mut from, &mut to);
+    let _ = to.shutdown(Shutdown::Both);
+}
+
+#[test]
+fn follower_continues_from_its_offset_after_a_disconnect() {
+    let _leader = Server::start();
+    let mut leader = Client::connect(TEXT_PORT);
+    assert_eq!(leader.request("set before 1"), "+OK\r\n");
+
+    let proxy = Proxy::start(TEXT_PORT);
+    let leader_address = format!("127.0.0.1:{}", proxy.port);
+    let _follower = Server::start_second(FOLLOWER_TEXT_PORT, FOLLOWER_RESP_PORT, &["--replicaof", &leader_address]);
+    let mut follower = Client::connect(FOLLOWER_TEXT_PORT);
+    eventually(&mut follower, "get before", "$1 1\r\n");
+
+    proxy.disconnect();
+    assert_eq!(leader.request("set during 2"), "+OK\r\n");
+    eventually(&mut follower, "get during", "$1 2\r\n");
+    assert_eq!(leader.request("set after 3"), "+OK\r\n");
+    eventually(&mut follower, "get after", "$1 3\r\n");
+
+    let handshakes = proxy.handshakes();
+    assert!(handshakes.len() >= 2, "no reconnect seen: {:?}", handshakes);
+    assert_eq!(handshakes[0].0, "sync");
+    assert!(handshakes[0].1.starts_with("+FULLSYNC "), "{:?}", handshakes[0]);
+    let id = handshakes[0].1.split_whitespace().nth(1).unwrap();
+    assert_eq!(handshakes[1].0, format!("sync {} 1", id));
+    assert_eq!(handshakes[1].1, "+CONTINUE");
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::{Client, Server, RESP_PORT};
+
+/// a command as an array of bulk strings
+fn command(args: &[&str]) -> Vec<u8> {
+    let mut bytes = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        bytes.extend(format!("${}\r\n{}\r\n", arg.len(), arg).into_bytes());
+    }
+    bytes
+}
+
+/// send a command and return the reply, which takes `lines` lines
+fn request(client: &mut Client, args: &[&str], lines: usize) -> String {
+    client.send(&command(args));
+    (0..lines).map(|_| client.read_line()).collect()
+}
+
+#[test]
+fn commands_round_trip() {
+    let _server = Server::start();
+    let mut client = Client::connect(RESP_PORT);
+
+    assert_eq!(request(&mut client, &["PING"], 1), "+PONG\r\n");
+    assert_eq!(request(&mut client, &["ECHO", "hi"], 2), "$2\r\nhi\r\n");
+    assert_eq!(request(&mut client, &["SET", "k", "hello world"], 1), "+OK\r\n");
+    assert_eq!(request(&mut client, &["GET", "k"], 2), "$11\r\nhello world\r\n");
+    assert_eq!(request(&mut client, &["get", "missing"], 1), "$-1\r\n");
+    assert_eq!(request(&mut client, &["INCR", "n"], 1), ":1\r\n");
+    assert_eq!(request(&mut client, &["MSET", "a", "1", "b", "2"], 1), "+OK\r\n");
+    assert_eq!(request(&mut client, &["MGET", "a", "missing", "b"], 6), "*3\r\n$1\r\n1\r\n$-1\r\n$1\r\n2\r\n");
+    assert_eq!(request(&mut client, &["KEYS", "k*"], 3), "*1\r\n$1\r\nk\r\n");
+    assert_eq!(request(&mut client, &["EXPIRE", "k", "100"], 1), ":1\r\n");
+    assert_eq!(request(&mut client, &["TTL", "k"], 1), ":100\r\n");
+    assert_eq!(request(&mut client, &["DEL", "a", "b", "missing"], 1), ":2\r\n");
+    assert_eq!(request(&mut client, &["EXISTS", "k", "a"], 1), ":1\r\n");
+
+    assert!(request(&mut client, &["INCR", "k"], 1).starts_with("-ERR "));
+    assert!(request(&mut client, &["FROBNICATE"], 1).starts_with("-ERR not a valid request"));
+    assert_eq!(request(&mut client, &["PING"], 1), "+PONG\r\n");
+}
+
+#[test]
+fn pipelined_commands_are_answered_in_order() {
+    let _server = Server::start();
+    let mut client = Client::connect(RESP_PORT);
+
+    let mut batch = Vec::new();
+    for n in 1..=10 {
+        batch.extend(command(&["INCR", "counter"]));
+        batch.extend(command(&["SET", &format!("key{}", n), &n.to_string()]));
+    }
+    client.send(&batch);
+    for n in 1..=10 {
+        assert_eq!(client.read_line(), format!(":{}\r\n", n));
+        assert_eq!(client.read_line(), "+OK\r\n");
+    }
+}
+
+/// read the rest of a reply to hello, which ends with the empty list of modules
+fn finish_hello(client: &mut Client) {
+    while client.read_line() != "modules\r\n" {}
+    assert_eq!(client.read_line(), "*0\r\n");
+}
+
+#[test]
+fn hello_negotiates_the_protocol_version() {
+    let _server = Server::start();
+    let mut client = Client::connect(RESP_PORT);
+
+    // RESP2 until the client asks for more
+    assert_eq!(request(&mut client, &["HELLO", "2"], 3), "*12\r\n$6\r\nserver\r\n");
+    finish_hello(&mut client);
+    assert_eq!(request(&mut client, &["GET", "missing"], 1), "$-1\r\n");
+
+    assert_eq!(request(&mut client, &["HELLO", "3"], 3), "%6\r\n$6\r\nserver\r\n");
+    finish_hello(&mut client);
+    assert_eq!(request(&mut client, &["GET", "missing"], 1), "_\r\n");
+    assert_eq!(request(&mut client, &["HELLO", "4"], 1), "-NOPROTO unsupported protocol version\r\n");
+    assert_eq!(request(&mut client, &["GET", "missing"], 1), "_\r\n");
+
+    // a name is accepted, unknown or incomplete options keep the version
+    assert_eq!(request(&mut client, &["HELLO", "3", "SETNAME", "app"], 3), "%6\r\n$6\r\nserver\r\n");
+    finish_hello(&mut client);
+    assert_eq!(request(&mut client, &["HELLO", "2", "LIBNAME", "x"], 1), "-ERR syntax error in HELLO option 'LIBNAME'\r\n");
+    assert_eq!(request(&mut client, &["HELLO", "2", "AUTH", "user"], 1), "-ERR syntax error in HELLO option 'AUTH'\r\n");
+    assert_eq!(request(&mut client, &["GET", "missing"], 1), "_\r\n");
+}
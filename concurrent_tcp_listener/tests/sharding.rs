@@ -0,0 +1,72 @@
+mod common;
+
+use common::{Client, Server, TEXT_PORT};
+
+const SHARDS: &[&str] = &["--shards", "4"];
+
+#[test]
+fn multi_key_commands_span_shards() {
+    let _server = Server::start_with(SHARDS);
+    let mut client = Client::connect(TEXT_PORT);
+
+    assert_eq!(client.request("mset a 1 b 2 c 3 d 4 e 5"), "+OK\r\n");
+    client.send(b"mget e d missing c b a\n");
+    assert_eq!(client.read_line(), "*6\r\n");
+    for expected in ["$1 5\r\n", "$1 4\r\n", "$-1\r\n", "$1 3\r\n", "$1 2\r\n", "$1 1\r\n"] {
+        assert_eq!(client.read_line(), expected);
+    }
+
+    client.send(b"keys *\n");
+    assert_eq!(client.read_line(), "*5\r\n");
+    for expected in ["$1 a\r\n", "$1 b\r\n", "$1 c\r\n", "$1 d\r\n", "$1 e\r\n"] {
+        assert_eq!(client.read_line(), expected);
+    }
+
+    assert_eq!(client.request("exists a b missing e"), ":3\r\n");
+    assert_eq!(client.request("del a b c missing"), ":3\r\n");
+    assert_eq!(client.request("exists a b c d e"), ":2\r\n");
+}
+
+#[test]
+fn transaction_across_shards_is_rejected() {
+    let _server = Server::start_with(SHARDS);
+    let mut client = Client::connect(TEXT_PORT);
+
+    // find two keys on different shards by watching which pair a transaction refuses
+    let keys: Vec<String> = (0..16).map(|i| format!("key{}", i)).collect();
+    let mut rejected = false;
+    for other in &keys[1..] {
+        assert_eq!(client.request("multi"), "+OK\r\n");
+        assert_eq!(client.request(&format!("set {} 1", keys[0])), "+QUEUED\r\n");
+        assert_eq!(client.request(&format!("set {} 2", other)), "+QUEUED\r\n");
+        let reply = client.request("exec");
+        if reply.starts_with("-ERR CROSSSHARD") {
+            rejected = true;
+            assert_eq!(client.request(&format!("exists {}", other)), ":0\r\n");
+            break;
+        }
+        assert_eq!(reply, "*2\r\n");
+        client.read_line();
+        client.read_line();
+    }
+    assert!(rejected, "16 keys should not all land on the same of 4 shards");
+}
+
+#[test]
+fn data_survives_a_change_of_the_shard_count() {
+    let mut server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+    for i in 0..20 {
+        assert_eq!(client.request(&format!("set key{} value{}", i, i)), "+OK\r\n");
+    }
+    drop(client);
+
+    for args in [SHARDS, &["--shards", "3"][..], &[][..]] {
+        server.restart_with(args);
+        let mut client = Client::connect(TEXT_PORT);
+        for i in 0..20 {
+            let value = format!("value{}", i);
+            assert_eq!(client.request(&format!("get key{}", i)), format!("${} {}\r\n", value.len(), value));
+        }
+    }
+}
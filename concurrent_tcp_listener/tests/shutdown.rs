@@ -0,0 +1,27 @@
+mod common;
+
+use common::{Client, Server, RESP_PORT, TEXT_PORT};
+
+#[test]
+fn sigterm_writes_the_final_snapshot_and_exits_cleanly() {
+    let mut server = Server::start_with(&["--persist-interval", "3600"]);
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("set kept forever"), "+OK\r\n");
+    let mut subscriber = Client::connect(TEXT_PORT);
+    assert_eq!(subscriber.request("subscribe news"), ":1\r\n");
+    let mut resp = Client::connect(RESP_PORT);
+    resp.send(b"*1\r\n$4\r\nPING\r\n");
+    assert_eq!(resp.read_line(), "+PONG\r\n");
+
+    let status = server.terminate();
+    assert!(status.success(), "{:?}", status);
+
+    // open connections are told before they are closed
+    assert_eq!(client.read_to_end(), ">shutdown server is going away\r\n");
+    assert_eq!(subscriber.read_to_end(), ">shutdown server is going away\r\n");
+    assert_eq!(resp.read_to_end(), "-ERR server is shutting down\r\n");
+
+    let snapshot = std::fs::read(server.dir().join("kv_snapshot.dat")).unwrap();
+    assert!(snapshot.windows(4).any(|w| w == b"kept"), "{:?}", String::from_utf8_lossy(&snapshot));
+    assert_eq!(std::fs::metadata(server.dir().join("kv_wal.log")).unwrap().len(), 0);
+}
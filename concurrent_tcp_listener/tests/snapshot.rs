@@ -0,0 +1,48 @@
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+use common::{Client, Server, TEXT_PORT};
+
+#[test]
+fn keys_are_loaded_from_the_snapshot_after_a_restart() {
+    let mut server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("set greeting \"hello world\""), "+OK\r\n");
+    assert_eq!(client.request("set expiring soon ex 100"), "+OK\r\n");
+    assert_eq!(client.request("set deleted x"), "+OK\r\n");
+    assert_eq!(client.request("del deleted"), ":1\r\n");
+    drop(client);
+
+    // the final snapshot holds everything, the write-ahead log is emptied
+    server.restart_with(&[]);
+    let snapshot = std::fs::read(server.dir().join("kv_snapshot.dat")).unwrap();
+    assert!(snapshot.starts_with(b"KVSNAP 2\n"), "{:?}", String::from_utf8_lossy(&snapshot));
+    assert_eq!(std::fs::metadata(server.dir().join("kv_wal.log")).unwrap().len(), 0);
+
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("get greeting"), "$11 hello world\r\n");
+    assert_eq!(client.request("ttl expiring"), ":100\r\n");
+    assert_eq!(client.request("get deleted"), "NOTFOUND deleted\r\n");
+}
+
+#[test]
+fn snapshot_is_written_while_the_server_runs() {
+    let mut server = Server::start_with(&["--persist-interval", "1"]);
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("set periodic yes"), "+OK\r\n");
+
+    let snapshot = server.dir().join("kv_snapshot.dat");
+    let started = Instant::now();
+    while !std::fs::read(&snapshot).is_ok_and(|content| content.windows(8).any(|w| w == b"periodic")) {
+        assert!(started.elapsed() < Duration::from_secs(10), "no snapshot written");
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    // nothing is lost if the server dies after the snapshot
+    drop(client);
+    server.kill();
+    server.restart_with(&[]);
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("get periodic"), "$3 yes\r\n");
+}
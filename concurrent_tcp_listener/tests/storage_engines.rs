@@ -0,0 +1,49 @@
+mod common;
+
+use common::{Client, Server, TEXT_PORT};
+
+const ENGINES: [&str; 3] = ["memory", "ordered", "disk"];
+
+fn value_reply(value: &str) -> String {
+    format!("${} {}\r\n", value.len(), value)
+}
+
+#[test]
+fn every_engine_serves_the_same_commands() {
+    for engine in ENGINES {
+        let _server = Server::start_with(&["--engine", engine]);
+        let mut client = Client::connect(TEXT_PORT);
+
+        assert_eq!(client.request("mset user:1 ann user:2 bob item:1 pen"), "+OK\r\n", "{}", engine);
+        assert_eq!(client.request("get user:2"), value_reply("bob"), "{}", engine);
+        assert_eq!(client.request("append user:2 by"), ":5\r\n", "{}", engine);
+        assert_eq!(client.request("incr counter"), ":1\r\n", "{}", engine);
+        assert_eq!(client.request("expire item:1 100"), ":1\r\n", "{}", engine);
+        assert_eq!(client.request("ttl item:1"), ":100\r\n", "{}", engine);
+        assert_eq!(client.request("del user:1 missing"), ":1\r\n", "{}", engine);
+
+        client.send(b"keys user:*\n");
+        assert_eq!(client.read_line(), "*1\r\n", "{}", engine);
+        assert_eq!(client.read_line(), value_reply("user:2"), "{}", engine);
+    }
+}
+
+#[test]
+fn data_moves_along_when_the_engine_changes() {
+    let mut server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("set kept value"), "+OK\r\n");
+    assert_eq!(client.request("set expiring soon ex 100"), "+OK\r\n");
+    drop(client);
+
+    for engine in ["disk", "ordered", "disk", "memory"] {
+        server.restart_with(&["--engine", engine]);
+        let mut client = Client::connect(TEXT_PORT);
+        assert_eq!(client.request("get kept"), value_reply("value"), "{}", engine);
+        assert_eq!(client.request("ttl expiring"), ":100\r\n", "{}", engine);
+        assert_eq!(client.request(&format!("set {} yes", engine)), "+OK\r\n", "{}", engine);
+    }
+
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("exists disk ordered memory"), ":3\r\n");
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+use tokio_rustls::rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use common::{Client, Server, RESP_PORT, TEXT_PORT};
+
+const FOLLOWER_TEXT_PORT: u16 = 8100;
+const FOLLOWER_RESP_PORT: u16 = 6480;
+
+/// a certificate authority with a server and a client certificate, written as PEM files
+struct Certificates {
+    dir: PathBuf,
+}
+
+impl Certificates {
+    fn generate() -> Certificates {
+        let dir = std::env::temp_dir().join(format!("kv-test-tls-{}-{:?}", std::process::id(), thread::current().id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+        std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+        let names = [("server", vec!["localhost", "127.0.0.1"]), ("client", vec!["client"])];
+        for (name, alt_names) in names {
+            let key = KeyPair::generate().unwrap();
+            let alt_names = alt_names.into_iter().map(String::from).collect::<Vec<String>>();
+            let cert = CertificateParams::new(alt_names).unwrap().signed_by(&key, &ca).unwrap();
+            std::fs::write(dir.join(format!("{}.pem", name)), cert.pem()).unwrap();
+            std::fs::write(dir.join(format!("{}-key.pem", name)), key.serialize_pem()).unwrap();
+        }
+        Certificates { dir }
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_str().unwrap().to_string()
+    }
+
+    /// arguments serving TLS with the server certificate
+    fn server_args(&self) -> Vec<String> {
+        vec!["--tls-cert".to_string(), self.path("server.pem"), "--tls-key".to_string(), self.path("server-key.pem")]
+    }
+
+    /// a client trusting the authority, presenting the client certificate if `with_cert`
+    fn client_config(&self, with_cert: bool) -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file(self.path("ca.pem")).unwrap()).unwrap();
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let config = if with_cert {
+            let chain = vec![CertificateDer::from_pem_file(self.path("client.pem")).unwrap()];
+            let key = PrivateKeyDer::from_pem_file(self.path("client-key.pem")).unwrap();
+            builder.with_client_auth_cert(chain, key).unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        Arc::new(config)
+    }
+}
+
+impl Drop for Certificates {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A blocking client speaking TLS, reading replies line by line.
+struct TlsClient {
+    stream: BufReader<StreamOwned<ClientConnection, TcpStream>>,
+}
+
+impl TlsClient {
+    fn connect(port: u16, config: Arc<ClientConfig>) -> TlsClient {
+        let socket = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let connection = ClientConnection::new(config, ServerName::try_from("localhost").unwrap()).unwrap();
+        TlsClient { stream: BufReader::new(StreamOwned::new(connection, socket)) }
+    }
+
+    /// send bytes and return the first reply line, an error if the server rejected the client
+    fn try_request(&mut self, bytes: &[u8]) -> std::io::Result<String> {
+        self.stream.get_mut().write_all(bytes)?;
+        self.stream.get_mut().flush()?;
+        let mut line = String::new();
+        self.stream.read_line(&mut line)?;
+        Ok(line)
+    }
+
+    fn request(&mut self, line: &str) -> String {
+        self.try_request(format!("{}\n", line).as_bytes()).unwrap()
+    }
+}
+
+#[test]
+fn both_ports_speak_tls() {
+    let certs = Certificates::generate();
+    let args = certs.server_args();
+    let _server = Server::start_with(&args.iter().map(String::as_str).collect::<Vec<&str>>());
+
+    let mut client = TlsClient::connect(TEXT_PORT, certs.client_config(false));
+    assert_eq!(client.request("set greeting hello"), "+OK\r\n");
+    assert_eq!(client.request("get greeting"), "$5 hello\r\n");
+
+    let mut resp = TlsClient::connect(RESP_PORT, certs.client_config(false));
+    assert_eq!(resp.try_request(b"*2\r\n$3\r\nGET\r\n$8\r\ngreeting\r\n").unwrap(), "$5\r\n");
+
+    // a plaintext client gets nothing but a closed connection
+    let mut plain = Client::connect(TEXT_PORT);
+    plain.send(b"get greeting\n");
+    assert!(!plain.read_to_end().contains("hello"));
+}
+
+#[test]
+fn client_certificates_are_required_with_a_client_ca() {
+    let certs = Certificates::generate();
+    let mut args = certs.server_args();
+    args.extend(["--tls-client-ca".to_string(), certs.path("ca.pem")]);
+    let _server = Server::start_with(&args.iter().map(String::as_str).collect::<Vec<&str>>());
+
+    let mut anonymous = TlsClient::connect(TEXT_PORT, certs.client_config(false));
+    assert!(anonymous.try_request(b"get a\n").is_err(), "accepted a client without certificate");
+
+    let mut client = TlsClient::connect(TEXT_PORT, certs.client_config(true));
+    assert_eq!(client.request("set a 1"), "+OK\r\n");
+}
+
+#[test]
+fn follower_replicates_over_tls() {
+    let certs = Certificates::generate();
+    let mut args = certs.server_args();
+    args.extend(["--tls-client-ca".to_string(), certs.path("ca.pem")]);
+    let _leader = Server::start_with(&args.iter().map(String::as_str).collect::<Vec<&str>>());
+    let mut leader = TlsClient::connect(TEXT_PORT, certs.client_config(true));
+    assert_eq!(leader.request("set replicated yes"), "+OK\r\n");
+
+    // the follower presents its own certificate, the leader's authority signed it
+    let leader_address = format!("127.0.0.1:{}", TEXT_PORT);
+    let mut args = certs.server_args();
+    args.extend(["--replicaof".to_string(), leader_address, "--leader-tls-ca".to_string(), certs.path("ca.pem")]);
+    let _follower = Server::start_second(FOLLOWER_TEXT_PORT, FOLLOWER_RESP_PORT,
+        &args.iter().map(String::as_str).collect::<Vec<&str>>());
+    let mut follower = TlsClient::connect(FOLLOWER_TEXT_PORT, certs.client_config(false));
+    let started = Instant::now();
+    while follower.request("get replicated") != "$3 yes\r\n" {
+        assert!(started.elapsed() < Duration::from_secs(5), "nothing replicated");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn stalled_handshakes_over_the_limit_are_closed_without_waiting() {
+    let certs = Certificates::generate();
+    let mut args = certs.server_args();
+    args.extend(["--max-connections".to_string(), "1".to_string()]);
+    let _server = Server::start_with(&args.iter().map(String::as_str).collect::<Vec<&str>>());
+    // the connections probing for the start may still be counted
+    thread::sleep(Duration::from_millis(100));
+    let mut client = TlsClient::connect(TEXT_PORT, certs.client_config(false));
+    assert_eq!(client.request("set a 1"), "+OK\r\n");
+
+    // clients that never start their handshake keep every rejecting task busy
+    let stalled = (0..16).map(|_| TcpStream::connect(("127.0.0.1", TEXT_PORT)).unwrap()).collect::<Vec<TcpStream>>();
+    thread::sleep(Duration::from_millis(100));
+    let started = Instant::now();
+    let mut next = Client::connect(TEXT_PORT);
+    assert_eq!(next.read_to_end(), "");
+    assert!(started.elapsed() < Duration::from_secs(2));
+    drop(stalled);
+    assert_eq!(client.request("get a"), "$1 1\r\n");
+}
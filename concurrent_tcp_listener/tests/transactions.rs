@@ -0,0 +1,71 @@
+mod common;
+
+use common::{Client, Server, TEXT_PORT};
+
+#[test]
+fn exec_runs_the_queued_commands_at_once() {
+    let _server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+    let mut other = Client::connect(TEXT_PORT);
+
+    assert_eq!(client.request("multi"), "+OK\r\n");
+    assert_eq!(client.request("set a 1"), "+QUEUED\r\n");
+    assert_eq!(client.request("incr a"), "+QUEUED\r\n");
+    assert_eq!(client.request("incr text"), "+QUEUED\r\n");
+    assert_eq!(client.request("get a"), "+QUEUED\r\n");
+    assert_eq!(other.request("set text abc"), "+OK\r\n");
+    // nothing ran before exec
+    assert_eq!(other.request("get a"), "NOTFOUND a\r\n");
+
+    // a failing command does not stop the others
+    assert_eq!(client.request("exec"), "*4\r\n");
+    assert_eq!(client.read_line(), "+OK\r\n");
+    assert_eq!(client.read_line(), ":2\r\n");
+    assert!(client.read_line().starts_with("-ERR WRONGTYPE "));
+    assert_eq!(client.read_line(), "$1 2\r\n");
+    assert_eq!(other.request("get a"), "$1 2\r\n");
+}
+
+#[test]
+fn discard_and_misplaced_transaction_commands() {
+    let _server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+
+    assert_eq!(client.request("multi"), "+OK\r\n");
+    assert_eq!(client.request("multi"), "-ERR BADREQUEST multi calls can not be nested\r\n");
+    assert_eq!(client.request("set b 1"), "+QUEUED\r\n");
+    assert_eq!(client.request("discard"), "+OK\r\n");
+    assert_eq!(client.request("get b"), "NOTFOUND b\r\n");
+    assert_eq!(client.request("exec"), "-ERR BADREQUEST exec without multi\r\n");
+    assert_eq!(client.request("discard"), "-ERR BADREQUEST discard without multi\r\n");
+}
+
+#[test]
+fn command_that_cannot_be_queued_aborts_the_transaction() {
+    let _server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+
+    assert_eq!(client.request("multi"), "+OK\r\n");
+    assert_eq!(client.request("set c 1"), "+QUEUED\r\n");
+    assert!(client.request("set onlykey").starts_with("-ERR BADREQUEST "));
+    assert_eq!(client.request("exec"), "-ERR EXECABORT transaction discarded because of previous errors\r\n");
+    assert_eq!(client.request("get c"), "NOTFOUND c\r\n");
+}
+
+#[test]
+fn cas_sets_the_value_only_if_it_is_the_expected_one() {
+    let _server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+
+    assert_eq!(client.request("set v old"), "+OK\r\n");
+    assert_eq!(client.request("cas v old new"), "+OK\r\n");
+    // a mismatch answers with the current value
+    assert_eq!(client.request("cas v old newer"), "$3 new\r\n");
+    assert_eq!(client.request("get v"), "$3 new\r\n");
+    assert_eq!(client.request("cas missing a b"), "NOTFOUND missing\r\n");
+
+    assert_eq!(client.request("set e x ex 100"), "+OK\r\n");
+    assert_eq!(client.request("cas e x \"y z\""), "+OK\r\n");
+    assert_eq!(client.request("get e"), "$3 y z\r\n");
+    assert_eq!(client.request("ttl e"), ":100\r\n");
+}
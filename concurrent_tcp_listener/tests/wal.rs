@@ -0,0 +1,57 @@
+mod common;
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use common::{Client, Server, TEXT_PORT};
+
+#[test]
+fn writes_are_replayed_from_the_log_after_a_crash() {
+    let mut server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+    for line in ["set a 1", "set b x ex 100", "set c gone", "mset d 4 e 5"] {
+        assert_eq!(client.request(line), "+OK\r\n", "{}", line);
+    }
+    assert_eq!(client.request("incr a"), ":2\r\n");
+    assert_eq!(client.request("append b y"), ":2\r\n");
+    assert_eq!(client.request("del c"), ":1\r\n");
+    drop(client);
+
+    // no snapshot was written, everything comes from the log
+    server.kill();
+    assert!(!server.dir().join("kv_snapshot.dat").exists());
+    server.restart_with(&[]);
+
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("get a"), "$1 2\r\n");
+    assert_eq!(client.request("get b"), "$2 xy\r\n");
+    assert_eq!(client.request("ttl b"), ":100\r\n");
+    assert_eq!(client.request("exists c d e"), ":2\r\n");
+}
+
+#[test]
+fn corrupted_tail_of_the_log_is_truncated() {
+    let mut server = Server::start();
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("set before crash"), "+OK\r\n");
+    drop(client);
+    server.kill();
+
+    // a frame announcing 32 bytes of which only a few were written
+    let wal = server.dir().join("kv_wal.log");
+    let intact = std::fs::metadata(&wal).unwrap().len();
+    OpenOptions::new().append(true).open(&wal).unwrap().write_all(&[32, 0, 0, 0, 1, 2, 3, 4, 1, 2]).unwrap();
+
+    server.restart_with(&[]);
+    assert!(server.log().contains("corrupted tail"), "{}", server.log());
+    assert_eq!(std::fs::metadata(&wal).unwrap().len(), intact);
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("get before"), "$5 crash\r\n");
+
+    // writes after the truncation are not hidden behind the damaged frame
+    assert_eq!(client.request("set after repair"), "+OK\r\n");
+    drop(client);
+    server.kill();
+    server.restart_with(&[]);
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(client.request("exists before after"), ":2\r\n");
+}
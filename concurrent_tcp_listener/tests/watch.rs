@@ -0,0 +1,54 @@
+mod common;
+
+use common::{Client, Server, TEXT_PORT};
+
+#[test]
+fn changes_of_watched_keys_and_prefixes_are_pushed() {
+    let _server = Server::start();
+    let mut watcher = Client::connect(TEXT_PORT);
+    let mut client = Client::connect(TEXT_PORT);
+
+    assert_eq!(watcher.request("watch user:1"), ":1\r\n");
+    assert_eq!(watcher.request("watch session:*"), ":2\r\n");
+    for line in ["set user:1 ann", "set session:9 x", "set other y", "del user:1", "expire session:9 100",
+                 "persist session:9", "incr user:1"] {
+        client.request(line);
+    }
+    for event in ["set user:1", "set session:9", "del user:1", "expire session:9", "persist session:9", "set user:1"] {
+        assert_eq!(watcher.read_line(), format!(">event {}\r\n", event));
+    }
+
+    assert_eq!(watcher.request("unwatch session:*"), ":1\r\n");
+    assert_eq!(client.request("set session:1 y"), "+OK\r\n");
+    assert_eq!(client.request("del user:1"), ":1\r\n");
+    assert_eq!(watcher.read_line(), ">event del user:1\r\n");
+}
+
+#[test]
+fn watcher_that_does_not_keep_up_is_told_how_many_pushes_it_missed() {
+    let _server = Server::start();
+    let mut watcher = Client::connect(TEXT_PORT);
+    let mut client = Client::connect(TEXT_PORT);
+    assert_eq!(watcher.request("watch counter"), ":1\r\n");
+    assert_eq!(watcher.request("subscribe bulk"), ":2\r\n");
+
+    // large messages the watcher does not read fill the socket buffers and then its outbox
+    let payload = "x".repeat(64 * 1024);
+    let mut delivered = 0;
+    while client.request(&format!("publish bulk {}", payload)) == ":1\r\n" {
+        delivered += 1;
+        assert!(delivered < 10_000, "the outbox never filled up");
+    }
+    for n in 1..=3 {
+        assert_eq!(client.request("incr counter"), format!(":{}\r\n", n));
+    }
+
+    let message = format!(">message bulk ${} {}\r\n", payload.len(), payload);
+    for _ in 0..delivered {
+        assert_eq!(watcher.read_line(), message);
+    }
+    // the next push is preceded by the count of the dropped message and events
+    assert_eq!(client.request("incr counter"), ":4\r\n");
+    assert_eq!(watcher.read_line(), ">lagged 4 events dropped\r\n");
+    assert_eq!(watcher.read_line(), ">event set counter\r\n");
+}
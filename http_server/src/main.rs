@@ -1,18 +1,34 @@
 use actix_web::{web, App, HttpRequest, HttpServer, Responder};
+use clap::Parser;
+use logging::LogArgs;
+use tracing::{debug, info};
+use tracing_actix_web::TracingLogger;
 
 async fn greet(req: HttpRequest) -> impl Responder {
     let name = req.match_info().get("name").unwrap_or("World");
+    debug!("Greeting {}", name);
     format!("Hello {}!", &name)
 }
 
+/// Greets on http://127.0.0.1:8000/ and /{name}
+#[derive(Parser)]
+struct Cli {
+    #[command(flatten)]
+    log: LogArgs,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
+    Cli::parse().log.init();
+    info!("Listening on 127.0.0.1:8000");
     HttpServer::new(|| {
         App::new()
+            // a span per request with its id, the client address, method and path
+            .wrap(TracingLogger::default())
             .route("/", web::get().to(greet))
             .route("/{name}", web::get().to(greet))
     })
         .bind("127.0.0.1:8000")?
         .run()
         .await
-}
\ No newline at end of file
+}
@@ -0,0 +1,42 @@
+//! The `--log-level` and `--log-format` flags of the small servers in this repository,
+//! added to a clap parser with `#[command(flatten)]`, and the `tracing` output they set up.
+
+use std::io::{self, IsTerminal};
+use clap::{Args, ValueEnum};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Args)]
+pub struct LogArgs {
+    /// Which messages are logged, in the syntax of RUST_LOG, e.g. `debug` or `info,my_crate=trace`
+    #[arg(long, value_name = "FILTER", env = "RUST_LOG", default_value = "info", value_parser = parse_filter)]
+    pub log_level: String,
+
+    /// How log messages are written
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = Format::Human)]
+    pub log_format: Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    /// one line per event, prefixed with its spans
+    Human,
+    /// one object per event, with the fields of its spans
+    Json,
+}
+
+/// accepts the directives only if `EnvFilter` does
+fn parse_filter(directives: &str) -> Result<String, String> {
+    EnvFilter::try_new(directives).map(|_| directives.to_string()).map_err(|e| format!("no log filter: {}", e))
+}
+
+impl LogArgs {
+    /// log to stdout, without colour codes in files and pipes
+    pub fn init(&self) {
+        let filter = EnvFilter::new(&self.log_level);
+        let builder = tracing_subscriber::fmt().with_env_filter(filter).with_ansi(io::stdout().is_terminal());
+        match self.log_format {
+            Format::Human => builder.init(),
+            Format::Json => builder.json().with_current_span(true).with_span_list(true).init(),
+        }
+    }
+}
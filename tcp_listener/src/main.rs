@@ -1,36 +1,108 @@
-use std::io::{BufRead, BufReader, LineWriter, Write};
+mod tls;
+
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use clap::{CommandFactory, Parser};
+use clap::error::ErrorKind;
+use logging::LogArgs;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use tracing::{debug, info, info_span, warn};
 
-fn handle_client(stream: TcpStream) {
-    println!("Starting handling a client");
+fn handle_client<S: Read + Write>(stream: S) -> io::Result<()> {
+    info!("Starting handling a client");
     let line = &mut String::new();
-    let mut reader = BufReader::new(&stream);
-    let mut writer = LineWriter::new(&stream);
-    loop {
+    let mut reader = BufReader::new(stream);
+    for id in 1.. {
         // stream.read_to_string(line).unwrap();
-        let nr_bytes = reader.read_line(line).unwrap();
+        let nr_bytes = reader.read_line(line)?;
+        let _request = info_span!("request", id).entered();
         if nr_bytes == 0 {
-            println!("0 bytes read");
-            writer.write_fmt(format_args!("bye!\r\n")).unwrap();
+            debug!("0 bytes read");
+            // replies go through the reader's stream, TLS needs a single owner of the connection
+            let writer = reader.get_mut();
+            writer.write_fmt(format_args!("bye!\r\n"))?;
+            writer.flush()?;
             break;
         } else {
-            println!("read {} bytes -> {}", nr_bytes, line);
+            debug!("read {} bytes -> {}", nr_bytes, line.trim_end());
             reader.consume(nr_bytes);
             // stream.write_fmt(format_args!("consumed {} bytes\r\n", nr_bytes)).unwrap();
-            writer.write_fmt(format_args!("consumed {} bytes\r\n", nr_bytes)).unwrap();
+            let writer = reader.get_mut();
+            writer.write_fmt(format_args!("consumed {} bytes\r\n", nr_bytes))?;
+            writer.flush()?;
         }
     }
-    println!("end of stream");
+    info!("end of stream");
+    Ok(())
+}
+
+/// plaintext, or TLS if the connection is set up with a config
+fn serve(stream: TcpStream, tls: Option<&Arc<ServerConfig>>) -> io::Result<()> {
+    match tls {
+        None => handle_client(stream),
+        Some(config) => {
+            let connection = ServerConnection::new(config.clone()).map_err(io::Error::other)?;
+            handle_client(StreamOwned::new(connection, stream))
+        },
+    }
+}
+
+/// Answers every line with the number of bytes it had
+#[derive(Parser)]
+struct Cli {
+    /// Port to listen on at 127.0.0.1
+    #[arg(long, default_value_t = 8000)]
+    port: u16,
+
+    /// PEM file with the server certificate chain, serves TLS together with --tls-key
+    #[arg(long, value_name = "PEM", requires = "tls_key")]
+    tls_cert: Option<String>,
+
+    /// PEM file with the private key of the certificate
+    #[arg(long, value_name = "PEM", requires = "tls_cert")]
+    tls_key: Option<String>,
+
+    /// PEM file of the authority clients need a certificate from
+    #[arg(long, value_name = "PEM", requires = "tls_cert")]
+    tls_client_ca: Option<String>,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+/// TLS if a certificate and key were given
+fn tls_config(cli: &Cli) -> Option<Arc<ServerConfig>> {
+    let (cert, key) = (cli.tls_cert.as_ref()?, cli.tls_key.as_ref()?);
+    let config = tls::server_config(cert, key, cli.tls_client_ca.as_deref()).unwrap_or_else(|e| {
+        Cli::command().error(ErrorKind::ValueValidation, format!("Unable to set up TLS: {}", e)).exit()
+    });
+    Some(config)
 }
 
 fn main() {
-    let port:u16 = 8000;
-    println!("Listening for connections on port {}", port);
+    let cli = Cli::parse();
+    cli.log.init();
+    let port = cli.port;
+    let tls = tls_config(&cli);
+    info!("Listening for {} connections on port {}", if tls.is_some() { "TLS" } else { "plaintext" }, port);
     let address = SocketAddr::from(([127,0,0,1], port));
     let listener = TcpListener::bind(address)
         .expect("Unable to bind TCP socket");
 
     for stream in listener.incoming() {
-        handle_client(stream.unwrap());
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("accepting a client failed: {}", e);
+                continue;
+            },
+        };
+        let peer = stream.peer_addr().map_or_else(|_| "unknown".to_string(), |peer| peer.to_string());
+        let _connection = info_span!("connection", %peer).entered();
+        // a client failing the handshake must not take the server down
+        if let Err(e) = serve(stream, tls.as_ref()) {
+            warn!("client failed: {}", e);
+        }
     }
 }
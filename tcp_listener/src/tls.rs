@@ -0,0 +1,36 @@
+use std::io;
+use std::sync::Arc;
+use rustls::RootCertStore;
+use rustls::ServerConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::pki_types::pem::PemObject;
+use rustls::server::WebPkiClientVerifier;
+
+/// Build the TLS settings from PEM files. With `client_ca` every client has to
+/// present a certificate signed by one of the authorities in that file (mutual TLS).
+pub fn server_config(cert: &str, key: &str, client_ca: Option<&str>) -> io::Result<Arc<ServerConfig>> {
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(format!("certificate {}: {}", cert, e)))?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| invalid(format!("private key {}: {}", key, e)))?;
+    let builder = ServerConfig::builder();
+    let builder = match client_ca {
+        None => builder.with_no_client_auth(),
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for ca in CertificateDer::pem_file_iter(path).map_err(|e| invalid(format!("client CA {}: {}", path, e)))? {
+                let ca = ca.map_err(|e| invalid(format!("client CA {}: {}", path, e)))?;
+                roots.add(ca).map_err(|e| invalid(format!("client CA {}: {}", path, e)))?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots)).build()
+                .map_err(|e| invalid(format!("client CA {}: {}", path, e)))?;
+            builder.with_client_cert_verifier(verifier)
+        },
+    };
+    let config = builder.with_single_cert(chain, key).map_err(|e| invalid(e.to_string()))?;
+    Ok(Arc::new(config))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,118 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::pki_types::pem::PemObject;
+
+const PORT: u16 = 8300;
+
+/// a certificate authority, a server certificate and a client certificate written as PEM files
+struct Certificates {
+    dir: PathBuf,
+}
+
+impl Certificates {
+    fn generate() -> Certificates {
+        let dir = std::env::temp_dir().join(format!("tcp-listener-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+        std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+        for (name, names) in [("server", vec!["localhost".to_string()]), ("client", vec!["client".to_string()])] {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(names).unwrap().signed_by(&key, &ca).unwrap();
+            std::fs::write(dir.join(format!("{}.pem", name)), cert.pem()).unwrap();
+            std::fs::write(dir.join(format!("{}-key.pem", name)), key.serialize_pem()).unwrap();
+        }
+        Certificates { dir }
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_str().unwrap().to_string()
+    }
+
+    /// a client trusting the authority, presenting the client certificate if `with_cert`
+    fn client_config(&self, with_cert: bool) -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file(self.path("ca.pem")).unwrap()).unwrap();
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let config = if with_cert {
+            let chain = vec![CertificateDer::from_pem_file(self.path("client.pem")).unwrap()];
+            let key = PrivateKeyDer::from_pem_file(self.path("client-key.pem")).unwrap();
+            builder.with_client_auth_cert(chain, key).unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        Arc::new(config)
+    }
+}
+
+impl Drop for Certificates {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+struct Server(Child);
+
+impl Server {
+    fn start(args: &[&str]) -> Server {
+        let child = Command::new(env!("CARGO_BIN_EXE_tcp_listener"))
+            .args(["--port", &PORT.to_string()])
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", PORT)).is_err() {
+            assert!(started.elapsed() < Duration::from_secs(10), "server did not start listening");
+            thread::sleep(Duration::from_millis(20));
+        }
+        Server(child)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// send a line over TLS and return the reply
+fn request(config: Arc<ClientConfig>, line: &str) -> std::io::Result<String> {
+    let name = ServerName::try_from("localhost").unwrap();
+    let connection = ClientConnection::new(config, name).unwrap();
+    let socket = TcpStream::connect(("127.0.0.1", PORT))?;
+    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut stream = BufReader::new(StreamOwned::new(connection, socket));
+    stream.get_mut().write_all(line.as_bytes())?;
+    stream.get_mut().flush()?;
+    let mut reply = String::new();
+    stream.read_line(&mut reply)?;
+    Ok(reply)
+}
+
+#[test]
+fn tls_with_and_without_client_certificates() {
+    let certs = Certificates::generate();
+    {
+        let _server = Server::start(&["--tls-cert", &certs.path("server.pem"), "--tls-key", &certs.path("server-key.pem")]);
+        assert_eq!(request(certs.client_config(false), "hello\n").unwrap(), "consumed 6 bytes\r\n");
+    }
+
+    let _server = Server::start(&["--tls-cert", &certs.path("server.pem"), "--tls-key", &certs.path("server-key.pem"),
+                                  "--tls-client-ca", &certs.path("ca.pem")]);
+    assert!(request(certs.client_config(false), "hello\n").is_err(), "accepted a client without certificate");
+    // the rejected client did not take the server down
+    assert_eq!(request(certs.client_config(true), "hello\n").unwrap(), "consumed 6 bytes\r\n");
+}